@@ -4,10 +4,14 @@
 
 // Inputs
 layout (location=0) in vec4 in_color; // Input color variable (location=0)
+layout (location=1) in vec2 in_uv; // Texture coordinates
+
+// Resources
+layout (set=1, binding=0) uniform sampler2D tex; // The texture (a 1x1 white texture for untextured geometry)
 
 // Outputs
 layout (location=0) out vec4 color; // Color output variable (location=0)
 
 void main() {
-  color = in_color; // RGBA Color
+  color = in_color * texture(tex, in_uv); // RGBA Color, tinted by the texture
 }
\ No newline at end of file
@@ -5,9 +5,26 @@
 // Inputs
 layout(location = 0) in vec4 in_position;
 layout(location = 1) in vec4 in_color;
+layout(location = 2) in vec2 in_uv;
+// Per instance inputs (binding 1)
+layout(location = 3) in mat4 in_model; // Takes up locations 3 to 6
+layout(location = 7) in vec4 in_instance_color;
+layout(location = 8) in vec4 in_uv_rect; // min uv in xy, max uv in zw
+
+// Resources
+layout (set=0, binding=0) uniform Camera {
+    mat4 view_projection; // World space to clip space
+} camera;
+
+// Per draw data
+layout (push_constant) uniform PushConstants {
+    mat4 model;
+    vec4 tint;
+} push;
 
 // Outputs
 layout (location=0) out vec4 out_color; // Note variables are defined by their location, not their names
+layout (location=1) out vec2 out_uv;
 
 out gl_PerVertex
 {
@@ -19,7 +36,8 @@ void main() {
     // gl_PointSize is a built-in variable in GLSL that sets the size of the point
     //gl_PointSize = 10.0;
     // gl_Position is a special variable that is used to store the final position of the vertex
-    gl_Position = in_position;
+    gl_Position = camera.view_projection * push.model * in_model * in_position;
 
-    out_color = in_color; //vec4(0.0, 0.6, 1.0, 1.0); // This is the color of the vertice(s)
+    out_color = in_color * in_instance_color * push.tint; //vec4(0.0, 0.6, 1.0, 1.0); // This is the color of the vertice(s)
+    out_uv = mix(in_uv_rect.xy, in_uv_rect.zw, in_uv);
 }
\ No newline at end of file
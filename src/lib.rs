@@ -0,0 +1,3 @@
+// The renderer as a library, so the demo (src/main.rs) and the integration tests (tests/) can share it
+pub mod vulkan;
+pub mod renderer;
\ No newline at end of file
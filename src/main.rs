@@ -1,13 +1,33 @@
-pub mod vulkan;
-
+use std::path::PathBuf;
 use std::time::Instant;
 
-use vulkan::{app::*, vertex::Vertex, vertex_buffer::VertexBuffer, index_buffer::IndexBuffer, renderable::Renderable};
+use vulkan_renderer::{renderer, vulkan};
+
+use renderer::{Renderer, Vertex, InstanceData};
+use renderer::renderer2d::Renderer2D;
+use renderer::atlas::{AtlasBuilder, TextureAtlas};
+use renderer::animation::{Animation, AnimatedSprite, PlaybackMode};
+use renderer::camera::Camera2D;
+use vulkan::app::VulkanApp;
+use vulkan::config::RendererConfig;
 use winit::{event::WindowEvent};
 
 const WINDOW_TITLE: &'static str = "Andrew's Rust-based Vulkan Renderer";
+const CHECKERBOARD_SIZE: u32 = 64;
+const PULSE_FRAMES: u32 = 8; // Frames in the animated sprite's sprite sheet
+const RING_INSTANCES: usize = 64; // How many copies of the unit quad are drawn (in one instanced draw call) around the ring
+const WORLD_HEIGHT: f32 = 2.0; // How much of the world the camera shows vertically, y from -1 (bottom) to 1 (top)
+const GRID_SIZE: usize = 100; // The background is a GRID_SIZE x GRID_SIZE grid of quads drawn by the 2D batch renderer, unless --grid-size says otherwise
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+  // Before the renderer, which asks the validation layer for as much detail as the log level lets through (RUST_LOG=debug for more)
+  simple_logger::SimpleLogger::new().env().init().unwrap();
+
+  if std::env::args().any(|arg| arg == "--list-devices") { // Print the devices (to pick one with VULKAN_RENDERER_DEVICE) and exit
+    VulkanApp::list_devices()?;
+    return Ok(());
+  }
+
   let eventloop = winit::event_loop::EventLoop::new(); // Create a winit event loop
   let window = winit::window::WindowBuilder::new()
     .with_title(WINDOW_TITLE)
@@ -16,16 +36,95 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     .build(&eventloop)
     .expect("Failed to create window!"); // Create a winit window
 
-  let mut app = VulkanApp::init(window)?; // Create a vulkan app instance
+  // Write what the system supports to a JSON file (the next argument, or device_report.json) and exit, for attaching to bug reports
+  let args: Vec<String> = std::env::args().collect();
+  if let Some(position) = args.iter().position(|arg| arg == "--device-report") {
+    let path = args.get(position + 1).map(|path| path.as_str()).unwrap_or("device_report.json");
+    let report = VulkanApp::device_report(Some(&window))?; // With the window, so it includes surface formats and present modes
+    std::fs::write(path, report.to_json()?)?;
+    log::info!("Wrote device report to {}.", path);
+    return Ok(());
+  }
+
+  // How many quads the background grid has on each side (--grid-size 317 for ~100K quads, to check the 2D renderer keeps up)
+  let grid_size = match args.iter().position(|arg| arg == "--grid-size") {
+    Some(position) => args.get(position + 1).and_then(|size| size.parse().ok()).ok_or("--grid-size needs a number")?,
+    None => GRID_SIZE,
+  };
+
+  let size = window.inner_size(); // Before the renderer takes the window, for the camera's aspect ratio
+  let camera = Camera2D::new(WORLD_HEIGHT, size.width as f32 / size.height as f32);
+  let config = RendererConfig {
+    // The source tree's shaders in debug builds, so they can be worked on without rebuilding. Release builds shouldn't depend on the source tree
+    shader_directory: cfg!(debug_assertions).then(|| PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders"))),
+    pipeline_cache_directory: user_cache_directory().map(|directory| directory.join("vulkan_renderer")),
+    ..RendererConfig::default()
+  };
+  let app = VulkanApp::init_with_config(window, config)?; // Create a vulkan app instance (the only place we pick a backend)
+  run(app, eventloop, camera, grid_size)
+}
+
+// Where this user's caches go (e.g. ~/.cache on Linux), None if the environment doesn't say.
+// Not the temp directory, which is shared with other users on most systems
+fn user_cache_directory() -> Option<PathBuf> {
+  let from_env = |name| std::env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+  if cfg!(windows) {
+    from_env("LOCALAPPDATA")
+  } else if cfg!(target_os = "macos") {
+    from_env("HOME").map(|home| home.join("Library/Caches"))
+  } else {
+    from_env("XDG_CACHE_HOME").or_else(|| from_env("HOME").map(|home| home.join(".cache")))
+  }
+}
+
+// The game loop, only talks to the renderer through the Renderer trait
+fn run<R: Renderer + 'static>(mut renderer: R, eventloop: winit::event_loop::EventLoop<()>, mut camera: Camera2D, grid_size: usize) -> Result<(), Box<dyn std::error::Error>> {
   let mut now = Instant::now();
   let mut avg_fps = 0.0;
 
-  simple_logger::SimpleLogger::new().env().init().unwrap();
+  let pipeline = renderer.default_pipeline();
+  // Buffers rewritten every frame get one copy per frame in flight, the GPU may still be reading the other frames' copies
+  let mut quad_vertices = Vec::with_capacity(renderer.frames_in_flight());
+  let mut triangle_vertices = Vec::with_capacity(renderer.frames_in_flight());
+  let mut ring_instances = Vec::with_capacity(renderer.frames_in_flight());
+  for _ in 0..renderer.frames_in_flight() {
+    quad_vertices.push(renderer.create_vertex_buffer(4)?);
+    triangle_vertices.push(renderer.create_vertex_buffer(3)?);
+    ring_instances.push(renderer.create_instance_buffer(RING_INSTANCES)?);
+  }
+  let quad_indices = renderer.create_static_index_buffer(&[0, 1, 2, 2, 3, 0])?; // The quad's indices never change (can also use u16)
+  let unit_quad_vertices = renderer.create_static_vertex_buffer(&[
+    Vertex { pos: [-0.5, 0.5, 0.0, 1.0], color: [1.0, 1.0, 1.0, 1.0], uv: [0.0, 0.0] },
+    Vertex { pos: [0.5, 0.5, 0.0, 1.0], color: [1.0, 1.0, 1.0, 1.0], uv: [1.0, 0.0] },
+    Vertex { pos: [0.5, -0.5, 0.0, 1.0], color: [1.0, 1.0, 1.0, 1.0], uv: [1.0, 1.0] },
+    Vertex { pos: [-0.5, -0.5, 0.0, 1.0], color: [1.0, 1.0, 1.0, 1.0], uv: [0.0, 1.0] },
+  ])?;
+  let mut renderer2d = Renderer2D::new(&mut renderer)?;
 
-  let renderable_1 = Renderable::new(&app.device, &mut app.allocator, 4, 6).expect("Failed to create renderable");
-  app.renderables.push(renderable_1);
-  let renderable_2 = Renderable::new(&app.device, &mut app.allocator, 3, 0).expect("Failed to create renderable");
-  app.renderables.push(renderable_2);
+  // A checkerboard texture, generated so the demo doesn't need any image files
+  let checkerboard_pixels: Vec<u8> = (0..CHECKERBOARD_SIZE * CHECKERBOARD_SIZE)
+    .flat_map(|i| {
+      let (x, y) = (i % CHECKERBOARD_SIZE, i / CHECKERBOARD_SIZE);
+      if (x / 8 + y / 8) % 2 == 0 { [255, 255, 255, 255] } else { [64, 64, 64, 255] }
+    })
+    .collect();
+  let checkerboard = renderer.create_texture_from_rgba(CHECKERBOARD_SIZE, CHECKERBOARD_SIZE, &checkerboard_pixels)?;
+
+  // A sprite sheet of a circle growing over PULSE_FRAMES frames, packed into an atlas and played back as an animation
+  let mut atlas_builder = AtlasBuilder::new();
+  let pulse_names: Vec<String> = (0..PULSE_FRAMES).map(|frame| format!("pulse_{}", frame)).collect();
+  for (frame, name) in pulse_names.iter().enumerate() {
+    let radius = 4.0 + frame as f32 * 1.5;
+    let frame_image = image::RgbaImage::from_fn(32, 32, |x, y| {
+      let (dx, dy) = (x as f32 - 15.5, y as f32 - 15.5);
+      if (dx * dx + dy * dy).sqrt() <= radius { image::Rgba([255, 200, 64, 255]) } else { image::Rgba([0, 0, 0, 0]) }
+    });
+    atlas_builder.add_image(name, frame_image)?;
+  }
+  let pulse_atlas = TextureAtlas::upload(&mut renderer, &atlas_builder.build()?)?;
+  let pulse_frame_names: Vec<&str> = pulse_names.iter().map(|name| name.as_str()).collect();
+  let pulse_animation = Animation::from_atlas(&pulse_atlas, &pulse_frame_names, 0.08, PlaybackMode::PingPong).expect("Every frame was packed");
+  let mut pulse = AnimatedSprite::new(pulse_atlas.texture, pulse_animation);
 
   let mut r_color = 0.0;
   let mut g_color = 0.0;
@@ -41,14 +140,16 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         *controlflow = winit::event_loop::ControlFlow::Exit;
       }
       WindowEvent::Resized(size) => {
-        println!("Window resized to {}px x {}px", size.width, size.height);
+        log::info!("Window resized to {}px x {}px.", size.width, size.height);
+        renderer.window_resized();
+        camera.set_viewport_size(size.width, size.height);
       }
       // Ignore other window events
       _ => {}
     }
     winit::event::Event::MainEventsCleared => {
       // doing the work here (later)
-      app.window.request_redraw();
+      renderer.request_redraw();
     }
     winit::event::Event::RedrawRequested(_) => {
       let delta_time = now.elapsed().as_secs_f32() * 1000.0;
@@ -56,7 +157,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
       let fps = ((1000.0/delta_time) * 10.0).round() / 10.0; // Divide by 10^(num digits after decimal). So 10 for 1 digit, 100 for 2 digits, etc.
       avg_fps = (avg_fps + fps) / 2.0;
       //println!("FPS: {:.0}", fps);
-      app.set_window_title(&format!("{} - FPS: {:.0} ({:.3}ms) | AVG FPS: {:.0}", WINDOW_TITLE, fps.round(), delta_time, avg_fps.round()));
+      let stats = renderer2d.stats(); // Of the last scene
+      renderer.set_window_title(&format!(
+        "{} - FPS: {:.0} ({:.3}ms) | AVG FPS: {:.0} | 2D: {} quads in {} draw calls",
+        WINDOW_TITLE, fps.round(), delta_time, avg_fps.round(), stats.quads, stats.draw_calls,
+      ));
 
       // Render here
       if r_color >= 1.0 {
@@ -77,51 +182,94 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
       x_pos = x_pos + ((pos_target / 2.0) * (delta_time/1000.0));
 
+      pulse.update(delta_time / 1000.0);
+
       let vertices: [Vertex; 4] = [
         Vertex {
-          pos: [-0.5, -0.5, 0.0, 1.0],
+          pos: [-0.5, 0.5, 0.0, 1.0],
           color: [1.0, 0.0, 0.0, 1.0],
+          uv: [0.0, 0.0],
         },
         Vertex {
-          pos: [0.5, -0.5, 0.0, 1.0],
+          pos: [0.5, 0.5, 0.0, 1.0],
           color: [0.0, 1.0, 0.0, 1.0],
+          uv: [0.0, 0.0],
         },
         Vertex {
-          pos: [0.5, 0.5, 0.0, 1.0],
+          pos: [0.5, -0.5, 0.0, 1.0],
           color: [0.0, 0.0, 1.0, 1.0],
+          uv: [0.0, 0.0],
         },
         Vertex {
-          pos: [-0.5, 0.5, 0.0, 1.0],
+          pos: [-0.5, -0.5, 0.0, 1.0],
           color: [1.0, 1.0, 1.0, 1.0],
+          uv: [0.0, 0.0],
         },
       ];
 
-      let indices: [u32; 6] = [0, 1, 2, 2, 3, 0]; // Can also use u16
-
       let vertices_two: [Vertex; 3] = [
           Vertex {
-              pos: [x_pos, 0.5, 0.0, 1.0],
+              pos: [x_pos, -0.5, 0.0, 1.0],
               color: [1.0, 1.0, 1.0, 0.4],
+              uv: [0.0, 0.0],
           },
           Vertex {
-            pos: [0.5 + x_pos, -0.5, 0.0, 1.0],
+            pos: [0.5 + x_pos, 0.5, 0.0, 1.0],
               color: [1.0, 1.0, 1.0, 0.4],
+              uv: [0.0, 0.0],
           },
           Vertex {
-            pos: [-0.5 + x_pos, -0.5, 0.0, 1.0],
+            pos: [-0.5 + x_pos, 0.5, 0.0, 1.0],
               color: [1.0, 1.0, 1.0, 0.4],
+              uv: [0.0, 0.0],
           },
       ];
 
-      app.renderables.get_mut(0).unwrap().update_vertices_buffer(&app.device, &vertices);
-      app.renderables.get_mut(0).unwrap().update_indices_buffer(&app.device, &indices);
+      // Spread the instances around a ring that turns with x_pos
+      let instances: Vec<InstanceData> = (0..RING_INSTANCES).map(|i| {
+        let angle = (i as f32 / RING_INSTANCES as f32) * std::f32::consts::TAU + x_pos;
+        let hue = i as f32 / RING_INSTANCES as f32;
+        InstanceData::new_2d([angle.cos() * 0.8, -angle.sin() * 0.8, 0.0], [0.05, 0.05], [hue, 1.0 - hue, 1.0, 1.0], [0.0, 0.0, 1.0, 1.0])
+      }).collect();
+
+      let result = (|| {
+        renderer.set_view_projection(camera.view_projection()); // Positions below are in world space
+        let frame = renderer.begin_frame()?;
 
-      app.renderables.get_mut(1).unwrap().update_vertices_buffer(&app.device, &vertices_two);
+        // begin_frame waited for the GPU to finish with this slot's buffers, so they're free to overwrite
+        let (quad_vertices, triangle_vertices, ring_instances) =
+          (quad_vertices[frame.slot()], triangle_vertices[frame.slot()], ring_instances[frame.slot()]);
+        renderer.update_vertex_buffer(quad_vertices, &vertices)?;
+        renderer.update_vertex_buffer(triangle_vertices, &vertices_two)?;
+        renderer.update_instance_buffer(ring_instances, &instances)?;
 
-      VulkanApp::fill_commandbuffers(&app.commandbuffers, &app.device, &app.renderpass, &app.swapchain, 
-        &app.pipeline, &app.renderables).expect("Failed to write commands!");
+        renderer2d.begin_scene();
+        let cell_size = 2.0 / grid_size as f32;
+        for y in 0..grid_size {
+          for x in 0..grid_size {
+            let (fx, fy) = (x as f32 / grid_size as f32, y as f32 / grid_size as f32);
+            renderer2d.draw_quad(
+              [-1.0 + (x as f32 + 0.5) * cell_size, 1.0 - (y as f32 + 0.5) * cell_size, 0.0], // Row 0 at the top
+              [cell_size * 0.8, cell_size * 0.8],
+              [fx * 0.3, fy * 0.3, 0.2, 1.0],
+            );
+          }
+        }
+        renderer2d.draw_textured_quad([0.75, 0.75, 0.0], [0.4, 0.4], checkerboard, [1.0, 1.0, 1.0, 1.0]);
+        pulse.draw(&mut renderer2d, [-0.75, 0.75, 0.0], [0.3, 0.3], [1.0, 1.0, 1.0, 1.0]);
+        renderer2d.end_scene(&mut renderer, &frame)?;
 
-      app.draw_frame();
+        renderer.draw(&frame, pipeline, quad_vertices, Some(quad_indices), None)?;
+        renderer.draw(&frame, pipeline, triangle_vertices, None, None)?;
+        renderer.draw_instanced(&frame, pipeline, unit_quad_vertices, Some(quad_indices), ring_instances, None)?;
+        renderer.end_frame(frame)
+      })();
+
+      // Out of date swapchains are handled by the renderer, anything else reaching here is fatal
+      if let Err(error) = result {
+        log::error!("Rendering failed: {}", error);
+        *controlflow = winit::event_loop::ControlFlow::Exit;
+      }
     }
     // Ignore other events
     _ => {}
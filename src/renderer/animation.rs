@@ -0,0 +1,327 @@
+// Sprite animation: an animation is a list of frames (regions of a sprite sheet / atlas, each shown for its own duration),
+// an AnimatedSprite plays one back by delta time and hands the current frame's UV rect to the 2D batch renderer.
+
+use super::atlas::*;
+use super::renderer2d::*;
+use super::*;
+
+// What happens when the last frame is reached
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlaybackMode {
+  Loop, // Start over from the first frame
+  PingPong, // Play backwards to the first frame, then forwards again (the first and last frames aren't repeated)
+  Once, // Stay on the last frame
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AnimationFrame {
+  pub uv: UvRect,
+  pub duration: f32, // Seconds
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Animation {
+  frames: Vec<AnimationFrame>,
+  mode: PlaybackMode,
+}
+
+impl Animation {
+  // Returns None if there are no frames
+  pub fn new(frames: Vec<AnimationFrame>, mode: PlaybackMode) -> Option<Animation> {
+    if frames.is_empty() {
+      return None;
+    }
+    Some(Animation { frames, mode })
+  }
+
+  // An animation through the named images of an atlas, each shown for frame_duration seconds.
+  // Returns None if any of the names aren't in the atlas (or there are none)
+  pub fn from_atlas(atlas: &TextureAtlas, names: &[&str], frame_duration: f32, mode: PlaybackMode) -> Option<Animation> {
+    let frames: Vec<(&str, f32)> = names.iter().map(|&name| (name, frame_duration)).collect();
+    Animation::from_atlas_with_durations(atlas, &frames, mode)
+  }
+
+  // Like from_atlas, but with a duration (in seconds) per frame
+  pub fn from_atlas_with_durations(atlas: &TextureAtlas, frames: &[(&str, f32)], mode: PlaybackMode) -> Option<Animation> {
+    let frames = frames.iter()
+      .map(|&(name, duration)| atlas.uv(name).map(|uv| AnimationFrame { uv, duration }))
+      .collect::<Option<Vec<AnimationFrame>>>()?;
+    Animation::new(frames, mode)
+  }
+
+  pub fn frames(&self) -> &[AnimationFrame] {
+    &self.frames
+  }
+
+  pub fn mode(&self) -> PlaybackMode {
+    self.mode
+  }
+
+  // How long it takes to play every frame once (seconds)
+  pub fn duration(&self) -> f32 {
+    self.frames.iter().map(|frame| frame.duration).sum()
+  }
+
+  // How long until the animation repeats itself (seconds). For ping-pong that's there and back again
+  fn cycle_duration(&self) -> f32 {
+    match self.mode {
+      PlaybackMode::PingPong if self.frames.len() > 2 => {
+        self.duration() + self.frames[1..self.frames.len() - 1].iter().map(|frame| frame.duration).sum::<f32>()
+      }
+      _ => self.duration(),
+    }
+  }
+
+  // Whether a Once animation has reached its end after time seconds (Loop and PingPong never finish)
+  pub fn is_finished(&self, time: f32) -> bool {
+    self.mode == PlaybackMode::Once && time >= self.duration()
+  }
+
+  // The index of the frame shown time seconds after the animation started. Repeating animations wrap negative times around
+  // to the end (so they play backwards), Once animations stay on the first frame before they start
+  pub fn frame_at(&self, time: f32) -> usize {
+    let cycle = self.cycle_duration();
+    if cycle <= 0.0 {
+      return 0;
+    }
+    let last = self.frames.len() - 1;
+    let time = match self.mode {
+      PlaybackMode::Once if time <= 0.0 => return 0,
+      PlaybackMode::Once if time >= cycle => return last,
+      PlaybackMode::Once => time,
+      PlaybackMode::Loop | PlaybackMode::PingPong => time.rem_euclid(cycle),
+    };
+
+    // Walk forwards through the frames
+    let mut elapsed = 0.0;
+    for (index, frame) in self.frames.iter().enumerate() {
+      elapsed += frame.duration;
+      if time < elapsed {
+        return index;
+      }
+    }
+    if self.mode != PlaybackMode::PingPong {
+      return last; // Rounding put us just past the end
+    }
+    // Only ping-pong gets here, walk backwards (skipping the last and first frame, they were just / are about to be shown)
+    for index in (1..last).rev() {
+      elapsed += self.frames[index].duration;
+      if time < elapsed {
+        return index;
+      }
+    }
+    0
+  }
+}
+
+// A sprite playing an animation from a sprite sheet
+pub struct AnimatedSprite {
+  pub texture: TextureHandle, // The sprite sheet the animation's UV rects point into
+  pub speed: f32, // Playback speed, 1 is normal speed. Negative speeds play the animation backwards
+  animation: Animation,
+  time: f32, // Seconds since the animation started (at normal speed)
+  is_playing: bool,
+}
+
+impl AnimatedSprite {
+  pub fn new(texture: TextureHandle, animation: Animation) -> AnimatedSprite {
+    AnimatedSprite {
+      texture,
+      speed: 1.0,
+      animation,
+      time: 0.0,
+      is_playing: true,
+    }
+  }
+
+  // Advance the animation, delta_time is in seconds
+  pub fn update(&mut self, delta_time: f32) {
+    if !self.is_playing {
+      return;
+    }
+    self.time += delta_time * self.speed;
+    let cycle = self.animation.cycle_duration();
+    if self.animation.mode == PlaybackMode::Once {
+      // Stop at either end, so reversing a finished animation starts moving back right away
+      self.time = self.time.clamp(0.0, cycle.max(0.0));
+    } else if cycle > 0.0 {
+      // Keep the time small (and positive, when playing backwards) for repeating animations, so it doesn't lose precision
+      // after running for a long time
+      self.time = self.time.rem_euclid(cycle);
+    }
+  }
+
+  // Switch to another animation, starting at its first frame
+  pub fn set_animation(&mut self, animation: Animation) {
+    self.animation = animation;
+    self.time = 0.0;
+  }
+
+  pub fn animation(&self) -> &Animation {
+    &self.animation
+  }
+
+  pub fn play(&mut self) {
+    self.is_playing = true;
+  }
+
+  pub fn pause(&mut self) {
+    self.is_playing = false;
+  }
+
+  // Go back to the first frame (keeps playing or paused)
+  pub fn restart(&mut self) {
+    self.time = 0.0;
+  }
+
+  pub fn is_finished(&self) -> bool {
+    self.animation.is_finished(self.time)
+  }
+
+  pub fn current_frame(&self) -> usize {
+    self.animation.frame_at(self.time)
+  }
+
+  // The UV rect of the current frame
+  pub fn uv(&self) -> UvRect {
+    self.animation.frames[self.current_frame()].uv
+  }
+
+  // Queue the current frame in the 2D batch
+  pub fn draw(&self, renderer2d: &mut Renderer2D, position: [f32; 3], size: [f32; 2], tint: [f32; 4]) {
+    renderer2d.draw_sprite(position, size, self.texture, self.uv(), tint);
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn new_animation(durations: &[f32], mode: PlaybackMode) -> Animation {
+    let frames = durations.iter().map(|&duration| AnimationFrame { uv: UvRect::FULL, duration }).collect();
+    Animation::new(frames, mode).unwrap()
+  }
+
+  fn frames_at(animation: &Animation, times: &[f32]) -> Vec<usize> {
+    times.iter().map(|&time| animation.frame_at(time)).collect()
+  }
+
+  #[test]
+  fn empty_animation() {
+    assert_eq!(Animation::new(vec![], PlaybackMode::Loop), None);
+  }
+
+  #[test]
+  fn loop_mode() {
+    let animation = new_animation(&[1.0, 2.0, 1.0], PlaybackMode::Loop);
+    assert_eq!(animation.duration(), 4.0);
+    assert_eq!(animation.cycle_duration(), 4.0);
+    assert_eq!(frames_at(&animation, &[0.0, 0.5, 1.0, 2.9, 3.0, 3.5, 4.0, 5.5, 41.0]), [0, 0, 1, 1, 2, 2, 0, 1, 1]);
+    assert!(!animation.is_finished(100.0));
+  }
+
+  #[test]
+  fn ping_pong_single_frame() {
+    let animation = new_animation(&[1.0], PlaybackMode::PingPong);
+    assert_eq!(animation.cycle_duration(), 1.0);
+    assert_eq!(frames_at(&animation, &[0.0, 0.5, 1.0, 7.5]), [0, 0, 0, 0]);
+  }
+
+  #[test]
+  fn ping_pong_two_frames() {
+    // Nothing in between to play backwards, so it's the same as looping
+    let animation = new_animation(&[1.0, 2.0], PlaybackMode::PingPong);
+    assert_eq!(animation.cycle_duration(), 3.0);
+    assert_eq!(frames_at(&animation, &[0.0, 1.0, 2.5, 3.0, 4.5]), [0, 1, 1, 0, 1]);
+  }
+
+  #[test]
+  fn ping_pong_many_frames() {
+    // 0 1 2 3 2 1 | 0 1 ..., the first and last frames aren't repeated
+    let animation = new_animation(&[1.0, 1.0, 1.0, 1.0], PlaybackMode::PingPong);
+    assert_eq!(animation.cycle_duration(), 6.0);
+    let times: Vec<f32> = (0..8).map(|second| second as f32 + 0.5).collect();
+    assert_eq!(frames_at(&animation, &times), [0, 1, 2, 3, 2, 1, 0, 1]);
+    assert!(!animation.is_finished(100.0));
+  }
+
+  #[test]
+  fn once_mode() {
+    let animation = new_animation(&[1.0, 1.0, 1.0], PlaybackMode::Once);
+    assert_eq!(frames_at(&animation, &[-1.0, 0.0, 1.5, 2.5, 3.0, 100.0]), [0, 0, 1, 2, 2, 2]);
+    assert!(!animation.is_finished(2.9));
+    assert!(animation.is_finished(3.0));
+  }
+
+  #[test]
+  fn zero_duration_frames() {
+    // Frames with no duration are skipped
+    let animation = new_animation(&[1.0, 0.0, 1.0], PlaybackMode::Loop);
+    assert_eq!(frames_at(&animation, &[0.5, 1.0, 1.5, 2.0]), [0, 2, 2, 0]);
+
+    // With no duration at all there's nothing to play, the first frame is shown
+    for mode in [PlaybackMode::Loop, PlaybackMode::PingPong, PlaybackMode::Once] {
+      let animation = new_animation(&[0.0, 0.0, 0.0], mode);
+      assert_eq!(frames_at(&animation, &[-1.0, 0.0, 1.0]), [0, 0, 0]);
+    }
+    assert!(new_animation(&[0.0, 0.0], PlaybackMode::Once).is_finished(0.0));
+  }
+
+  #[test]
+  fn negative_time_wraps() {
+    let animation = new_animation(&[1.0, 1.0, 1.0], PlaybackMode::Loop);
+    assert_eq!(frames_at(&animation, &[-0.5, -1.5, -2.5, -3.5]), [2, 1, 0, 2]);
+    let animation = new_animation(&[1.0, 1.0, 1.0], PlaybackMode::PingPong);
+    assert_eq!(frames_at(&animation, &[-0.5, -1.5, -2.5, -3.5]), [1, 2, 1, 0]);
+  }
+
+  #[test]
+  fn sprite_plays_backwards() {
+    let mut sprite = AnimatedSprite::new(TextureHandle(0), new_animation(&[1.0, 1.0, 1.0], PlaybackMode::Loop));
+    sprite.speed = -1.0;
+    let mut frames = vec![];
+    for _ in 0..4 {
+      sprite.update(1.0);
+      frames.push(sprite.current_frame());
+    }
+    assert_eq!(frames, [2, 1, 0, 2]);
+    assert!(sprite.time >= 0.0 && sprite.time < 3.0);
+  }
+
+  #[test]
+  fn sprite_once() {
+    let mut sprite = AnimatedSprite::new(TextureHandle(0), new_animation(&[1.0, 1.0], PlaybackMode::Once));
+    sprite.update(0.5);
+    assert!(!sprite.is_finished());
+    sprite.update(10.0);
+    assert!(sprite.is_finished());
+    assert_eq!(sprite.current_frame(), 1);
+
+    // Reversing plays back from the end straight away, and stops at the first frame
+    sprite.speed = -1.0;
+    sprite.update(0.5);
+    assert!(!sprite.is_finished());
+    assert_eq!(sprite.current_frame(), 1);
+    sprite.update(1.0);
+    assert_eq!(sprite.current_frame(), 0);
+    sprite.update(10.0);
+    assert_eq!(sprite.current_frame(), 0);
+    sprite.speed = 1.0;
+    sprite.update(1.5);
+    assert_eq!(sprite.current_frame(), 1);
+  }
+
+  #[test]
+  fn paused_sprite_doesnt_advance() {
+    let mut sprite = AnimatedSprite::new(TextureHandle(0), new_animation(&[1.0, 1.0], PlaybackMode::Loop));
+    sprite.pause();
+    sprite.update(1.5);
+    assert_eq!(sprite.current_frame(), 0);
+    sprite.play();
+    sprite.update(1.5);
+    assert_eq!(sprite.current_frame(), 1);
+    sprite.restart();
+    assert_eq!(sprite.current_frame(), 0);
+  }
+}
\ No newline at end of file
@@ -0,0 +1,411 @@
+// Texture atlases (sprite sheets): many small images packed into one big texture, so sprites using different images can still be
+// drawn in the same batch. Packing happens on the CPU, the result is uploaded as a single texture through the Renderer trait.
+// A packed atlas can be saved as a PNG plus a JSON metadata file and loaded again without repacking.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use image::RgbaImage;
+use serde::{Deserialize, Serialize};
+
+use super::*;
+
+// The biggest atlas we build (in pixels, per side). Every GPU we care about supports textures at least this big
+pub const MAX_ATLAS_SIZE: u32 = 4096;
+
+#[derive(Debug)]
+pub enum AtlasError {
+  Image(image::ImageError), // Reading, decoding or encoding an image failed
+  Io(std::io::Error), // Reading or writing the metadata file failed
+  Json(serde_json::Error), // The metadata file isn't valid
+  DuplicateName(String), // Two images were added with the same name
+  EmptyImage(String), // An image with no pixels (a width or height of 0) was added
+  TooLarge { name: String, width: u32, height: u32 }, // A single image (with padding and extrusion) doesn't fit in the biggest atlas
+  Full { max_size: u32 }, // The images don't all fit in a max_size x max_size atlas
+}
+
+impl std::fmt::Display for AtlasError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      AtlasError::Image(error) => write!(f, "Atlas image error: {}", error),
+      AtlasError::Io(error) => write!(f, "Atlas metadata IO error: {}", error),
+      AtlasError::Json(error) => write!(f, "Invalid atlas metadata: {}", error),
+      AtlasError::DuplicateName(name) => write!(f, "An image named {} is already in the atlas", name),
+      AtlasError::EmptyImage(name) => write!(f, "Image {} is empty", name),
+      AtlasError::TooLarge { name, width, height } => write!(f, "Image {} ({}x{}) is too large for the atlas", name, width, height),
+      AtlasError::Full { max_size } => write!(f, "The images don't fit in a {}x{} atlas", max_size, max_size),
+    }
+  }
+}
+
+impl std::error::Error for AtlasError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      AtlasError::Image(error) => Some(error),
+      AtlasError::Io(error) => Some(error),
+      AtlasError::Json(error) => Some(error),
+      _ => None,
+    }
+  }
+}
+
+impl From<image::ImageError> for AtlasError {
+  fn from(error: image::ImageError) -> Self {
+    AtlasError::Image(error)
+  }
+}
+
+impl From<std::io::Error> for AtlasError {
+  fn from(error: std::io::Error) -> Self {
+    AtlasError::Io(error)
+  }
+}
+
+impl From<serde_json::Error> for AtlasError {
+  fn from(error: serde_json::Error) -> Self {
+    AtlasError::Json(error)
+  }
+}
+
+// Where a named image ended up in the atlas. x, y, width and height are in pixels and exclude the extruded border
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AtlasRegion {
+  pub x: u32,
+  pub y: u32,
+  pub width: u32,
+  pub height: u32,
+  pub uv: UvRect,
+}
+
+// Everything needed to use an atlas without repacking it, this is what gets written to the JSON file
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AtlasMetadata {
+  pub image: String, // The atlas image's file name, relative to the metadata file (empty until saved)
+  pub width: u32,
+  pub height: u32,
+  pub padding: u32,
+  pub extrusion: u32,
+  pub regions: BTreeMap<String, AtlasRegion>, // Sorted so the file doesn't change between runs
+}
+
+// Collects named images and packs them into an atlas
+pub struct AtlasBuilder {
+  images: Vec<(String, RgbaImage)>,
+  padding: u32,
+  extrusion: u32,
+  max_size: u32,
+}
+
+impl Default for AtlasBuilder {
+  fn default() -> Self {
+    AtlasBuilder::new()
+  }
+}
+
+impl AtlasBuilder {
+  pub fn new() -> AtlasBuilder {
+    AtlasBuilder {
+      images: vec![],
+      padding: 2,
+      extrusion: 1,
+      max_size: MAX_ATLAS_SIZE,
+    }
+  }
+
+  // Transparent pixels left between images (and around the edge of the atlas)
+  pub fn padding(mut self, padding: u32) -> AtlasBuilder {
+    self.padding = padding;
+    self
+  }
+
+  // How many times each image's edge pixels are repeated around it. Linear filtering near a sprite's edge then blends with
+  // copies of the edge instead of with its neighbours
+  pub fn extrusion(mut self, extrusion: u32) -> AtlasBuilder {
+    self.extrusion = extrusion;
+    self
+  }
+
+  // The biggest the atlas is allowed to get (per side, at most MAX_ATLAS_SIZE)
+  pub fn max_size(mut self, max_size: u32) -> AtlasBuilder {
+    self.max_size = max_size.min(MAX_ATLAS_SIZE);
+    self
+  }
+
+  pub fn add_image(&mut self, name: &str, image: RgbaImage) -> Result<(), AtlasError> {
+    if self.images.iter().any(|(existing, _)| existing == name) {
+      return Err(AtlasError::DuplicateName(name.to_string()));
+    }
+    if image.width() == 0 || image.height() == 0 { // There'd be no edge pixels to extrude
+      return Err(AtlasError::EmptyImage(name.to_string()));
+    }
+    self.images.push((name.to_string(), image));
+    Ok(())
+  }
+
+  // Add a PNG or JPEG from disk
+  pub fn add_file(&mut self, name: &str, path: &Path) -> Result<(), AtlasError> {
+    let image = image::open(path)?.to_rgba8();
+    self.add_image(name, image)
+  }
+
+  // Pack every image into the smallest power of two atlas that fits them (up to max_size)
+  pub fn build(&self) -> Result<PackedAtlas, AtlasError> {
+    let border = self.extrusion * 2;
+
+    // Place the tallest images first, shelves waste less space that way. Ties are broken by name so packing is deterministic
+    let mut order: Vec<usize> = (0..self.images.len()).collect();
+    order.sort_by(|&a, &b| {
+      let (name_a, image_a) = &self.images[a];
+      let (name_b, image_b) = &self.images[b];
+      image_b.height().cmp(&image_a.height())
+        .then(image_b.width().cmp(&image_a.width()))
+        .then(name_a.cmp(name_b))
+    });
+    let footprints: Vec<(u32, u32)> = order.iter().map(|&i| (self.images[i].1.width() + border, self.images[i].1.height() + border)).collect();
+
+    for (&i, &(width, height)) in order.iter().zip(&footprints) {
+      if width + self.padding * 2 > self.max_size || height + self.padding * 2 > self.max_size {
+        let (name, image) = &self.images[i];
+        return Err(AtlasError::TooLarge { name: name.clone(), width: image.width(), height: image.height() });
+      }
+    }
+
+    // Start at the smallest square that could hold all the pixels, then keep doubling (alternating width and height) until everything fits
+    let area: u64 = footprints.iter().map(|&(width, height)| (width + self.padding) as u64 * (height + self.padding) as u64).sum();
+    let mut atlas_width = ((area as f64).sqrt().ceil() as u32).max(1).next_power_of_two();
+    let mut atlas_height = atlas_width;
+    let positions = loop {
+      if atlas_width > self.max_size || atlas_height > self.max_size {
+        return Err(AtlasError::Full { max_size: self.max_size });
+      }
+      if let Some(positions) = AtlasBuilder::pack_shelves(&footprints, atlas_width, atlas_height, self.padding) {
+        break positions;
+      }
+      if atlas_width == atlas_height {
+        atlas_width *= 2;
+      } else {
+        atlas_height *= 2;
+      }
+    };
+
+    // Copy the images in, with their edges extruded
+    let mut atlas_image = RgbaImage::new(atlas_width, atlas_height); // Starts fully transparent
+    let mut regions = BTreeMap::new();
+    for (&i, &(footprint_x, footprint_y)) in order.iter().zip(&positions) {
+      let (name, image) = &self.images[i];
+      for dy in 0..image.height() + border {
+        for dx in 0..image.width() + border {
+          // Pixels in the border repeat the closest edge pixel
+          let src_x = dx.saturating_sub(self.extrusion).min(image.width() - 1);
+          let src_y = dy.saturating_sub(self.extrusion).min(image.height() - 1);
+          atlas_image.put_pixel(footprint_x + dx, footprint_y + dy, *image.get_pixel(src_x, src_y));
+        }
+      }
+
+      let (x, y) = (footprint_x + self.extrusion, footprint_y + self.extrusion);
+      let uv = UvRect {
+        min: [x as f32 / atlas_width as f32, y as f32 / atlas_height as f32],
+        max: [(x + image.width()) as f32 / atlas_width as f32, (y + image.height()) as f32 / atlas_height as f32],
+      };
+      regions.insert(name.clone(), AtlasRegion { x, y, width: image.width(), height: image.height(), uv });
+    }
+
+    Ok(PackedAtlas {
+      image: atlas_image,
+      metadata: AtlasMetadata {
+        image: String::new(),
+        width: atlas_width,
+        height: atlas_height,
+        padding: self.padding,
+        extrusion: self.extrusion,
+        regions,
+      },
+    })
+  }
+
+  // Place the footprints left to right in rows (shelves), starting a new row when one is full.
+  // Returns the top left corner of each footprint, or None if they don't fit
+  fn pack_shelves(footprints: &[(u32, u32)], atlas_width: u32, atlas_height: u32, padding: u32) -> Option<Vec<(u32, u32)>> {
+    let mut positions = Vec::with_capacity(footprints.len());
+    let (mut x, mut y, mut shelf_height) = (padding, padding, 0);
+    for &(width, height) in footprints {
+      if x + width + padding > atlas_width {
+        x = padding;
+        y += shelf_height + padding;
+        shelf_height = 0;
+      }
+      if x + width + padding > atlas_width || y + height + padding > atlas_height {
+        return None;
+      }
+      positions.push((x, y));
+      x += width + padding;
+      shelf_height = shelf_height.max(height);
+    }
+    Some(positions)
+  }
+}
+
+// A packed atlas that hasn't been uploaded yet
+pub struct PackedAtlas {
+  pub image: RgbaImage,
+  pub metadata: AtlasMetadata,
+}
+
+impl PackedAtlas {
+  // Write the metadata as JSON to metadata_path, and the image as a PNG next to it (same name, .png extension)
+  pub fn save(&self, metadata_path: &Path) -> Result<(), AtlasError> {
+    let image_path = metadata_path.with_extension("png");
+    self.image.save(&image_path)?;
+
+    let mut metadata = self.metadata.clone();
+    metadata.image = image_path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    std::fs::write(metadata_path, serde_json::to_string_pretty(&metadata)?)?;
+    Ok(())
+  }
+
+  // Load an atlas written by save
+  pub fn load(metadata_path: &Path) -> Result<PackedAtlas, AtlasError> {
+    let metadata: AtlasMetadata = serde_json::from_str(&std::fs::read_to_string(metadata_path)?)?;
+    let image_path = metadata_path.parent().unwrap_or_else(|| Path::new("")).join(&metadata.image);
+    let image = image::open(image_path)?.to_rgba8();
+    Ok(PackedAtlas { image, metadata })
+  }
+}
+
+// An atlas living on the GPU, as one texture
+pub struct TextureAtlas {
+  pub texture: TextureHandle,
+  pub metadata: AtlasMetadata,
+}
+
+impl TextureAtlas {
+  pub fn upload<R: Renderer>(renderer: &mut R, packed: &PackedAtlas) -> Result<TextureAtlas, R::Error> {
+    let texture = renderer.create_texture_from_rgba(packed.image.width(), packed.image.height(), packed.image.as_raw())?;
+    Ok(TextureAtlas { texture, metadata: packed.metadata.clone() })
+  }
+
+  // The UV rect of the image added under name (for Renderer2D::draw_sprite)
+  pub fn uv(&self, name: &str) -> Option<UvRect> {
+    self.metadata.regions.get(name).map(|region| region.uv)
+  }
+
+  pub fn destroy<R: Renderer>(self, renderer: &mut R) -> Result<(), R::Error> {
+    renderer.destroy_texture(self.texture)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn solid(width: u32, height: u32, color: [u8; 4]) -> RgbaImage {
+    RgbaImage::from_pixel(width, height, image::Rgba(color))
+  }
+
+  // The region with its extruded border, which has to be at least the padding away from other images and the atlas edges
+  fn footprint(region: &AtlasRegion, metadata: &AtlasMetadata) -> (u32, u32, u32, u32) {
+    let border = metadata.extrusion;
+    (region.x - border, region.y - border, region.x + region.width + border, region.y + region.height + border)
+  }
+
+  #[test]
+  fn packs_without_overlap() {
+    let mut builder = AtlasBuilder::new().padding(2).extrusion(1);
+    builder.add_image("wide", solid(30, 20, [255, 0, 0, 255])).unwrap();
+    builder.add_image("small", solid(10, 10, [0, 255, 0, 255])).unwrap();
+    builder.add_image("big", solid(50, 40, [0, 0, 255, 255])).unwrap();
+    let atlas = builder.build().unwrap();
+    let metadata = &atlas.metadata;
+
+    // 64x64 holds the pixels, but the shelves don't fit until the width doubles
+    assert_eq!((metadata.width, metadata.height), (128, 64));
+    assert_eq!((atlas.image.width(), atlas.image.height()), (128, 64));
+    assert_eq!(metadata.regions.len(), 3);
+
+    let padding = metadata.padding;
+    let regions: Vec<&AtlasRegion> = metadata.regions.values().collect();
+    for (i, a) in regions.iter().enumerate() {
+      let (x0, y0, x1, y1) = footprint(a, metadata);
+      assert!(x0 >= padding && y0 >= padding && x1 + padding <= metadata.width && y1 + padding <= metadata.height);
+      for b in &regions[i + 1..] {
+        let (bx0, by0, bx1, by1) = footprint(b, metadata);
+        assert!(x1 + padding <= bx0 || bx1 + padding <= x0 || y1 + padding <= by0 || by1 + padding <= y0, "{:?} overlaps {:?}", a, b);
+      }
+    }
+
+    let big = &metadata.regions["big"];
+    assert_eq!((big.width, big.height), (50, 40));
+    assert_eq!(big.uv.min, [big.x as f32 / 128.0, big.y as f32 / 64.0]);
+    assert_eq!(big.uv.max, [(big.x + 50) as f32 / 128.0, (big.y + 40) as f32 / 64.0]);
+    assert_eq!(*atlas.image.get_pixel(big.x, big.y), image::Rgba([0, 0, 255, 255]));
+  }
+
+  #[test]
+  fn extrudes_edges() {
+    // Every pixel a different color, so it's clear which one was repeated
+    let image = RgbaImage::from_fn(2, 2, |x, y| image::Rgba([x as u8 * 100 + 50, y as u8 * 100 + 50, 0, 255]));
+    let mut builder = AtlasBuilder::new().padding(2).extrusion(1);
+    builder.add_image("image", image.clone()).unwrap();
+    let atlas = builder.build().unwrap();
+    let region = &atlas.metadata.regions["image"];
+    let (x, y) = (region.x, region.y);
+    let pixel = |dx: i32, dy: i32| *atlas.image.get_pixel((x as i32 + dx) as u32, (y as i32 + dy) as u32);
+
+    for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+      assert_eq!(pixel(dx, dy), *image.get_pixel(dx as u32, dy as u32));
+    }
+    // The border repeats the closest edge pixel, corners included
+    assert_eq!(pixel(-1, -1), *image.get_pixel(0, 0));
+    assert_eq!(pixel(2, -1), *image.get_pixel(1, 0));
+    assert_eq!(pixel(-1, 1), *image.get_pixel(0, 1));
+    assert_eq!(pixel(2, 2), *image.get_pixel(1, 1));
+    assert_eq!(pixel(1, 2), *image.get_pixel(1, 1));
+    // Past the border is padding, which stays transparent
+    assert_eq!(pixel(-2, 0), image::Rgba([0, 0, 0, 0]));
+    assert_eq!(pixel(0, 3), image::Rgba([0, 0, 0, 0]));
+  }
+
+  #[test]
+  fn rejects_bad_images() {
+    let mut builder = AtlasBuilder::new();
+    builder.add_image("image", solid(4, 4, [255; 4])).unwrap();
+    assert!(matches!(builder.add_image("image", solid(4, 4, [255; 4])), Err(AtlasError::DuplicateName(name)) if name == "image"));
+    assert!(matches!(builder.add_image("empty", solid(0, 4, [255; 4])), Err(AtlasError::EmptyImage(name)) if name == "empty"));
+    assert!(matches!(builder.add_image("empty", solid(4, 0, [255; 4])), Err(AtlasError::EmptyImage(_))));
+  }
+
+  #[test]
+  fn too_large_and_full() {
+    // 63 + 2 (extrusion) + 4 (padding) doesn't fit in 64
+    let mut builder = AtlasBuilder::new().max_size(64);
+    builder.add_image("huge", solid(63, 10, [255; 4])).unwrap();
+    assert!(matches!(builder.build(), Err(AtlasError::TooLarge { name, width: 63, height: 10 }) if name == "huge"));
+
+    // Each fits on its own, but only one 32x32 footprint fits in 64x64 with padding
+    let mut builder = AtlasBuilder::new().max_size(64);
+    builder.add_image("a", solid(30, 30, [255; 4])).unwrap();
+    builder.add_image("b", solid(30, 30, [255; 4])).unwrap();
+    assert!(matches!(builder.build(), Err(AtlasError::Full { max_size: 64 })));
+  }
+
+  #[test]
+  fn save_and_load() {
+    let mut builder = AtlasBuilder::new();
+    builder.add_image("red", solid(8, 4, [255, 0, 0, 255])).unwrap();
+    builder.add_image("green", solid(3, 9, [0, 255, 0, 128])).unwrap();
+    let atlas = builder.build().unwrap();
+
+    let directory = std::env::temp_dir().join(format!("vulkan_renderer_atlas_test_{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let metadata_path = directory.join("atlas.json");
+    atlas.save(&metadata_path).unwrap();
+    let loaded = PackedAtlas::load(&metadata_path);
+    std::fs::remove_dir_all(&directory).unwrap();
+    let loaded = loaded.unwrap();
+
+    let mut expected = atlas.metadata.clone();
+    expected.image = "atlas.png".to_string(); // Filled in by save
+    assert_eq!(loaded.metadata, expected);
+    assert_eq!(loaded.image, atlas.image);
+  }
+}
\ No newline at end of file
@@ -0,0 +1,193 @@
+// Cameras build the view-projection matrix given to Renderer::set_view_projection, so geometry can be placed in world space
+// instead of clip space. Matrices are column major ([column][row]), like GLSL's mat4 and InstanceData::model.
+
+pub type Matrix4 = [[f32; 4]; 4];
+
+pub const IDENTITY_MATRIX: Matrix4 = [
+  [1.0, 0.0, 0.0, 0.0],
+  [0.0, 1.0, 0.0, 0.0],
+  [0.0, 0.0, 1.0, 0.0],
+  [0.0, 0.0, 0.0, 1.0],
+];
+
+// An orthographic projection showing left..right, bottom..top and near..far (along z) of the world.
+// Vulkan's clip space has y pointing down and depth from 0 to 1, which this takes care of: top ends up at the top of the screen
+pub fn orthographic(left: f32, right: f32, bottom: f32, top: f32, near: f32, far: f32) -> Matrix4 {
+  [
+    [2.0 / (right - left), 0.0, 0.0, 0.0],
+    [0.0, 2.0 / (bottom - top), 0.0, 0.0],
+    [0.0, 0.0, 1.0 / (far - near), 0.0],
+    [-(right + left) / (right - left), -(bottom + top) / (bottom - top), -near / (far - near), 1.0],
+  ]
+}
+
+// a * b, so b is applied to a vector first
+pub fn multiply(a: &Matrix4, b: &Matrix4) -> Matrix4 {
+  let mut result = [[0.0; 4]; 4];
+  for (column, result_column) in result.iter_mut().enumerate() {
+    for (row, value) in result_column.iter_mut().enumerate() {
+      *value = (0..4).map(|k| a[k][row] * b[column][k]).sum();
+    }
+  }
+  result
+}
+
+// A 2D camera: an orthographic view of the world (y up) that can be moved, rotated and zoomed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera2D {
+  pub position: [f32; 2], // The world position at the center of the screen
+  pub rotation: f32, // Radians, counter-clockwise
+  pub zoom: f32, // 2 shows half as much of the world
+  pub height: f32, // How much of the world is visible vertically (at zoom 1)
+  pub aspect_ratio: f32, // Width / height of the screen
+}
+
+impl Camera2D {
+  // The visible range of z, from the front to the back
+  pub const NEAR: f32 = -1.0;
+  pub const FAR: f32 = 1.0;
+
+  // A camera showing height world units vertically, centered on the origin
+  pub fn new(height: f32, aspect_ratio: f32) -> Camera2D {
+    Camera2D {
+      position: [0.0, 0.0],
+      rotation: 0.0,
+      zoom: 1.0,
+      height,
+      aspect_ratio,
+    }
+  }
+
+  // Call when the window is resized, so the world isn't stretched
+  pub fn set_viewport_size(&mut self, width: u32, height: u32) {
+    if height > 0 {
+      self.aspect_ratio = width as f32 / height as f32;
+    }
+  }
+
+  pub fn projection(&self) -> Matrix4 {
+    let half_height = self.height / self.zoom / 2.0;
+    let half_width = half_height * self.aspect_ratio;
+    orthographic(-half_width, half_width, -half_height, half_height, Camera2D::NEAR, Camera2D::FAR)
+  }
+
+  // Moves the world so the camera ends up at the origin, unrotated (the inverse of the camera's own transform)
+  pub fn view(&self) -> Matrix4 {
+    let (sin, cos) = (-self.rotation).sin_cos();
+    let [x, y] = self.position;
+    [
+      [cos, sin, 0.0, 0.0],
+      [-sin, cos, 0.0, 0.0],
+      [0.0, 0.0, 1.0, 0.0],
+      [-(cos * x - sin * y), -(sin * x + cos * y), 0.0, 1.0],
+    ]
+  }
+
+  // What to pass to Renderer::set_view_projection
+  pub fn view_projection(&self) -> Matrix4 {
+    multiply(&self.projection(), &self.view())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn transform(matrix: &Matrix4, point: [f32; 3]) -> [f32; 4] {
+    let vector = [point[0], point[1], point[2], 1.0];
+    let mut result = [0.0; 4];
+    for (row, value) in result.iter_mut().enumerate() {
+      *value = (0..4).map(|column| matrix[column][row] * vector[column]).sum();
+    }
+    result
+  }
+
+  fn assert_close(actual: [f32; 4], expected: [f32; 4]) {
+    for (a, e) in actual.iter().zip(expected) {
+      assert!((a - e).abs() < 1e-5, "{:?} != {:?}", actual, expected);
+    }
+  }
+
+  fn translation(x: f32, y: f32) -> Matrix4 {
+    let mut matrix = IDENTITY_MATRIX;
+    matrix[3] = [x, y, 0.0, 1.0];
+    matrix
+  }
+
+  fn scale(factor: f32) -> Matrix4 {
+    let mut matrix = IDENTITY_MATRIX;
+    matrix[0][0] = factor;
+    matrix[1][1] = factor;
+    matrix
+  }
+
+  #[test]
+  fn orthographic_corners() {
+    // Top of the world at the top of the screen, which is -1 in Vulkan
+    let projection = orthographic(-2.0, 6.0, -1.0, 3.0, 0.0, 10.0);
+    assert_close(transform(&projection, [-2.0, 3.0, 0.0]), [-1.0, -1.0, 0.0, 1.0]);
+    assert_close(transform(&projection, [6.0, 3.0, 0.0]), [1.0, -1.0, 0.0, 1.0]);
+    assert_close(transform(&projection, [-2.0, -1.0, 0.0]), [-1.0, 1.0, 0.0, 1.0]);
+    assert_close(transform(&projection, [6.0, -1.0, 0.0]), [1.0, 1.0, 0.0, 1.0]);
+    assert_close(transform(&projection, [2.0, 1.0, 0.0]), [0.0, 0.0, 0.0, 1.0]);
+  }
+
+  #[test]
+  fn orthographic_depth() {
+    let projection = orthographic(-1.0, 1.0, -1.0, 1.0, 2.0, 6.0);
+    assert_eq!(transform(&projection, [0.0, 0.0, 2.0])[2], 0.0);
+    assert_eq!(transform(&projection, [0.0, 0.0, 4.0])[2], 0.5);
+    assert_eq!(transform(&projection, [0.0, 0.0, 6.0])[2], 1.0);
+  }
+
+  #[test]
+  fn multiply_applies_right_first() {
+    let scale_then_translate = multiply(&translation(1.0, 2.0), &scale(2.0));
+    let translate_then_scale = multiply(&scale(2.0), &translation(1.0, 2.0));
+    assert_eq!(scale_then_translate[3], [1.0, 2.0, 0.0, 1.0]);
+    assert_eq!(translate_then_scale[3], [2.0, 4.0, 0.0, 1.0]);
+    assert_close(transform(&scale_then_translate, [1.0, 1.0, 0.0]), [3.0, 4.0, 0.0, 1.0]);
+    assert_close(transform(&translate_then_scale, [1.0, 1.0, 0.0]), [4.0, 6.0, 0.0, 1.0]);
+    assert_eq!(multiply(&IDENTITY_MATRIX, &translate_then_scale), translate_then_scale);
+  }
+
+  #[test]
+  fn camera_corners() {
+    // 4 units high at zoom 1, so 2 at zoom 2, and twice as wide
+    let mut camera = Camera2D::new(4.0, 2.0);
+    camera.zoom = 2.0;
+    let view_projection = camera.view_projection();
+    assert_close(transform(&view_projection, [-2.0, 1.0, 0.0]), [-1.0, -1.0, 0.5, 1.0]);
+    assert_close(transform(&view_projection, [2.0, -1.0, 0.0]), [1.0, 1.0, 0.5, 1.0]);
+    assert_eq!(transform(&view_projection, [0.0, 0.0, Camera2D::NEAR])[2], 0.0);
+    assert_eq!(transform(&view_projection, [0.0, 0.0, Camera2D::FAR])[2], 1.0);
+  }
+
+  #[test]
+  fn camera_centers_its_position() {
+    let mut camera = Camera2D::new(10.0, 1.5);
+    camera.position = [5.0, -3.0];
+    camera.rotation = 0.7;
+    assert_close(transform(&camera.view(), [5.0, -3.0, 0.0]), [0.0, 0.0, 0.0, 1.0]);
+    assert_close(transform(&camera.view_projection(), [5.0, -3.0, 0.0]), [0.0, 0.0, 0.5, 1.0]);
+  }
+
+  #[test]
+  fn camera_rotation() {
+    // Turned a quarter counter-clockwise, what's above the camera is on the right of the screen
+    let mut camera = Camera2D::new(2.0, 1.0);
+    camera.position = [1.0, 1.0];
+    camera.rotation = std::f32::consts::FRAC_PI_2;
+    assert_close(transform(&camera.view_projection(), [1.0, 2.0, 0.0]), [1.0, 0.0, 0.5, 1.0]);
+    assert_close(transform(&camera.view_projection(), [0.0, 1.0, 0.0]), [0.0, -1.0, 0.5, 1.0]);
+  }
+
+  #[test]
+  fn viewport_size() {
+    let mut camera = Camera2D::new(2.0, 1.0);
+    camera.set_viewport_size(1600, 900);
+    assert_eq!(camera.aspect_ratio, 1600.0 / 900.0);
+    camera.set_viewport_size(1600, 0); // Minimized
+    assert_eq!(camera.aspect_ratio, 1600.0 / 900.0);
+  }
+}
\ No newline at end of file
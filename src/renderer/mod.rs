@@ -0,0 +1,200 @@
+// The backend agnostic renderer interface. Game code should only ever talk to the Renderer trait (and the handles it hands out),
+// never to a graphics API directly, so that other backends can be added later without rewriting the game.
+// Vulkan (src/vulkan) is the first, and for now only, implementation.
+// Global renderer functionality lives here, 2D and 3D specific functionality will live in their own submodules.
+
+pub mod renderer2d;
+pub mod atlas;
+pub mod animation;
+pub mod camera;
+
+// Vertices (and instances, and push constants) are plain data, so they're shared between the renderer interface and the backends
+pub use crate::vulkan::vertex::{Vertex, InstanceData};
+pub use crate::vulkan::uniforms::PushConstants;
+pub use crate::vulkan::pipeline::BlendMode;
+
+// Opaque handle to a GPU buffer (vertex, index or instance) owned by the renderer
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BufferHandle(pub(crate) usize);
+
+// Opaque handle to a texture owned by the renderer
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TextureHandle(pub(crate) usize);
+
+// The part of a texture to draw, in UV coordinates ((0, 0) is the top left of the texture, (1, 1) the bottom right)
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct UvRect {
+  pub min: [f32; 2],
+  pub max: [f32; 2],
+}
+
+impl UvRect {
+  // The whole texture
+  pub const FULL: UvRect = UvRect { min: [0.0, 0.0], max: [1.0, 1.0] };
+}
+
+// A rectangle of the framebuffer in pixels, (0, 0) is the top left corner. Draws can be clipped to one (see Renderer::set_scissor)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScissorRect {
+  pub x: i32,
+  pub y: i32,
+  pub width: u32,
+  pub height: u32,
+}
+
+// Opaque handle to a pipeline (shaders + fixed function state) owned by the renderer
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineHandle(pub(crate) usize);
+
+// How the vertices of a draw make up primitives
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Topology {
+  TriangleList, // Every 3 vertices are a triangle
+  TriangleStrip, // Every vertex after the first two makes a triangle with the two before it
+  LineList, // Every 2 vertices are a line
+  LineStrip, // A line from every vertex to the next
+  PointList, // Every vertex is a point. Needs shaders that write gl_PointSize, the default ones don't
+}
+
+// Which side of triangles isn't drawn. Triangles whose vertices go counter-clockwise on screen are front facing
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CullMode {
+  None,
+  Front,
+  Back, // For closed meshes, whose back faces are hidden anyway
+}
+
+// Where a pipeline's shaders come from. Custom shaders get the same inputs as the default ones (Vertex and InstanceData, the camera
+// in set 0, the texture in set 1 and PushConstants), and creating the pipeline fails if they expect anything else
+#[derive(Clone, Debug, PartialEq)]
+pub enum ShaderSource {
+  Default, // shader.vert and shader.frag, what the default pipeline uses
+  Files { vertex: std::path::PathBuf, fragment: std::path::PathBuf }, // SPIR-V (.spv) or GLSL files, reloaded when changed if they're in the shader directory
+  Spirv { vertex: Vec<u32>, fragment: Vec<u32> },
+}
+
+// Everything a pipeline is created from (see Renderer::create_pipeline). The default describes the default pipeline
+#[derive(Clone, Debug, PartialEq)]
+pub struct PipelineDescription {
+  pub shaders: ShaderSource,
+  pub blend_mode: BlendMode,
+  pub depth_test: bool, // Skip fragments behind what's already been drawn. Depth is ignored without a depth buffer
+  pub depth_write: bool, // Let what's drawn hide what's drawn behind it later
+  pub topology: Topology,
+  pub cull_mode: CullMode,
+  pub wireframe: bool, // Only draw the edges of triangles. Not every device supports it
+}
+
+impl Default for PipelineDescription {
+  fn default() -> Self {
+    PipelineDescription {
+      shaders: ShaderSource::Default,
+      blend_mode: BlendMode::Alpha,
+      depth_test: true,
+      depth_write: true,
+      topology: Topology::TriangleList,
+      cull_mode: CullMode::None,
+      wireframe: false,
+    }
+  }
+}
+
+// Handle to the frame currently being recorded. Returned by begin_frame and given back to end_frame, which consumes it
+#[derive(Debug, PartialEq, Eq)]
+pub struct FrameHandle {
+  pub(crate) index: usize,
+  pub(crate) slot: usize,
+}
+
+impl FrameHandle {
+  // The number of the frame (counts up from 0 over the lifetime of the renderer)
+  pub fn index(&self) -> usize {
+    self.index
+  }
+
+  // Which frame in flight this is (0..frames_in_flight). Resources written every frame should have one copy per slot,
+  // since the GPU may still be reading the other slots' copies
+  pub fn slot(&self) -> usize {
+    self.slot
+  }
+}
+
+pub trait Renderer {
+  type Error: std::error::Error + 'static;
+
+  // Create a vertex buffer big enough for vertex_count vertices
+  fn create_vertex_buffer(&mut self, vertex_count: usize) -> Result<BufferHandle, Self::Error>;
+  // Create an index buffer big enough for index_count indices
+  fn create_index_buffer(&mut self, index_count: usize) -> Result<BufferHandle, Self::Error>;
+  // Create a vertex buffer holding the given vertices in fast device memory. It can't be updated afterwards
+  fn create_static_vertex_buffer(&mut self, vertices: &[Vertex]) -> Result<BufferHandle, Self::Error>;
+  // Create an index buffer holding the given indices in fast device memory. It can't be updated afterwards
+  fn create_static_index_buffer(&mut self, indices: &[u32]) -> Result<BufferHandle, Self::Error>;
+  // Replace the contents of a vertex buffer (the amount of vertices drawn is the amount last written). The GPU may still be reading
+  // the buffer for an earlier frame, so buffers written every frame should have one copy per frame in flight (see FrameHandle::slot)
+  fn update_vertex_buffer(&mut self, buffer: BufferHandle, vertices: &[Vertex]) -> Result<(), Self::Error>;
+  // Replace the contents of an index buffer (the amount of indices drawn is the amount last written)
+  fn update_index_buffer(&mut self, buffer: BufferHandle, indices: &[u32]) -> Result<(), Self::Error>;
+  // Create a buffer of per instance data big enough for instance_count instances (see draw_instanced)
+  fn create_instance_buffer(&mut self, instance_count: usize) -> Result<BufferHandle, Self::Error>;
+  // Replace the contents of an instance buffer (the amount of instances drawn is the amount last written)
+  fn update_instance_buffer(&mut self, buffer: BufferHandle, instances: &[InstanceData]) -> Result<(), Self::Error>;
+  // Destroy a buffer, the handle must not be used afterwards
+  fn destroy_buffer(&mut self, buffer: BufferHandle) -> Result<(), Self::Error>;
+
+  // Create a texture from an image file (PNG or JPEG)
+  fn create_texture_from_file(&mut self, path: &std::path::Path) -> Result<TextureHandle, Self::Error>;
+  // Create a texture from an image file that's already in memory (PNG or JPEG)
+  fn create_texture_from_memory(&mut self, bytes: &[u8]) -> Result<TextureHandle, Self::Error>;
+  // Create a texture from tightly packed RGBA8 rows, top row first
+  fn create_texture_from_rgba(&mut self, width: u32, height: u32, pixels: &[u8]) -> Result<TextureHandle, Self::Error>;
+  // Destroy a texture, the handle must not be used afterwards
+  fn destroy_texture(&mut self, texture: TextureHandle) -> Result<(), Self::Error>;
+
+  // The pipeline used for plain colored geometry, described by PipelineDescription::default(). It can't be destroyed
+  fn default_pipeline(&self) -> PipelineHandle;
+  // Create a pipeline to draw with, e.g. for another blend mode, lines or custom shaders. Expensive, so best done while loading
+  fn create_pipeline(&mut self, description: &PipelineDescription) -> Result<PipelineHandle, Self::Error>;
+  // Destroy a pipeline, the handle must not be used afterwards
+  fn destroy_pipeline(&mut self, pipeline: PipelineHandle) -> Result<(), Self::Error>;
+
+  // How many frames the CPU may record ahead of the GPU (see FrameHandle::slot)
+  fn frames_in_flight(&self) -> usize;
+
+  // Start recording a frame
+  fn begin_frame(&mut self) -> Result<FrameHandle, Self::Error>;
+  // Set the matrix (column major) that takes vertices from world space to clip space, used from the next frame submitted on.
+  // It's the identity until set, so world space is clip space. See renderer::camera for building one
+  fn set_view_projection(&mut self, view_projection: [[f32; 4]; 4]);
+  // Set the model matrix and tint of the draws that follow in this frame. begin_frame resets them to PushConstants::IDENTITY
+  fn set_push_constants(&mut self, frame: &FrameHandle, push_constants: PushConstants);
+  // Clip the draws that follow in this frame to the given rectangle (e.g. a scrolling UI panel), None draws to the whole framebuffer.
+  // begin_frame resets it to None
+  fn set_scissor(&mut self, frame: &FrameHandle, scissor: Option<ScissorRect>);
+  // Draw the vertices in vertex_buffer with the given pipeline, using index_buffer to index them if given.
+  // The texture is multiplied with the vertex colors, without one the vertex colors are used as is
+  fn draw(
+    &mut self, frame: &FrameHandle, pipeline: PipelineHandle, vertex_buffer: BufferHandle, index_buffer: Option<BufferHandle>,
+    texture: Option<TextureHandle>,
+  ) -> Result<(), Self::Error>;
+  // Draw index_count indices from index_buffer, starting at first_index, indexing into vertex_buffer
+  #[allow(clippy::too_many_arguments)]
+  fn draw_indexed(
+    &mut self, frame: &FrameHandle, pipeline: PipelineHandle, vertex_buffer: BufferHandle, index_buffer: BufferHandle,
+    first_index: u32, index_count: u32, texture: Option<TextureHandle>,
+  ) -> Result<(), Self::Error>;
+  // Like draw, but the vertices are drawn once for every instance in instance_buffer, in a single draw call
+  fn draw_instanced(
+    &mut self, frame: &FrameHandle, pipeline: PipelineHandle, vertex_buffer: BufferHandle, index_buffer: Option<BufferHandle>,
+    instance_buffer: BufferHandle, texture: Option<TextureHandle>,
+  ) -> Result<(), Self::Error>;
+  // Submit everything drawn this frame. When rendering headless the rendered pixels are handed back (tightly packed RGBA8 rows)
+  fn end_frame(&mut self, frame: FrameHandle) -> Result<Option<Vec<u8>>, Self::Error>;
+
+  // Let the renderer know the window has been resized so it can recreate whatever depends on the window size
+  fn window_resized(&mut self);
+  // Wait for the display's refresh before showing a frame (no tearing, frame rate capped to the refresh rate), or show frames as soon as they're done
+  fn set_vsync(&mut self, vsync: bool) -> Result<(), Self::Error>;
+  fn set_window_title(&self, title: &str);
+  fn request_redraw(&self);
+}
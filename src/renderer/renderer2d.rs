@@ -0,0 +1,389 @@
+// The 2D batch renderer. Everything in 2D is a quad (a sprite is just a quad with a texture that has transparency), so between
+// begin_scene and end_scene quads are only collected on the CPU. end_scene writes all of them into one big vertex buffer and
+// draws them with a single indexed draw call per run of quads sharing a texture, instead of one draw call (and buffer) per quad.
+// It only uses the Renderer trait, so it works with any backend.
+
+use super::*;
+
+// How many quads the buffers can hold before they have to grow. They grow to the next power of two that fits the scene,
+// so after the first few frames of a scene they stop growing
+const INITIAL_QUAD_CAPACITY: usize = 10_000;
+const VERTICES_PER_QUAD: usize = 4;
+const INDICES_PER_QUAD: usize = 6;
+const QUAD_INDICES: [u32; INDICES_PER_QUAD] = [0, 1, 2, 2, 3, 0]; // Two triangles, relative to the quad's first vertex
+
+// What the last scene cost, useful for checking the batching actually works
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Renderer2DStats {
+  pub quads: usize,
+  pub draw_calls: usize,
+}
+
+// A run of consecutive quads that use the same texture, drawn with one draw call
+struct Batch {
+  texture: Option<TextureHandle>,
+  first_quad: usize,
+  quad_count: usize,
+}
+
+// A vertex buffer the scene is written into, one per frame in flight so we never overwrite vertices the GPU is still reading
+struct SceneBuffer {
+  vertex_buffer: BufferHandle,
+  quad_capacity: usize,
+}
+
+pub struct Renderer2D {
+  pipeline: PipelineHandle,
+  scene_buffers: Vec<SceneBuffer>,
+  index_buffer: BufferHandle, // Every quad uses the same index pattern, so the indices never change and one static buffer is shared by all frames
+  index_quad_capacity: usize,
+  vertices: Vec<Vertex>, // The quads of the current scene
+  batches: Vec<Batch>,
+  is_in_scene: bool,
+  last_frame: Option<usize>, // The frame the last scene was drawn in (only one scene per frame for now)
+  stats: Renderer2DStats,
+}
+
+impl Renderer2D {
+  pub fn new<R: Renderer>(renderer: &mut R) -> Result<Renderer2D, R::Error> {
+    let mut scene_buffers = Vec::with_capacity(renderer.frames_in_flight());
+    for _ in 0..renderer.frames_in_flight() {
+      scene_buffers.push(SceneBuffer {
+        vertex_buffer: renderer.create_vertex_buffer(INITIAL_QUAD_CAPACITY * VERTICES_PER_QUAD)?,
+        quad_capacity: INITIAL_QUAD_CAPACITY,
+      });
+    }
+    let index_buffer = renderer.create_static_index_buffer(&Renderer2D::quad_indices(INITIAL_QUAD_CAPACITY))?;
+
+    Ok(Renderer2D {
+      pipeline: renderer.default_pipeline(),
+      scene_buffers,
+      index_buffer,
+      index_quad_capacity: INITIAL_QUAD_CAPACITY,
+      vertices: Vec::with_capacity(INITIAL_QUAD_CAPACITY * VERTICES_PER_QUAD),
+      batches: vec![],
+      is_in_scene: false,
+      last_frame: None,
+      stats: Renderer2DStats::default(),
+    })
+  }
+
+  // Start collecting the quads of a scene
+  pub fn begin_scene(&mut self) {
+    debug_assert!(!self.is_in_scene, "begin_scene called twice without end_scene");
+    self.vertices.clear();
+    self.batches.clear();
+    self.is_in_scene = true;
+  }
+
+  // Queue a quad centered on position with the given size and color. The position is in world space (see Renderer::set_view_projection),
+  // z is the depth
+  pub fn draw_quad(&mut self, position: [f32; 3], size: [f32; 2], color: [f32; 4]) {
+    self.push_quad(position, size, color, None, UvRect::FULL);
+  }
+
+  // Queue a quad showing the whole texture, multiplied by tint (use white to draw the texture as is)
+  pub fn draw_textured_quad(&mut self, position: [f32; 3], size: [f32; 2], texture: TextureHandle, tint: [f32; 4]) {
+    self.push_quad(position, size, tint, Some(texture), UvRect::FULL);
+  }
+
+  // Queue a quad showing part of a texture, e.g. a sprite in a texture atlas (see TextureAtlas::uv)
+  pub fn draw_sprite(&mut self, position: [f32; 3], size: [f32; 2], texture: TextureHandle, uv: UvRect, tint: [f32; 4]) {
+    self.push_quad(position, size, tint, Some(texture), uv);
+  }
+
+  fn push_quad(&mut self, position: [f32; 3], size: [f32; 2], color: [f32; 4], texture: Option<TextureHandle>, uv: UvRect) {
+    debug_assert!(self.is_in_scene, "quad drawn outside of begin_scene/end_scene");
+    let [x, y, z] = position;
+    let (half_width, half_height) = (size[0] / 2.0, size[1] / 2.0);
+    // World y points up (see Camera2D) and texture v down, so the top of the texture goes at the top of the quad
+    self.vertices.extend_from_slice(&[
+      Vertex { pos: [x - half_width, y + half_height, z, 1.0], color, uv: [uv.min[0], uv.min[1]] },
+      Vertex { pos: [x + half_width, y + half_height, z, 1.0], color, uv: [uv.max[0], uv.min[1]] },
+      Vertex { pos: [x + half_width, y - half_height, z, 1.0], color, uv: [uv.max[0], uv.max[1]] },
+      Vertex { pos: [x - half_width, y - half_height, z, 1.0], color, uv: [uv.min[0], uv.max[1]] },
+    ]);
+
+    // Quads are drawn in the order they were queued, so a new batch starts whenever the texture changes
+    match self.batches.last_mut() {
+      Some(batch) if batch.texture == texture => batch.quad_count += 1,
+      _ => self.batches.push(Batch { texture, first_quad: self.vertices.len() / VERTICES_PER_QUAD - 1, quad_count: 1 }),
+    }
+  }
+
+  // Write every quad queued since begin_scene into this frame's vertex buffer and draw them all at once
+  pub fn end_scene<R: Renderer>(&mut self, renderer: &mut R, frame: &FrameHandle) -> Result<(), R::Error> {
+    debug_assert!(self.is_in_scene, "end_scene called without begin_scene");
+    debug_assert!(self.last_frame != Some(frame.index()), "only one 2D scene can be drawn per frame");
+    self.is_in_scene = false;
+    self.last_frame = Some(frame.index());
+
+    let quad_count = self.vertices.len() / VERTICES_PER_QUAD;
+    self.stats = Renderer2DStats { quads: quad_count, draw_calls: 0 };
+    if quad_count == 0 {
+      return Ok(());
+    }
+
+    // Grow the buffers if the scene doesn't fit. This waits for the GPU, but only happens when the scene gets bigger than ever before
+    if quad_count > self.index_quad_capacity {
+      let capacity = quad_count.next_power_of_two();
+      renderer.destroy_buffer(self.index_buffer)?;
+      self.index_buffer = renderer.create_static_index_buffer(&Renderer2D::quad_indices(capacity))?;
+      self.index_quad_capacity = capacity;
+    }
+    let scene_buffer = &mut self.scene_buffers[frame.slot()];
+    if quad_count > scene_buffer.quad_capacity {
+      let capacity = quad_count.next_power_of_two();
+      renderer.destroy_buffer(scene_buffer.vertex_buffer)?;
+      scene_buffer.vertex_buffer = renderer.create_vertex_buffer(capacity * VERTICES_PER_QUAD)?;
+      scene_buffer.quad_capacity = capacity;
+    }
+
+    // begin_frame waited for the last frame that used this slot, so its vertex buffer is free to overwrite
+    renderer.update_vertex_buffer(scene_buffer.vertex_buffer, &self.vertices)?;
+    for batch in &self.batches {
+      renderer.draw_indexed(
+        frame, self.pipeline, scene_buffer.vertex_buffer, self.index_buffer,
+        (batch.first_quad * INDICES_PER_QUAD) as u32, (batch.quad_count * INDICES_PER_QUAD) as u32, batch.texture,
+      )?;
+      self.stats.draw_calls += 1;
+    }
+    Ok(())
+  }
+
+  // What the last scene drew
+  pub fn stats(&self) -> Renderer2DStats {
+    self.stats
+  }
+
+  pub fn destroy<R: Renderer>(self, renderer: &mut R) -> Result<(), R::Error> {
+    for scene_buffer in self.scene_buffers {
+      renderer.destroy_buffer(scene_buffer.vertex_buffer)?;
+    }
+    renderer.destroy_buffer(self.index_buffer)
+  }
+
+  // The indices for quad_count quads, each using QUAD_INDICES offset to its own 4 vertices
+  fn quad_indices(quad_count: usize) -> Vec<u32> {
+    (0..quad_count as u32)
+      .flat_map(|quad| QUAD_INDICES.iter().map(move |index| quad * VERTICES_PER_QUAD as u32 + index))
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // A draw_indexed call the mock renderer received
+  #[derive(Debug, PartialEq)]
+  struct DrawIndexed {
+    vertex_buffer: BufferHandle,
+    index_buffer: BufferHandle,
+    first_index: u32,
+    index_count: u32,
+    texture: Option<TextureHandle>,
+  }
+
+  // Records what Renderer2D asks for instead of rendering anything
+  #[derive(Default)]
+  struct MockRenderer {
+    buffers: Vec<Option<usize>>, // How many elements each buffer holds, None once destroyed
+    vertex_updates: Vec<(BufferHandle, usize)>,
+    draws: Vec<DrawIndexed>,
+  }
+
+  impl MockRenderer {
+    fn create_buffer(&mut self, len: usize) -> Result<BufferHandle, std::fmt::Error> {
+      self.buffers.push(Some(len));
+      Ok(BufferHandle(self.buffers.len() - 1))
+    }
+
+    fn buffer_len(&self, buffer: BufferHandle) -> Option<usize> {
+      self.buffers[buffer.0]
+    }
+
+    fn live_buffers(&self) -> usize {
+      self.buffers.iter().flatten().count()
+    }
+  }
+
+  impl Renderer for MockRenderer {
+    type Error = std::fmt::Error;
+
+    fn create_vertex_buffer(&mut self, vertex_count: usize) -> Result<BufferHandle, Self::Error> {
+      self.create_buffer(vertex_count)
+    }
+    fn create_index_buffer(&mut self, index_count: usize) -> Result<BufferHandle, Self::Error> {
+      self.create_buffer(index_count)
+    }
+    fn create_static_vertex_buffer(&mut self, vertices: &[Vertex]) -> Result<BufferHandle, Self::Error> {
+      self.create_buffer(vertices.len())
+    }
+    fn create_static_index_buffer(&mut self, indices: &[u32]) -> Result<BufferHandle, Self::Error> {
+      self.create_buffer(indices.len())
+    }
+    fn update_vertex_buffer(&mut self, buffer: BufferHandle, vertices: &[Vertex]) -> Result<(), Self::Error> {
+      assert!(vertices.len() <= self.buffer_len(buffer).expect("updated a destroyed buffer"), "vertices don't fit the buffer");
+      self.vertex_updates.push((buffer, vertices.len()));
+      Ok(())
+    }
+    fn update_index_buffer(&mut self, _buffer: BufferHandle, _indices: &[u32]) -> Result<(), Self::Error> {
+      unimplemented!()
+    }
+    fn create_instance_buffer(&mut self, _instance_count: usize) -> Result<BufferHandle, Self::Error> {
+      unimplemented!()
+    }
+    fn update_instance_buffer(&mut self, _buffer: BufferHandle, _instances: &[InstanceData]) -> Result<(), Self::Error> {
+      unimplemented!()
+    }
+    fn destroy_buffer(&mut self, buffer: BufferHandle) -> Result<(), Self::Error> {
+      assert!(self.buffers[buffer.0].take().is_some(), "destroyed a buffer twice");
+      Ok(())
+    }
+    fn create_texture_from_file(&mut self, _path: &std::path::Path) -> Result<TextureHandle, Self::Error> {
+      unimplemented!()
+    }
+    fn create_texture_from_memory(&mut self, _bytes: &[u8]) -> Result<TextureHandle, Self::Error> {
+      unimplemented!()
+    }
+    fn create_texture_from_rgba(&mut self, _width: u32, _height: u32, _pixels: &[u8]) -> Result<TextureHandle, Self::Error> {
+      unimplemented!()
+    }
+    fn destroy_texture(&mut self, _texture: TextureHandle) -> Result<(), Self::Error> {
+      unimplemented!()
+    }
+    fn default_pipeline(&self) -> PipelineHandle {
+      PipelineHandle(0)
+    }
+    fn create_pipeline(&mut self, _description: &PipelineDescription) -> Result<PipelineHandle, Self::Error> {
+      unimplemented!()
+    }
+    fn destroy_pipeline(&mut self, _pipeline: PipelineHandle) -> Result<(), Self::Error> {
+      unimplemented!()
+    }
+    fn frames_in_flight(&self) -> usize {
+      2
+    }
+    fn begin_frame(&mut self) -> Result<FrameHandle, Self::Error> {
+      unimplemented!()
+    }
+    fn set_view_projection(&mut self, _view_projection: [[f32; 4]; 4]) {}
+    fn set_push_constants(&mut self, _frame: &FrameHandle, _push_constants: PushConstants) {}
+    fn set_scissor(&mut self, _frame: &FrameHandle, _scissor: Option<ScissorRect>) {}
+    fn draw(
+      &mut self, _frame: &FrameHandle, _pipeline: PipelineHandle, _vertex_buffer: BufferHandle, _index_buffer: Option<BufferHandle>,
+      _texture: Option<TextureHandle>,
+    ) -> Result<(), Self::Error> {
+      unimplemented!()
+    }
+    fn draw_indexed(
+      &mut self, _frame: &FrameHandle, _pipeline: PipelineHandle, vertex_buffer: BufferHandle, index_buffer: BufferHandle,
+      first_index: u32, index_count: u32, texture: Option<TextureHandle>,
+    ) -> Result<(), Self::Error> {
+      let available = self.buffer_len(index_buffer).expect("drew with a destroyed index buffer");
+      assert!((first_index + index_count) as usize <= available, "indices past the end of the index buffer");
+      self.draws.push(DrawIndexed { vertex_buffer, index_buffer, first_index, index_count, texture });
+      Ok(())
+    }
+    fn draw_instanced(
+      &mut self, _frame: &FrameHandle, _pipeline: PipelineHandle, _vertex_buffer: BufferHandle, _index_buffer: Option<BufferHandle>,
+      _instance_buffer: BufferHandle, _texture: Option<TextureHandle>,
+    ) -> Result<(), Self::Error> {
+      unimplemented!()
+    }
+    fn end_frame(&mut self, _frame: FrameHandle) -> Result<Option<Vec<u8>>, Self::Error> {
+      unimplemented!()
+    }
+    fn window_resized(&mut self) {}
+    fn set_vsync(&mut self, _vsync: bool) -> Result<(), Self::Error> {
+      unimplemented!()
+    }
+    fn set_window_title(&self, _title: &str) {}
+    fn request_redraw(&self) {}
+  }
+
+  fn frame(index: usize) -> FrameHandle {
+    FrameHandle { index, slot: index % 2 }
+  }
+
+  #[test]
+  fn batches_by_texture() {
+    let mut renderer = MockRenderer::default();
+    let mut renderer2d = Renderer2D::new(&mut renderer).unwrap();
+    let (texture_a, texture_b) = (TextureHandle(1), TextureHandle(2));
+
+    renderer2d.begin_scene();
+    for _ in 0..2 {
+      renderer2d.draw_textured_quad([0.0, 0.0, 0.0], [1.0, 1.0], texture_a, [1.0; 4]);
+    }
+    renderer2d.draw_sprite([0.0, 0.0, 0.0], [1.0, 1.0], texture_b, UvRect::FULL, [1.0; 4]);
+    for _ in 0..3 {
+      renderer2d.draw_textured_quad([0.0, 0.0, 0.0], [1.0, 1.0], texture_a, [1.0; 4]);
+    }
+    renderer2d.end_scene(&mut renderer, &frame(0)).unwrap();
+
+    // Switching back to A starts a new batch, quads are drawn in the order they were queued
+    let vertex_buffer = renderer2d.scene_buffers[0].vertex_buffer;
+    let index_buffer = renderer2d.index_buffer;
+    let batch = |first_index, index_count, texture| DrawIndexed { vertex_buffer, index_buffer, first_index, index_count, texture: Some(texture) };
+    assert_eq!(renderer.draws, [batch(0, 12, texture_a), batch(12, 6, texture_b), batch(18, 18, texture_a)]);
+    assert_eq!(renderer.vertex_updates, [(vertex_buffer, 6 * VERTICES_PER_QUAD)]);
+    assert_eq!(renderer2d.stats(), Renderer2DStats { quads: 6, draw_calls: 3 });
+  }
+
+  #[test]
+  fn empty_scene() {
+    let mut renderer = MockRenderer::default();
+    let mut renderer2d = Renderer2D::new(&mut renderer).unwrap();
+    renderer2d.begin_scene();
+    renderer2d.end_scene(&mut renderer, &frame(0)).unwrap();
+    assert!(renderer.draws.is_empty());
+    assert!(renderer.vertex_updates.is_empty());
+    assert_eq!(renderer2d.stats(), Renderer2DStats::default());
+  }
+
+  #[test]
+  fn grows_past_initial_capacity() {
+    let mut renderer = MockRenderer::default();
+    let mut renderer2d = Renderer2D::new(&mut renderer).unwrap();
+    let initial_index_buffer = renderer2d.index_buffer;
+    let initial_vertex_buffers: Vec<BufferHandle> = renderer2d.scene_buffers.iter().map(|scene_buffer| scene_buffer.vertex_buffer).collect();
+    let quad_count = INITIAL_QUAD_CAPACITY + 1;
+    let draw_scene = |renderer2d: &mut Renderer2D, renderer: &mut MockRenderer, frame: FrameHandle| {
+      renderer2d.begin_scene();
+      for _ in 0..quad_count {
+        renderer2d.draw_quad([0.0, 0.0, 0.0], [1.0, 1.0], [1.0; 4]);
+      }
+      renderer2d.end_scene(renderer, &frame).unwrap();
+    };
+
+    // Frame 1 uses slot 1: the shared index buffer and slot 1's vertex buffer grow, slot 0's is left alone
+    draw_scene(&mut renderer2d, &mut renderer, frame(1));
+    let capacity = quad_count.next_power_of_two();
+    assert_eq!(renderer.buffer_len(initial_index_buffer), None);
+    assert_eq!(renderer.buffer_len(initial_vertex_buffers[1]), None);
+    assert_eq!(renderer.buffer_len(renderer2d.index_buffer), Some(capacity * INDICES_PER_QUAD));
+    assert_eq!(renderer.buffer_len(renderer2d.scene_buffers[1].vertex_buffer), Some(capacity * VERTICES_PER_QUAD));
+    assert_eq!(renderer2d.scene_buffers[0].vertex_buffer, initial_vertex_buffers[0]);
+    let draw = DrawIndexed {
+      vertex_buffer: renderer2d.scene_buffers[1].vertex_buffer,
+      index_buffer: renderer2d.index_buffer,
+      first_index: 0,
+      index_count: (quad_count * INDICES_PER_QUAD) as u32,
+      texture: None,
+    };
+    assert_eq!(renderer.draws, [draw]);
+
+    // Slot 0 only has its vertex buffer to grow, the index buffer is big enough now
+    let grown_index_buffer = renderer2d.index_buffer;
+    draw_scene(&mut renderer2d, &mut renderer, frame(2));
+    assert_eq!(renderer2d.index_buffer, grown_index_buffer);
+    assert_eq!(renderer.buffer_len(initial_vertex_buffers[0]), None);
+    assert_eq!(renderer.buffer_len(renderer2d.scene_buffers[0].vertex_buffer), Some(capacity * VERTICES_PER_QUAD));
+    assert_eq!(renderer.live_buffers(), 3);
+
+    renderer2d.destroy(&mut renderer).unwrap();
+    assert_eq!(renderer.live_buffers(), 0);
+  }
+}
\ No newline at end of file
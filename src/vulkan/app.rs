@@ -1,5 +1,7 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
 use ash::vk;
-use ash::vk::DebugUtilsMessengerCreateInfoEXT;
 use gpu_allocator::vulkan::*;
 
 #[cfg(any(target_os = "macos", target_os = "ios"))]
@@ -11,120 +13,373 @@ use super::surface::*;
 use super::command_pool::*;
 use super::queue::*;
 use super::pipeline::*;
+use super::pipeline_cache::*;
+use super::shader_watcher::*;
 use super::swapchain::*;
 use super::debug_utils::*;
 use super::vertex_buffer::*;
+use super::instance_buffer::*;
 use super::vertex::*;
 use super::index_buffer::*;
 use super::physical_device::*;
+use super::device_report::*;
 use super::logical_device::*;
 use super::renderable::*;
 use super::render_pass::*;
+use super::offscreen::*;
+use super::attachment::*;
+use super::config::*;
+use super::error::*;
+use super::renderer::*;
+use crate::renderer::{PipelineDescription, PipelineHandle};
+use super::frames::*;
+use super::upload::*;
+use super::descriptors::*;
+use super::texture::*;
+use super::uniform_buffer::*;
+use super::uniforms::*;
+
+// What the color target is cleared to at the start of every frame. Linear, like every color the GPU works with (0.08 once sRGB encoded)
+const CLEAR_COLOR: [f32; 4] = [0.0, 0.0, 0.007, 1.0];
 
 // Stores what we need to use Vulkan to render our graphics (including the window)
+// When running headless there is no window, surface or swapchain, instead we render into an offscreen target
 pub struct VulkanApp {
-  pub window: winit::window::Window,
+  pub window: Option<winit::window::Window>,
+  pub config: RendererConfig,
   pub entry: ash::Entry,
   pub is_framebuffer_resized: bool,
   pub instance: ash::Instance,
   pub debug: std::mem::ManuallyDrop<VulkanDebugInfo>,
-  pub surface: std::mem::ManuallyDrop<VulkanSurface>,
+  pub validation_errors: Arc<AtomicUsize>, // How many errors the validation layer reported. Outlives the instance, the callback counts into it
+  pub surface: std::mem::ManuallyDrop<Option<VulkanSurface>>,
   pub physical_device: vk::PhysicalDevice,
   pub physical_device_properties: vk::PhysicalDeviceProperties,
   pub physical_device_features: vk::PhysicalDeviceFeatures,
   pub queue_families: QueueFamilies,
   pub queues: Queues,
   pub device: ash::Device,
-  pub swapchain: VulkanSwapchain,
+  pub swapchain: Option<VulkanSwapchain>,
+  pub offscreen: Option<OffscreenTarget>,
+  pub msaa_samples: vk::SampleCountFlags, // The sample count actually used (config.msaa_samples clamped to what the device supports)
+  pub msaa_target: Option<AttachmentImage>, // The multisampled color image resolved into the swapchain/offscreen image, None without MSAA
+  pub depth_format: Option<vk::Format>, // None without a depth buffer
+  pub depth_target: Option<AttachmentImage>, // The depth image, the same size (and sample count) as the color target
   pub renderpass: vk::RenderPass,
-  pub pipeline: Pipeline,
+  pub descriptors: Descriptors,
+  pub pipelines: Vec<Option<PipelineSlot>>, // Pipelines created through the Renderer trait, indexed by PipelineHandle. 0 is the default pipeline
+  pub pipeline_cache: PipelineCache, // Every pipeline is created through it
+  pub shader_watcher: Option<ShaderWatcher>, // Watches config.shader_directory, None if there isn't one
   pub pools: Pools,
-  pub commandbuffers: Vec<vk::CommandBuffer>,
+  pub frames: Frames,
+  pub uploader: Uploader,
   pub allocator: std::mem::ManuallyDrop<Allocator>,
   pub renderables: Vec<Renderable>,
+  pub buffers: Vec<Option<BufferSlot>>, // Buffers created through the Renderer trait, indexed by BufferHandle
+  pub textures: Vec<Option<Texture>>, // Textures created through the Renderer trait, indexed by TextureHandle
+  pub white_texture: Texture, // 1x1 white texture bound for untextured draws, so the shaders don't need an untextured variant
+  pub identity_instance: InstanceBuffer, // A single InstanceData::IDENTITY, bound for non-instanced draws
+  pub frame_uniforms: Vec<FrameUniforms>, // The uniform buffers of each frame in flight, indexed like Frames::frames
+  pub camera: CameraUniform, // Written into the uniform buffer of each frame when it's submitted
+  pub push_constants: PushConstants, // Pushed for draws queued through the Renderer trait from now on
+  pub scissor: Option<vk::Rect2D>, // The scissor rect of draws queued through the Renderer trait from now on
+  pub draws: Vec<DrawCall>, // Draws queued through the Renderer trait for the current frame
+  pub frame_count: usize,
 }
 
 impl VulkanApp {
-  pub fn init(window: winit::window::Window) -> Result<VulkanApp, Box<dyn std::error::Error>> {
-      let entry = ash::Entry::linked(); // Statically link the Vulkan library at compile time
-
-      let layer_names = vec!["VK_LAYER_KHRONOS_validation"]; // Enable the validation layer
-      let instance = VulkanApp::init_instance(&entry, &layer_names, &window).0.expect("Failed to initialize instance!"); // Create the instance
-      let debug = VulkanDebugInfo::init(&entry, &instance)?; // Create the debug info
-      let surface = VulkanSurface::init(&window, &entry, &instance)?; // Create the surface
+  // Create an app that renders to (and presents on) the given window
+  pub fn init(window: winit::window::Window) -> Result<VulkanApp, RendererError> {
+      VulkanApp::init_with_config(window, RendererConfig::default())
+  }
 
-      // Find the most suitable physical device
-      let (physical_device, physical_device_properties, physical_device_features) = PhysicalDevice::pick_physical_device(&instance).expect("No suitable physical device found!");
+  pub fn init_with_config(window: winit::window::Window, config: RendererConfig) -> Result<VulkanApp, RendererError> {
+      VulkanApp::init_internal(Some(window), None, config)
+  }
 
-      // Find the most suitable queue families on the physical device
-      let queue_families = QueueFamilies::init(&instance, physical_device, &surface)?;
+  // Create an app with no window, surface, swapchain or presentation. Frames are rendered into an offscreen
+  // color image of the given size and draw_frame hands back the pixels (RGBA8).
+  // Works with software ICDs such as lavapipe, so it can be used for render tests in CI.
+  pub fn init_headless(width: u32, height: u32) -> Result<VulkanApp, RendererError> {
+      VulkanApp::init_headless_with_config(width, height, RendererConfig::default())
+  }
 
-      // Create the logical device
-      let (logical_device, queues) = LogicalDevice::init_device_and_queues(&instance, physical_device, &queue_families, &layer_names)?;
+  pub fn init_headless_with_config(width: u32, height: u32, config: RendererConfig) -> Result<VulkanApp, RendererError> {
+      VulkanApp::init_internal(None, Some(vk::Extent2D { width, height }), config)
+  }
 
-      // Create the swapchain
-      let mut swapchain = VulkanSwapchain::init(&instance, physical_device, &logical_device, &surface, &queue_families, &queues)?;
+  fn init_internal(window: Option<winit::window::Window>, headless_extent: Option<vk::Extent2D>, config: RendererConfig) -> Result<VulkanApp, RendererError> {
+      let entry = ash::Entry::linked(); // Statically link the Vulkan library at compile time
 
-      // Create the render pass
-      let renderpass = RenderPass::init_renderpass(&logical_device, physical_device, swapchain.surface_format.format)?;
+      // Enable the validation layer (if it's installed, build servers often won't have it)
+      let layer_names = VulkanApp::get_available_layers(&entry, &["VK_LAYER_KHRONOS_validation"]);
+      let validation_errors = Arc::new(AtomicUsize::new(0));
+      // Everything created from here on goes into the guard straight away, so it's destroyed again if anything after it fails
+      let mut guard = InitGuard::default();
+      let instance = &*guard.instance.insert(VulkanApp::init_instance(&entry, &layer_names, window.as_ref(), &validation_errors)?); // Create the instance
+      guard.debug = Some(VulkanDebugInfo::init(&entry, instance, &validation_errors)?); // Create the debug info
+      guard.surface = match &window { // Create the surface (only when we have a window to present to)
+        Some(window) => Some(VulkanSurface::init(window, &entry, instance)?),
+        None => None,
+      };
+      let surface = guard.surface.as_ref();
 
-      // Create the framebuffers
-      swapchain.create_framebuffers(&logical_device, renderpass)?;
+      // Find the most suitable physical device (or the one asked for)
+      let device_selection = DeviceSelection::from_env().or(config.device.clone());
+      let (physical_device, physical_device_properties, physical_device_features) =
+        PhysicalDevice::pick_physical_device(instance, surface, device_selection.as_ref())?;
 
-      // Create the pipeline
-      let pipeline = Pipeline::init(&logical_device, &swapchain, &renderpass)?;
+      // Find the most suitable queue families on the physical device
+      let queue_families = QueueFamilies::init(instance, physical_device, surface)?;
 
-      // Create the command pools
-      let pools = Pools::init(&logical_device, &queue_families)?;
+      // Create the logical device, with the optional features we use that the device supports (wireframe pipelines need fillModeNonSolid)
+      let enabled_features = vk::PhysicalDeviceFeatures { fill_mode_non_solid: physical_device_features.fill_mode_non_solid, ..Default::default() };
+      let (logical_device, queues) =
+        LogicalDevice::init_device_and_queues(instance, physical_device, &queue_families, &layer_names, surface.is_some(), &enabled_features)?;
+      let logical_device = &*guard.device.insert(logical_device);
 
       let buffer_device_address = false; // Check for and enable buffer device address support at creation time
-      let mut allocator = Allocator::new(&AllocatorCreateDesc {
+      let allocator = guard.allocator.insert(Allocator::new(&AllocatorCreateDesc {
         instance: instance.clone(),
         device: logical_device.clone(),
         physical_device,
         debug_settings: Default::default(),
-        buffer_device_address: buffer_device_address,  // Ideally, check the BufferDeviceAddressFeatures struct.
-      }).expect("Failed to create allocator!");
+        buffer_device_address,  // Ideally, check the BufferDeviceAddressFeatures struct.
+      })?);
       allocator.report_memory_leaks(log::Level::Info);
 
-      // Create the command buffers (one for each framebuffer)
-      let commandbuffers = VulkanApp::create_commandbuffers(&logical_device, &pools, swapchain.amount_of_images)?;
-
-      // Fill the command buffers
-      VulkanApp::fill_commandbuffers(
-          &commandbuffers,
-          &logical_device,
-          &renderpass,
-          &swapchain,
-          &pipeline,
-          &vec![],
+      // Create the swapchain, or the offscreen target when running headless
+      match (surface, headless_extent) {
+        (Some(surface), _) => guard.swapchain = Some(
+          VulkanSwapchain::init(instance, physical_device, logical_device, surface, &queue_families, &queues, &config.swapchain)?,
+        ),
+        (None, Some(extent)) => guard.offscreen = Some(OffscreenTarget::init(logical_device, allocator, extent)?),
+        (None, None) => unreachable!("Either a window or a headless extent is required"),
+      };
+
+      if let Some(swapchain) = &guard.swapchain {
+        VulkanApp::report_present_mode(&config.swapchain, swapchain);
+      }
+
+      let (format, final_layout, extent) = match (&guard.swapchain, &guard.offscreen) {
+        (Some(swapchain), _) => (swapchain.surface_format.format, vk::ImageLayout::PRESENT_SRC_KHR, swapchain.extent),
+        (None, Some(offscreen)) => (offscreen.format, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, offscreen.extent),
+        (None, None) => unreachable!(),
+      };
+
+      // Find a depth format (if the depth buffer is enabled)
+      let depth_format = match config.depth_buffer {
+        true => PhysicalDevice::pick_depth_format(instance, physical_device, config.stencil_buffer),
+        false => None,
+      };
+      if config.depth_buffer && depth_format.is_none() {
+        log::warn!("No supported depth{} format, continuing without a depth buffer.", if config.stencil_buffer { "/stencil" } else { "" });
+      }
+
+      // Create the multisampled color image (if the device supports MSAA and it's enabled)
+      let msaa_samples = PhysicalDevice::pick_sample_count(&physical_device_properties, config.msaa_samples, depth_format);
+      if msaa_samples.as_raw() != config.msaa_samples {
+        log::warn!("{}x MSAA is not supported, using {}x instead.", config.msaa_samples, msaa_samples.as_raw());
+      }
+      guard.msaa_target = match msaa_samples {
+        vk::SampleCountFlags::TYPE_1 => None,
+        samples => Some(AttachmentImage::new_msaa_color(logical_device, allocator, extent, format, samples)?),
+      };
+      let msaa_view = guard.msaa_target.as_ref().map(|target| target.imageview);
+
+      // Create the depth image
+      guard.depth_target = match depth_format {
+        Some(depth_format) => Some(AttachmentImage::new_depth(logical_device, allocator, extent, depth_format, msaa_samples)?),
+        None => None,
+      };
+      let depth_view = guard.depth_target.as_ref().map(|target| target.imageview);
+
+      // Create the render pass
+      let renderpass = *guard.renderpass.insert(
+        RenderPass::init_renderpass(logical_device, physical_device, format, final_layout, msaa_samples, depth_format)?,
+      );
+
+      // Create the framebuffers
+      if let Some(swapchain) = &mut guard.swapchain {
+        swapchain.create_framebuffers(logical_device, renderpass, msaa_view, depth_view)?;
+      }
+      if let Some(offscreen) = &mut guard.offscreen {
+        offscreen.create_framebuffer(logical_device, renderpass, msaa_view, depth_view)?;
+      }
+
+      // Create the descriptor set layouts and pool
+      let descriptors = guard.descriptors.insert(Descriptors::init(logical_device, MAX_FRAMES_IN_FLIGHT)?);
+
+      // Load the pipeline cache, before creating any pipelines
+      let pipeline_cache = &*guard.pipeline_cache.insert(
+        PipelineCache::load(logical_device, &physical_device_properties, config.pipeline_cache_directory.as_deref())?,
+      );
+
+      // Create the default pipeline
+      let builder = VulkanApp::describe_pipeline(
+        logical_device, descriptors, config.shader_directory.as_deref(), msaa_samples, &PipelineDescription::default(),
       )?;
+      guard.pipeline = Some(builder.build(logical_device, renderpass, pipeline_cache.cache)?);
+
+      // Watch the shaders, so pipelines can be rebuilt when they change
+      let shader_watcher = match &config.shader_directory {
+        Some(directory) => match ShaderWatcher::new(directory) {
+          Ok(shader_watcher) => Some(shader_watcher),
+          Err(error) => {
+            log::warn!("Can't watch {} for shader changes: {}", directory.display(), error);
+            None
+          }
+        },
+        None => None,
+      };
+
+      // Create the command pools
+      let pools = &*guard.pools.insert(Pools::init(logical_device, &queue_families)?);
+
+      // Create the frames in flight (each with its own command buffer and sync objects)
+      guard.frames = Some(Frames::init(logical_device, pools, MAX_FRAMES_IN_FLIGHT)?);
 
-      Ok(VulkanApp {
+      // Create the uploader (copies data into device local memory on the transfer queue)
+      let uploader = guard.uploader.insert(Uploader::init(logical_device, pools, &queue_families)?);
+
+      // Create the texture untextured draws sample from (uploaded with the first frame)
+      guard.white_texture = Some(Texture::from_rgba(logical_device, allocator, uploader, descriptors, 1, 1, &[255, 255, 255, 255])?);
+
+      // Create the instance non-instanced draws are drawn with
+      guard.identity_instance = Some(InstanceBuffer::new_static(logical_device, allocator, uploader, &[InstanceData::IDENTITY])?);
+
+      // Create the uniform buffers of each frame in flight
+      for _ in 0..MAX_FRAMES_IN_FLIGHT {
+        let camera_buffer = UniformBuffer::new::<CameraUniform>(logical_device, allocator)?;
+        guard.frame_uniforms.push(FrameUniforms { camera_buffer, camera_set: vk::DescriptorSet::null() }); // The set is allocated every frame
+      }
+
+      // Everything was created, hand it over to the app (which cleans it up from now on) and leave nothing for the guard to destroy
+      let app = VulkanApp {
           window,
+          config,
           entry,
           is_framebuffer_resized: false,
-          instance,
-          debug: std::mem::ManuallyDrop::new(debug),
-          surface: std::mem::ManuallyDrop::new(surface),
+          instance: guard.instance.take().unwrap(),
+          debug: std::mem::ManuallyDrop::new(guard.debug.take().unwrap()),
+          validation_errors,
+          surface: std::mem::ManuallyDrop::new(guard.surface.take()),
           physical_device,
           physical_device_properties,
           physical_device_features,
           queue_families,
           queues,
-          device: logical_device,
-          swapchain,
-          renderpass,
-          pipeline,
-          pools,
-          commandbuffers,
-          allocator: std::mem::ManuallyDrop::new(allocator),
+          device: guard.device.take().unwrap(),
+          swapchain: guard.swapchain.take(),
+          offscreen: guard.offscreen.take(),
+          msaa_samples,
+          msaa_target: guard.msaa_target.take(),
+          depth_format,
+          depth_target: guard.depth_target.take(),
+          renderpass: guard.renderpass.take().unwrap(),
+          descriptors: guard.descriptors.take().unwrap(),
+          pipelines: vec![Some(PipelineSlot { pipeline: guard.pipeline.take().unwrap(), builder })],
+          pipeline_cache: guard.pipeline_cache.take().unwrap(),
+          shader_watcher,
+          pools: guard.pools.take().unwrap(),
+          frames: guard.frames.take().unwrap(),
+          uploader: guard.uploader.take().unwrap(),
+          allocator: std::mem::ManuallyDrop::new(guard.allocator.take().unwrap()),
           renderables: vec![],
-      })
+          buffers: vec![],
+          textures: vec![],
+          white_texture: guard.white_texture.take().unwrap(),
+          identity_instance: guard.identity_instance.take().unwrap(),
+          frame_uniforms: std::mem::take(&mut guard.frame_uniforms),
+          camera: CameraUniform::IDENTITY,
+          push_constants: PushConstants::IDENTITY,
+          scissor: None,
+          draws: vec![],
+          frame_count: 0,
+      };
+      app.name_objects();
+      if app.config.strict_validation {
+        app.check_validation()?;
+      }
+      Ok(app)
   }
 
-  // Initialize Vulkan instance
-  pub fn init_instance(entry: &ash::Entry, layer_names: &[&str], window: &winit::window::Window) -> (Result<ash::Instance, vk::Result>, DebugUtilsMessengerCreateInfoEXT) {
+  // Name the objects validation messages are most likely to be about. Called again whenever they're recreated
+  fn name_objects(&self) {
+      let result = self.debug.set_object_name(&self.device, self.renderpass, "main render pass")
+        .and_then(|()| self.debug.set_object_name(&self.device, self.pipelines[0].as_ref().unwrap().pipeline.pipeline, "default pipeline"));
+      if let Err(error) = result {
+        log::warn!("Failed to name objects for the validation layer: {}", error);
+      }
+  }
+
+  // How many errors the validation layer has reported so far
+  pub fn validation_error_count(&self) -> usize {
+      self.validation_errors.load(Ordering::Relaxed)
+  }
+
+  // Fail if the validation layer has reported any errors. Strict mode does this after every frame, tests can also call it
+  // themselves after doing something other than drawing
+  pub fn check_validation(&self) -> Result<(), RendererError> {
+      match self.validation_error_count() {
+        0 => Ok(()),
+        count => Err(RendererError::Validation(count)),
+      }
+  }
+
+  // Log every physical device with its score, or why it can't be used. Without a window to check presentation against,
+  // so this also lists devices that can only render headless
+  pub fn list_devices() -> Result<Vec<DeviceCandidate>, RendererError> {
+      let entry = ash::Entry::linked();
+      let validation_errors = Arc::new(AtomicUsize::new(0));
+      let instance = VulkanApp::init_instance(&entry, &[], None, &validation_errors)?;
+      let candidates = PhysicalDevice::list_physical_devices(&instance, None);
+      unsafe { instance.destroy_instance(None) };
+      candidates
+  }
+
+  // Report the capabilities of every physical device, including what they support presenting to the window if there is one
+  pub fn device_report(window: Option<&winit::window::Window>) -> Result<DeviceReport, RendererError> {
+      let entry = ash::Entry::linked();
+      let validation_errors = Arc::new(AtomicUsize::new(0));
+      let instance = VulkanApp::init_instance(&entry, &[], window, &validation_errors)?;
+      let surface = match window {
+        Some(window) => Some(VulkanSurface::init(window, &entry, &instance)?),
+        None => None,
+      };
+      let report = DeviceReport::collect(&entry, &instance, surface.as_ref());
+      drop(surface); // Has to go before the instance
+      unsafe { instance.destroy_instance(None) };
+      report
+  }
+
+  // The same report from a running app, without creating another instance
+  pub fn capability_report(&self) -> Result<DeviceReport, RendererError> {
+      DeviceReport::collect(&self.entry, &self.instance, self.surface.as_ref())
+  }
+
+  // Filter the requested layers down to the ones actually installed on this system
+  pub fn get_available_layers<'a>(entry: &ash::Entry, wanted_layers: &[&'a str]) -> Vec<&'a str> {
+      let available_layers = entry.enumerate_instance_layer_properties().unwrap_or_default();
+      wanted_layers
+          .iter()
+          .filter(|&&wanted| {
+            let found = available_layers.iter().any(|layer| {
+              unsafe { std::ffi::CStr::from_ptr(layer.layer_name.as_ptr()) }.to_str() == Ok(wanted)
+            });
+            if !found {
+              log::warn!("Layer {} is not available, continuing without it.", wanted);
+            }
+            found
+          })
+          .copied()
+          .collect()
+  }
+
+  // Initialize Vulkan instance. Validation errors while creating or destroying it are counted in validation_errors, which has to outlive it
+  pub fn init_instance(
+    entry: &ash::Entry, layer_names: &[&str], window: Option<&winit::window::Window>, validation_errors: &Arc<AtomicUsize>,
+  ) -> Result<ash::Instance, RendererError> {
       let enginename = std::ffi::CString::new("Quasar Engine").unwrap(); // Create a CString with the name of the engine
       let appname = std::ffi::CString::new("Andrew's Vulkan Renderer").unwrap();
 
@@ -151,8 +406,17 @@ impl VulkanApp {
           vec![
               ash::extensions::ext::DebugUtils::name().as_ptr(),
           ];
-      let required_surface_extensions = ash_window::enumerate_required_extensions(&window).unwrap().iter().map(|ext| *ext).collect::<Vec<*const i8>>();
-      extension_name_pointers.extend(required_surface_extensions.iter());
+      if let Some(window) = window { // Headless rendering doesn't need any surface extensions
+        let required_surface_extensions = ash_window::enumerate_required_extensions(window).map_err(RendererError::Instance)?;
+        extension_name_pointers.extend(required_surface_extensions.iter());
+
+        // Lets surfaces offer HDR color spaces (see SurfaceColorSpace). Not every platform has it, so it's only enabled if it's there
+        let colorspace_extension = vk::ExtSwapchainColorspaceFn::name();
+        let available_extensions = entry.enumerate_instance_extension_properties(None).unwrap_or_default();
+        if available_extensions.iter().any(|extension| unsafe { std::ffi::CStr::from_ptr(extension.extension_name.as_ptr()) } == colorspace_extension) {
+          extension_name_pointers.push(colorspace_extension.as_ptr());
+        }
+      }
 
       #[cfg(any(target_os = "macos", target_os = "ios"))]
       {
@@ -160,24 +424,11 @@ impl VulkanApp {
         extension_name_pointers.push(KhrGetPhysicalDeviceProperties2Fn::name().as_ptr()); // Required by VK_HKR_portability_subset
       }
 
-      println!("Extensions in use: ");
-      for ext in extension_name_pointers.iter() {
-          println!("\t{}", unsafe { std::ffi::CStr::from_ptr(*ext).to_str().unwrap() });
-      }
+      let extension_names: Vec<_> = extension_name_pointers.iter().map(|ext| unsafe { std::ffi::CStr::from_ptr(*ext).to_string_lossy() }).collect();
+      log::info!("Extensions in use: {}.", extension_names.join(", "));
 
-      // Setup debug messenger for validation layers
-      // TODO: Switch this to VulkanDebugInfo
-      let mut debugcreateinfo = vk::DebugUtilsMessengerCreateInfoEXT {
-          message_severity: vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
-              //| vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE
-              //| vk::DebugUtilsMessageSeverityFlagsEXT::INFO
-              | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
-          message_type: vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
-              | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE
-              | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
-          pfn_user_callback: Some(vulkan_debug_utils_callback),
-          ..Default::default()
-      };
+      // Setup debug messenger for validation layers (while the instance is being created and destroyed, VulkanDebugInfo takes over after)
+      let mut debugcreateinfo = VulkanDebugInfo::messenger_create_info(validation_errors);
 
       let create_flags = if cfg!(any(target_os = "macos", target_os = "ios")) {
         vk::InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR
@@ -193,57 +444,84 @@ impl VulkanApp {
           .enabled_extension_names(&extension_name_pointers)
           .flags(create_flags);
 
-      unsafe { (entry.create_instance(&create_info, None), debugcreateinfo) }
+      unsafe { entry.create_instance(&create_info, None).map_err(RendererError::Instance) }
   }
 
-  // Creates the desired number of command buffers
-  pub fn create_commandbuffers(logical_device: &ash::Device, pools: &Pools, amount: usize) -> Result<Vec<vk::CommandBuffer>, vk::Result> {
-      let command_buffer_allocate_info = vk::CommandBufferAllocateInfo::builder()
-          .command_pool(pools.graphics_command_pool)
-          .command_buffer_count(amount as u32);
-          //.level(vk::CommandBufferLevel::PRIMARY);
-
-      unsafe { logical_device.allocate_command_buffers(&command_buffer_allocate_info) }
+  // Draw a frame. When headless the rendered pixels are handed back (tightly packed RGBA8 rows, top row first),
+  // otherwise the frame is presented to the window and None is returned.
+  // An out of date swapchain is recreated here, so that error never reaches the caller.
+  // In strict mode this fails if the validation layer has reported an error
+  pub fn draw_frame(&mut self) -> Result<Option<Vec<u8>>, RendererError> {
+    let pixels = self.draw_frame_unchecked()?;
+    if self.config.strict_validation {
+      self.check_validation()?;
+    }
+    Ok(pixels)
   }
 
-  pub fn draw_frame(&mut self) {
-    self.swapchain.current_image = (self.swapchain.current_image + 1) % self.swapchain.amount_of_images as usize; // Acquire the next image in the swapchain
+  fn draw_frame_unchecked(&mut self) -> Result<Option<Vec<u8>>, RendererError> {
+    // Pick up shader changes before recording anything with the pipeline
+    self.reload_changed_shaders()?;
 
-    let (image_index, _is_sub_optimal) = unsafe {
-      let result = self.swapchain.swapchain_loader.acquire_next_image(
-        self.swapchain.swapchain, // The swapchain to acquire an image from
-        std::u64::MAX, // How long to wait for the image (nanoseconds)
-        self.swapchain.image_available[self.swapchain.current_image], // The semaphore to signal when the image is ready to be used
-        vk::Fence::null(), // A fence to signal when the image is acquired (must have either a semaphore or fence)
-      );
-      match result {
-        Ok(image_index) => image_index,
-        Err(vk_result) => match vk_result {
-            vk::Result::ERROR_OUT_OF_DATE_KHR => {
-                self.recreate_swapchain();
-                return;
-            }
-            _ => panic!("Failed to acquire Swap Chain Image!"),
-        },
+    // Wait until the GPU is done with the last submission of this frame in flight, so we can reuse its command buffer
+    self.frames.wait_for_current(&self.device)?;
+
+    // Make sure anything uploaded to device local memory has arrived before we draw with it
+    if self.uploader.has_pending() {
+      self.uploader.flush(&self.device, &mut self.allocator, &self.queues)?;
+    }
+
+    // The frame's previous submission is done, so its descriptor sets and camera can be replaced
+    let slot = self.frames.current_frame;
+    self.descriptors.reset_frame(&self.device, slot)?;
+    let frame_uniforms = &mut self.frame_uniforms[slot];
+    frame_uniforms.camera_buffer.update(&self.camera)?;
+    frame_uniforms.camera_set = self.descriptors.allocate_camera_set(
+      &self.device, slot, frame_uniforms.camera_buffer.get_buffer(), frame_uniforms.camera_buffer.get_size(),
+    )?;
+
+    if self.offscreen.is_some() {
+      return self.draw_frame_offscreen().map(Some);
+    }
+    if self.swapchain.is_none() { // The window was minimized, or recreating the swapchain failed. Try again, there's nothing to draw to until it works
+      self.recreate_swapchain()?;
+      return Ok(None);
+    }
+    let defaults = self.draw_defaults();
+    let swapchain = self.swapchain.as_mut().unwrap();
+    let frame = self.frames.current();
+
+    // Acquire the next image in the swapchain. A suboptimal swapchain can still be drawn to, it's recreated once this frame is submitted
+    // (image_available gets signalled either way, skipping the frame would leave it signalled with nothing waiting on it)
+    let (image_index, is_acquire_suboptimal) = match swapchain.acquire_next_image(frame.image_available) {
+      Ok(acquired) => acquired,
+      Err(RendererError::OutOfDate) => {
+        self.recreate_swapchain()?;
+        return Ok(None);
       }
+      Err(error) => return Err(error),
     };
 
-    unsafe {
-      // Wait for our fence to signal that we can render to the image
-      self.device.wait_for_fences(
-        &[self.swapchain.may_begin_drawing[self.swapchain.current_image]], // The fence to wait for
-        true, // If true wait for all fences, if false wait for at least one fence
-        std::u64::MAX, // How long to wait for the fences (nanoseconds)
-      ).expect("Fence wait failed!");
+    // The image may still be in use by another frame in flight (when there are more frames in flight than images, or images are acquired out of order)
+    let image_in_flight = swapchain.images_in_flight[image_index as usize];
+    if image_in_flight != vk::Fence::null() {
+      unsafe { self.device.wait_for_fences(&[image_in_flight], true, std::u64::MAX)? };
     }
+    swapchain.images_in_flight[image_index as usize] = frame.in_flight; // This frame now owns the image
+
+    // Record only the command buffer for this frame, targeting the image we acquired
+    VulkanApp::fill_commandbuffer(
+      frame.commandbuffer, &self.device, &self.renderpass, swapchain.framebuffers[image_index as usize], swapchain.extent,
+      self.msaa_samples, self.depth_format.is_some(), &self.pipelines, defaults, &self.renderables, &self.draws, None,
+    )?;
 
     // Begin rendering
 
     // Draw to the image
-    let semaphores_available = [self.swapchain.image_available[self.swapchain.current_image]];
+    let semaphores_available = [frame.image_available];
     let waiting_stages = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
-    let semaphores_finished = [self.swapchain.rendering_finished[self.swapchain.current_image]];
-    let commandbuffers = [self.commandbuffers[image_index as usize]];
+    let semaphores_finished = [swapchain.rendering_finished[image_index as usize]];
+    let commandbuffers = [frame.commandbuffer];
     let submit_info = [vk::SubmitInfo::builder()
       .wait_semaphores(&semaphores_available)
       .wait_dst_stage_mask(&waiting_stages)
@@ -252,226 +530,493 @@ impl VulkanApp {
       .build()];
 
     unsafe {
-      // Reset the fence to signal that we can begin drawing to the image
-      self.device.reset_fences(
-        &[self.swapchain.may_begin_drawing[self.swapchain.current_image]], // The fences to reset
-      ).expect("Fence reset failed!");
+      // Reset the fence so it can signal when this frame is done
+      self.device.reset_fences(&[frame.in_flight])?;
 
       self.device.queue_submit(
         self.queues.graphics_queue, 
         &submit_info, 
-        self.swapchain.may_begin_drawing[self.swapchain.current_image],
-      ).expect("Failed to submit command buffer!");
+        frame.in_flight,
+      )?;
     }
+    self.frames.advance();
 
     // Present the image
-    let swapchains = [self.swapchain.swapchain];
-    let indices = [image_index];
-    let present_info = vk::PresentInfoKHR::builder()
-      .wait_semaphores(&semaphores_finished)
-      .swapchains(&swapchains)
-      .image_indices(&indices);
-    
-    let result = unsafe { 
-      self.swapchain.swapchain_loader.queue_present(self.queues.graphics_queue, &present_info) // TODO: Use a present queue here
-    };
-
-    let is_resized = match result {
-      Ok(_) => self.is_framebuffer_resized,
-      Err(vk_result) => match vk_result {
-        vk::Result::ERROR_OUT_OF_DATE_KHR | vk::Result::SUBOPTIMAL_KHR => true,
-        _ => panic!("Failed to present swapchain image!"),
-      },
+    let is_outdated = match swapchain.present(self.queues.present_queue, image_index, &semaphores_finished) {
+      Ok(is_suboptimal) => is_suboptimal || is_acquire_suboptimal,
+      Err(RendererError::OutOfDate) => true,
+      Err(error) => return Err(error),
     };
 
-    if is_resized {
+    if is_outdated || self.is_framebuffer_resized {
       self.is_framebuffer_resized = false;
-      self.recreate_swapchain();
+      self.recreate_swapchain()?;
     }
+    Ok(None)
   }
 
-  // TODO: There may be a small memory leak here. I saw this because when the window is resized a bunch of times memory usage goes up slightly without dropping.
-  pub fn recreate_swapchain(&mut self) {
-    // Recreate the swapchain
+  // Render a frame into the offscreen target and read back the pixels. This waits for the GPU to finish the frame
+  fn draw_frame_offscreen(&mut self) -> Result<Vec<u8>, RendererError> {
+    let offscreen = self.offscreen.as_ref().unwrap();
+    let frame = self.frames.current();
+
+    VulkanApp::fill_commandbuffer(
+      frame.commandbuffer, &self.device, &self.renderpass, offscreen.framebuffer, offscreen.extent,
+      self.msaa_samples, self.depth_format.is_some(), &self.pipelines, self.draw_defaults(), &self.renderables, &self.draws, Some(offscreen),
+    )?;
+
+    let commandbuffers = [frame.commandbuffer];
+    let submit_info = [vk::SubmitInfo::builder()
+      .command_buffers(&commandbuffers) // No semaphores needed, there is no image to acquire or present
+      .build()];
+
     unsafe {
-      self.device
-          .device_wait_idle()
-          .expect("Failed to wait device idle (recreate swapchain)!")
+      self.device.reset_fences(&[frame.in_flight])?;
+
+      self.device.queue_submit(
+        self.queues.graphics_queue,
+        &submit_info,
+        frame.in_flight,
+      )?;
+
+      // Wait for the frame (and the copy into the readback buffer) to finish
+      self.device.wait_for_fences(&[frame.in_flight], true, std::u64::MAX)?;
+    }
+    self.frames.advance();
+
+    offscreen.read_pixels()
+  }
+
+  // TODO: There may be a small memory leak here. I saw this because when the window is resized a bunch of times memory usage goes up slightly without dropping.
+  pub fn recreate_swapchain(&mut self) -> Result<(), RendererError> {
+    let surface = match self.surface.as_ref() {
+      Some(surface) => surface,
+      None => return Ok(()), // Headless, the offscreen target never goes out of date
     };
 
-    unsafe {
-      // TODO: Track which buffer came from which pool
-      self.device.free_command_buffers(self.pools.graphics_command_pool, &self.commandbuffers);
+    // A minimized window has no size, and a swapchain can't be created for it. Keep the old one until the window comes back
+    let extent = surface.get_capabilities(self.physical_device)?.current_extent;
+    if extent.width == 0 || extent.height == 0 {
+      return Ok(());
+    }
 
-      self.pools.cleanup(&self.device); // Cleanup the command pool resources
-      self.pipeline.cleanup(&self.device); // Clean up the pipeline
-      //self.device.destroy_render_pass(self.renderpass, None); // Destroy the render pass
-      RenderPass::cleanup_renderpass(&self.device, self.renderpass);
-      self.swapchain.cleanup(&self.device); // Destroy the swapchain
+    // Recreate the swapchain
+    unsafe { self.device.device_wait_idle()? };
+
+    // The frames in flight (command buffers and sync objects) don't depend on the swapchain, so they're kept.
+    // The old swapchain is taken out first, so if anything below fails it isn't left behind to be destroyed again
+    let old_format = self.swapchain.as_ref().map(|swapchain| swapchain.surface_format.format);
+    if let Some(mut swapchain) = self.swapchain.take() {
+      unsafe { swapchain.cleanup(&self.device) }; // Destroy the swapchain
+    }
+    // Destroy the multisampled color and depth images, they're the size of the old swapchain
+    for mut target in self.msaa_target.take().into_iter().chain(self.depth_target.take()) {
+      target.destroy(&self.device, &mut self.allocator)?;
     }
 
     // Create the swapchain
-    self.swapchain = VulkanSwapchain::init(&self.instance, self.physical_device, &self.device, &self.surface, &self.queue_families, &self.queues).expect("Failed to recreate swapchain [swapchain recreation].");
+    let mut swapchain = VulkanSwapchain::init(
+      &self.instance, self.physical_device, &self.device, surface, &self.queue_families, &self.queues, &self.config.swapchain,
+    )?;
+    if let Err(error) = self.init_swapchain_targets(&mut swapchain, old_format) {
+      unsafe { swapchain.cleanup(&self.device) };
+      return Err(error);
+    }
+    self.swapchain = Some(swapchain);
 
-    // Create the render pass
-    self.renderpass = RenderPass::init_renderpass(&self.device, self.physical_device, self.swapchain.surface_format.format).expect("Failed to recreate renderpass [swapchain recreation].");
+    log::info!("Swapchain recreated.");
+    Ok(())
+  }
+
+  // Create what renders into a new swapchain: the multisampled color and depth images, the render pass and pipelines (only if the
+  // format changed, they don't depend on the size) and the framebuffers. The render pass and pipelines are only replaced once the new
+  // ones are built, so a failure leaves the old ones in place
+  fn init_swapchain_targets(&mut self, swapchain: &mut VulkanSwapchain, old_format: Option<vk::Format>) -> Result<(), RendererError> {
+    // Create the multisampled color image
+    if self.msaa_samples != vk::SampleCountFlags::TYPE_1 {
+      self.msaa_target = Some(AttachmentImage::new_msaa_color(&self.device, &mut self.allocator, swapchain.extent, swapchain.surface_format.format, self.msaa_samples)?);
+    }
+
+    // Create the depth image
+    if let Some(depth_format) = self.depth_format {
+      self.depth_target = Some(AttachmentImage::new_depth(&self.device, &mut self.allocator, swapchain.extent, depth_format, self.msaa_samples)?);
+    }
+
+    // The render pass (and so the pipelines) only depends on the formats, which rarely change. The pipelines' viewport and scissor are dynamic,
+    // so the new size doesn't matter to them
+    if old_format != Some(swapchain.surface_format.format) {
+      let renderpass = RenderPass::init_renderpass(
+        &self.device, self.physical_device, swapchain.surface_format.format, vk::ImageLayout::PRESENT_SRC_KHR, self.msaa_samples, self.depth_format,
+      )?;
+      let mut pipelines = Vec::with_capacity(self.pipelines.len());
+      for slot in &self.pipelines {
+        let pipeline = match slot {
+          Some(slot) => slot.builder.build(&self.device, renderpass, self.pipeline_cache.cache).map(Some),
+          None => Ok(None),
+        };
+        match pipeline {
+          Ok(pipeline) => pipelines.push(pipeline),
+          Err(error) => {
+            pipelines.iter().flatten().for_each(|pipeline: &Pipeline| pipeline.cleanup(&self.device));
+            RenderPass::cleanup_renderpass(&self.device, renderpass);
+            return Err(error);
+          }
+        }
+      }
+      for (slot, pipeline) in self.pipelines.iter_mut().zip(pipelines) {
+        if let (Some(slot), Some(pipeline)) = (slot, pipeline) {
+          slot.pipeline.cleanup(&self.device);
+          slot.pipeline = pipeline;
+        }
+      }
+      RenderPass::cleanup_renderpass(&self.device, self.renderpass);
+      self.renderpass = renderpass;
+      self.name_objects();
+    }
 
     // Create the framebuffers
-    self.swapchain.create_framebuffers(&self.device, self.renderpass).expect("Failed to recreate framebuffers [swapchain recreation].");
+    swapchain.create_framebuffers(
+      &self.device, self.renderpass, self.msaa_target.as_ref().map(|target| target.imageview), self.depth_target.as_ref().map(|target| target.imageview),
+    )
+  }
+
+  // Turn vsync on (present modes that wait for the vertical blank) or off (present as fast as possible, see SwapchainConfig).
+  // This replaces the configured present modes and recreates the swapchain. Does nothing when headless
+  pub fn set_vsync(&mut self, vsync: bool) -> Result<(), RendererError> {
+    self.config.swapchain.present_modes = SwapchainConfig::present_modes_for_vsync(vsync);
+    if self.swapchain.is_none() {
+      return Ok(());
+    }
+    self.recreate_swapchain()?;
+    if let Some(swapchain) = &self.swapchain {
+      VulkanApp::report_present_mode(&self.config.swapchain, swapchain);
+    }
+    Ok(())
+  }
 
-    // Create the pipeline
-    self.pipeline = Pipeline::init(&self.device, &self.swapchain, &self.renderpass).expect("Failed to recreate pipeline [swapchain recreation].");
+  fn report_present_mode(config: &SwapchainConfig, swapchain: &VulkanSwapchain) {
+    match config.present_modes.first() {
+      Some(&preferred) if preferred != swapchain.present_mode => {
+        log::warn!("Present mode {:?} is not supported, using {:?} instead.", preferred, swapchain.present_mode);
+      }
+      _ => {}
+    }
+    let color_space_available = match config.color_space {
+      SurfaceColorSpace::Srgb => true,
+      SurfaceColorSpace::Hdr10 => swapchain.surface_format.color_space == vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+      SurfaceColorSpace::ScRgb => swapchain.surface_format.color_space == vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT,
+    };
+    if !color_space_available {
+      log::warn!("The surface doesn't support the {:?} color space, using sRGB instead.", config.color_space);
+    }
+    log::info!(
+      "Presenting {:?} ({:?}) with {:?} and {} swapchain images.",
+      swapchain.surface_format.format, swapchain.surface_format.color_space, swapchain.present_mode, swapchain.amount_of_images,
+    );
+  }
+
+  // Rebuild the pipelines with a shader that changed on disk. If the new shaders don't load or compile the old pipeline is kept
+  // (and the error is reported), so a typo in a shader doesn't take the app down. Does nothing without a shader watcher
+  pub fn reload_changed_shaders(&mut self) -> Result<(), RendererError> {
+    let changed_files = match &mut self.shader_watcher {
+      Some(shader_watcher) => shader_watcher.changed_files(),
+      None => return Ok(()),
+    };
 
-    // Create the command pools
-    self.pools = Pools::init(&self.device, &self.queue_families).expect("Failed to recreate command pools [swapchain recreation].");
+    let mut rebuilt = vec![];
+    for (index, slot) in self.pipelines.iter().enumerate() {
+      let Some(slot) = slot else { continue };
+      if !changed_files.iter().any(|path| slot.builder.uses_shader_file(path)) {
+        continue;
+      }
+      let result = slot.builder.reload_shaders()
+        .and_then(|builder| Ok((builder.build(&self.device, self.renderpass, self.pipeline_cache.cache)?, builder)));
+      match result {
+        Ok((pipeline, builder)) => rebuilt.push((index, PipelineSlot { pipeline, builder })),
+        Err(error) => log::error!("{}. Keeping the old pipeline.", error),
+      }
+    }
+    if rebuilt.is_empty() {
+      return Ok(());
+    }
 
-    // Create the command buffers (one for each framebuffer)
-    self.commandbuffers = VulkanApp::create_commandbuffers(&self.device, &self.pools, self.swapchain.amount_of_images).expect("Failed to recreate commandbuffers [swapchain recreation].");
+    // Frames in flight may still be using the old pipelines
+    if let Err(error) = unsafe { self.device.device_wait_idle() } {
+      rebuilt.iter().for_each(|(_, slot)| slot.pipeline.cleanup(&self.device));
+      return Err(error.into());
+    }
+    for (index, slot) in rebuilt {
+      if let Some(old) = self.pipelines[index].replace(slot) {
+        old.pipeline.cleanup(&self.device);
+      }
+    }
+    self.name_objects();
+    log::info!("Shaders reloaded.");
+    Ok(())
+  }
 
-    // Fill the command buffers
-    VulkanApp::fill_commandbuffers(
-      &self.commandbuffers,
-      &self.device,
-      &self.renderpass,
-      &self.swapchain,
-      &self.pipeline,
-      &self.renderables,
-    ).expect("Failed to fill commandbuffers [swapchain recreation].");
+  // The builder of a pipeline drawn by this app: the description's settings, with our sample count, descriptor sets and push constants
+  pub fn describe_pipeline(
+    logical_device: &ash::Device, descriptors: &mut Descriptors, shader_directory: Option<&std::path::Path>, msaa_samples: vk::SampleCountFlags,
+    description: &PipelineDescription,
+  ) -> Result<PipelineBuilder, RendererError> {
+    Pipeline::description_builder(description, shader_directory)?
+      .samples(msaa_samples)
+      .descriptor_layout(
+        logical_device, &mut descriptors.layout_cache, &[&Descriptors::CAMERA_BINDINGS, &Descriptors::TEXTURE_BINDINGS], &[PushConstants::get_range()],
+      )
+  }
 
-    println!("Swapchain recreated!");
+  // What draws that don't bring their own texture or instances are drawn with
+  pub fn draw_defaults(&self) -> DrawDefaults {
+    DrawDefaults {
+      camera_set: self.frame_uniforms[self.frames.current_frame].camera_set,
+      texture_set: self.white_texture.descriptor_set,
+      instance_buffer: self.identity_instance.get_buffer(),
+    }
   }
 
-  // A method to actually perform our renderpass
-  pub fn fill_commandbuffers(
-    commandbuffers: &[vk::CommandBuffer], logical_device: &ash::Device, renderpass: &vk::RenderPass, swapchain: &VulkanSwapchain, 
-    pipeline: &Pipeline, renderables: &Vec<Renderable>,
-  ) -> Result<(), vk::Result> {
+  // A method to actually perform our renderpass, recording it into a single command buffer.
+  // The caller must make sure the GPU is done with the command buffer (i.e. wait on its frame's fence) first.
+  // When a readback target is given, the rendered image is also copied into its readback buffer after the renderpass
+  #[allow(clippy::too_many_arguments)]
+  pub fn fill_commandbuffer(
+    commandbuffer: vk::CommandBuffer, logical_device: &ash::Device, renderpass: &vk::RenderPass, framebuffer: vk::Framebuffer,
+    extent: vk::Extent2D, samples: vk::SampleCountFlags, has_depth: bool, pipelines: &[Option<PipelineSlot>], defaults: DrawDefaults,
+    renderables: &[Renderable], draws: &[DrawCall], readback: Option<&OffscreenTarget>,
+  ) -> Result<(), RendererError> {
+    // Look the pipelines up before recording, so a destroyed one doesn't leave the command buffer half recorded
+    let pipeline = VulkanApp::get_pipeline(pipelines, PipelineHandle(0))?; // Renderables are drawn with the default pipeline
+    let draw_pipelines = draws.iter().map(|draw| VulkanApp::get_pipeline(pipelines, draw.pipeline)).collect::<Result<Vec<&Pipeline>, _>>()?;
+
+    let commandbuffer_begininfo = vk::CommandBufferBeginInfo::builder() // Start recording a command buffer (this implicitly resets it)
+      .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT); // We re-record it every frame
     unsafe {
-      // Wait for our fence to signal that we can write to the command buffer
-      logical_device.wait_for_fences(
-        &[swapchain.may_begin_drawing[swapchain.current_image]], // The fence to wait for
-        true, // If true wait for all fences, if false wait for at least one fence
-        std::u64::MAX, // How long to wait for the fences (nanoseconds)
-      ).expect("Fence wait failed!");
+        logical_device.begin_command_buffer(commandbuffer, &commandbuffer_begininfo)?; // Begin the command buffer
     }
-    
-    for (i, &commandbuffer) in commandbuffers.iter().enumerate() {
-      let commandbuffer_begininfo = vk::CommandBufferBeginInfo::builder(); // Start recording a command buffer
-      unsafe {
-          logical_device.begin_command_buffer(commandbuffer, &commandbuffer_begininfo)?; // Begin the command buffer
-      }
 
-      // Clear color
-      let clear_values = [vk::ClearValue {
-          color: vk::ClearColorValue {
-              float32: [0.0, 0.0, 0.08, 1.0],
-          },
-      }];
-
-      // Setup a renderpass
-      let renderpass_begininfo = vk::RenderPassBeginInfo::builder()
-        .render_pass(*renderpass)
-        .framebuffer(swapchain.framebuffers[i])
-        .render_area(vk::Rect2D {
-            offset: vk::Offset2D { x: 0, y: 0 },
-            extent: swapchain.extent,
-        })
-        .clear_values(&clear_values);
+    // Clear color (and depth)
+    let clear_values = RenderPass::clear_values(CLEAR_COLOR, samples, has_depth);
 
-      unsafe {
-        // Start the renderpass
-        logical_device.cmd_begin_render_pass(
-            commandbuffer,
-            &renderpass_begininfo,
-            vk::SubpassContents::INLINE, // Commands for the first subpass are provided inline, not in a secondary command buffer
+    // Setup a renderpass
+    let renderpass_begininfo = vk::RenderPassBeginInfo::builder()
+      .render_pass(*renderpass)
+      .framebuffer(framebuffer)
+      .render_area(vk::Rect2D {
+          offset: vk::Offset2D { x: 0, y: 0 },
+          extent,
+      })
+      .clear_values(&clear_values);
+
+    unsafe {
+      // Start the renderpass
+      logical_device.cmd_begin_render_pass(
+          commandbuffer,
+          &renderpass_begininfo,
+          vk::SubpassContents::INLINE, // Commands for the first subpass are provided inline, not in a secondary command buffer
+      );
+
+      // Draw to the whole framebuffer. Pipelines take the viewport and scissor from the command buffer, so they work at any size
+      let full_scissor = vk::Rect2D { offset: vk::Offset2D { x: 0, y: 0 }, extent };
+      logical_device.cmd_set_viewport(commandbuffer, 0, &[vk::Viewport {
+        x: 0.0,
+        y: 0.0,
+        width: extent.width as f32,
+        height: extent.height as f32,
+        min_depth: 0.0,
+        max_depth: 1.0,
+      }]);
+      logical_device.cmd_set_scissor(commandbuffer, 0, &[full_scissor]);
+
+      for (_i, renderable) in renderables.iter().enumerate() {
+        // Choose (bind) our graphics pipeline
+        logical_device.cmd_bind_pipeline(
+          commandbuffer, 
+          vk::PipelineBindPoint::GRAPHICS, 
+          pipeline.pipeline,
         );
+        logical_device.cmd_bind_descriptor_sets(
+          commandbuffer,
+          vk::PipelineBindPoint::GRAPHICS,
+          pipeline.layout,
+          0, // The first set to bind
+          &[defaults.camera_set, defaults.texture_set], // Renderables have no texture of their own
+          &[], // No dynamic offsets
+        );
+        logical_device.cmd_push_constants(
+          commandbuffer, pipeline.layout, vk::ShaderStageFlags::VERTEX, 0, PushConstants::IDENTITY.as_bytes(), // Renderables have no push constants either
+        );
+        let (instance_buffer, instance_count) = match &renderable.instance_buffer {
+          Some(instance_buffer) => (instance_buffer.get_buffer(), instance_buffer.get_instance_count()),
+          None => (defaults.instance_buffer, 1),
+        };
+        match &renderable.index_buffer {
+          Some(index_buffer) => {
+            // Bind the index buffer (unlike vertex buffers, can only have 1 index buffer bound at a time)
+            logical_device.cmd_bind_index_buffer(
+                commandbuffer,
+                index_buffer.get_buffer(),
+                0,
+                vk::IndexType::UINT32, // Can also be UINT16
+            );
 
-        for (_i, renderable) in renderables.iter().enumerate() {
-          // Choose (bind) our graphics pipeline
-          logical_device.cmd_bind_pipeline(
-            commandbuffer, 
-            vk::PipelineBindPoint::GRAPHICS, 
-            pipeline.pipeline,
-          );
-          match &renderable.index_buffer {
-            Some(index_buffer) => {
-              // Bind the index buffer (unlike vertex buffers, can only have 1 index buffer bound at a time)
-              logical_device.cmd_bind_index_buffer(
+            // Draw the vertices
+            for vb in &renderable.vertex_buffers {
+              logical_device.cmd_bind_vertex_buffers(
                   commandbuffer,
-                  index_buffer.get_buffer(),
                   0,
-                  vk::IndexType::UINT32, // Can also be UINT16
-              );
-
-              // Draw the vertices
-              for vb in &renderable.vertex_buffers {
-                logical_device.cmd_bind_vertex_buffers(
-                    commandbuffer,
-                    0,
-                    &[vb.get_buffer()],
-                    &[0],
+                  &[vb.get_buffer(), instance_buffer], // Bindings 0 (vertices) and 1 (instances)
+                  &[0, 0],
+            );
+            logical_device.cmd_draw_indexed(
+              commandbuffer,
+              index_buffer.get_indice_count(), // Num verts to draw
+              instance_count, // How many copies to draw
+              0, // We start at the first index within the index buffer
+              0, // We start at the first vertex in the vertex buffer
+              0 // We start at the first instance in the instance buffer
+            );
+          }
+          },
+          None => {
+            // Draw the vertices
+            for vb in &renderable.vertex_buffers {
+              logical_device.cmd_bind_vertex_buffers(
+                commandbuffer,
+                0,
+                &[vb.get_buffer(), instance_buffer],
+                &[0, 0],
               );
-              logical_device.cmd_draw_indexed(
+              logical_device.cmd_draw(
                 commandbuffer,
-                index_buffer.get_indice_count(), // Num verts to draw
-                1, // Not using instanced drawing
-                0, // We start at the first index within the index buffer
-                0, // We start at the first vertex in the vertex buffer
-                0 // Not using instanced drawing so no offset here
+                vb.get_vert_count(),
+                instance_count,
+                0,
+                0,
               );
             }
-            },
-            None => {
-              // Draw the vertices
-              for vb in &renderable.vertex_buffers {
-                logical_device.cmd_bind_vertex_buffers(
-                  commandbuffer,
-                  0,
-                  &[vb.get_buffer()],
-                  &[0],
-                );
-                logical_device.cmd_draw(
-                  commandbuffer,
-                  vb.get_vert_count(),
-                  1,
-                  0,
-                  0,
-                );
-              }
-            }
           }
         }
+      }
 
-        // End the renderpass
-        logical_device.cmd_end_render_pass(commandbuffer);
-        // End the command buffer
-        logical_device.end_command_buffer(commandbuffer)?;
+      // Draws queued through the Renderer trait
+      let mut current_scissor = full_scissor;
+      for (draw, pipeline) in draws.iter().zip(draw_pipelines) {
+        // Clip to the draw's scissor rect, if it has one
+        let scissor = draw.scissor.unwrap_or(full_scissor);
+        if scissor != current_scissor {
+          logical_device.cmd_set_scissor(commandbuffer, 0, &[scissor]);
+          current_scissor = scissor;
+        }
+        logical_device.cmd_bind_pipeline(
+          commandbuffer,
+          vk::PipelineBindPoint::GRAPHICS,
+          pipeline.pipeline,
+        );
+        logical_device.cmd_bind_descriptor_sets(
+          commandbuffer, vk::PipelineBindPoint::GRAPHICS, pipeline.layout, 0, &[defaults.camera_set, draw.texture_set], &[],
+        );
+        logical_device.cmd_push_constants(commandbuffer, pipeline.layout, vk::ShaderStageFlags::VERTEX, 0, draw.push_constants.as_bytes());
+        logical_device.cmd_bind_vertex_buffers(commandbuffer, 0, &[draw.vertex_buffer, draw.instance_buffer], &[0, 0]);
+        match draw.index_buffer {
+          Some(index_buffer) => {
+            logical_device.cmd_bind_index_buffer(commandbuffer, index_buffer, 0, vk::IndexType::UINT32);
+            logical_device.cmd_draw_indexed(commandbuffer, draw.index_count, draw.instance_count, draw.first_index, 0, 0);
+          }
+          None => logical_device.cmd_draw(commandbuffer, draw.vertex_count, draw.instance_count, 0, 0),
+        }
+      }
+
+      // End the renderpass
+      logical_device.cmd_end_render_pass(commandbuffer);
+      // Copy the image out so the pixels can be read back (headless only)
+      if let Some(readback) = readback {
+        readback.cmd_copy_to_readback(logical_device, commandbuffer);
       }
+      // End the command buffer
+      logical_device.end_command_buffer(commandbuffer)?;
     }
     Ok(())
   }
 
   pub fn set_window_title(&self, title: &str) {
-    self.window.set_title(title);
+    if let Some(window) = &self.window {
+      window.set_title(title);
+    }
+  }
+
+  pub fn request_redraw(&self) {
+    if let Some(window) = &self.window {
+      window.request_redraw();
+    }
   }
 }
 
 impl Drop for VulkanApp {
   fn drop(&mut self) {
       unsafe {
-          self.device.device_wait_idle().expect("Failed to wait for device idle!"); // Wait for the device to be idle before cleaning up
+          // Wait for the device to be idle before cleaning up. We can't return errors from drop, so just report them and carry on
+          if let Err(error) = self.device.device_wait_idle() {
+            log::error!("Failed to wait for device idle: {}", error);
+          }
 
           for rb in &mut self.renderables {
-            rb.destroy(&self.device, &mut self.allocator);
+            if let Err(error) = rb.destroy(&self.device, &mut self.allocator) {
+              log::error!("Failed to destroy renderable: {}", error);
+            }
+          }
+          for buffer in self.buffers.iter_mut().flatten() {
+            let result = match buffer {
+              BufferSlot::Vertex(vertex_buffer) => vertex_buffer.destroy(&self.device, &mut self.allocator),
+              BufferSlot::Index(index_buffer) => index_buffer.destroy(&self.device, &mut self.allocator),
+              BufferSlot::Instance(instance_buffer) => instance_buffer.destroy(&self.device, &mut self.allocator),
+            };
+            if let Err(error) = result {
+              log::error!("Failed to destroy buffer: {}", error);
+            }
+          }
+          if let Err(error) = self.identity_instance.destroy(&self.device, &mut self.allocator) {
+            log::error!("Failed to destroy buffer: {}", error);
+          }
+          for frame_uniforms in &mut self.frame_uniforms { // Their descriptor sets go with the descriptor pool
+            if let Err(error) = frame_uniforms.camera_buffer.destroy(&self.device, &mut self.allocator) {
+              log::error!("Failed to destroy uniform buffer: {}", error);
+            }
+          }
+          for texture in self.textures.iter_mut().flatten().chain(std::iter::once(&mut self.white_texture)) {
+            if let Err(error) = texture.destroy(&self.device, &mut self.allocator, &mut self.descriptors) {
+              log::error!("Failed to destroy texture: {}", error);
+            }
           }
 
-          // TODO: Track which buffer came from which pool
-          self.device.free_command_buffers(self.pools.graphics_command_pool, &self.commandbuffers);
+          self.frames.cleanup(&self.device, &self.pools); // Free the command buffers and sync objects of the frames in flight
+          if let Err(error) = self.uploader.cleanup(&self.device, &mut self.allocator, &self.pools) {
+            log::error!("Failed to clean up uploader: {}", error);
+          }
 
           self.pools.cleanup(&self.device); // Cleanup the command pool resources
-          self.pipeline.cleanup(&self.device); // Clean up the pipeline
+          for slot in self.pipelines.iter().flatten() {
+            slot.pipeline.cleanup(&self.device); // Clean up the pipelines
+          }
+          if let Err(error) = self.pipeline_cache.save(&self.device) { // Keep the compiled pipelines for the next run
+            log::warn!("{}", error);
+          }
+          self.pipeline_cache.destroy(&self.device);
+          self.descriptors.cleanup(&self.device); // Destroy the descriptor pools and set layouts
           self.device.destroy_render_pass(self.renderpass, None); // Destroy the render pass
-          self.swapchain.cleanup(&self.device); // Destroy the swapchain
+          if let Some(swapchain) = &mut self.swapchain {
+            swapchain.cleanup(&self.device); // Destroy the swapchain
+          }
+          if let Some(offscreen) = &mut self.offscreen {
+            if let Err(error) = offscreen.destroy(&self.device, &mut self.allocator) { // Destroy the offscreen target
+              log::error!("Failed to destroy offscreen target: {}", error);
+            }
+          }
+          for target in self.msaa_target.iter_mut().chain(self.depth_target.iter_mut()) {
+            if let Err(error) = target.destroy(&self.device, &mut self.allocator) { // Destroy the multisampled color and depth images
+              log::error!("Failed to destroy attachment image: {}", error);
+            }
+          }
           std::mem::ManuallyDrop::drop(&mut self.allocator); // Explicitly drop before destruction of device and instance.
           self.device.destroy_device(None); // Destroy the logical device
           std::mem::ManuallyDrop::drop(&mut self.surface); // Destroy the surfaces
@@ -479,4 +1024,104 @@ impl Drop for VulkanApp {
           self.instance.destroy_instance(None) // Destroy the instance
       };
   }
+}
+
+// Owns what init_internal has created so far, so that if creating anything fails, everything before it is destroyed again
+// (in the same order VulkanApp's drop does it). Once the app is built everything has been taken out of it and it does nothing
+#[derive(Default)]
+struct InitGuard {
+  instance: Option<ash::Instance>,
+  debug: Option<VulkanDebugInfo>,
+  surface: Option<VulkanSurface>,
+  device: Option<ash::Device>,
+  allocator: Option<Allocator>,
+  swapchain: Option<VulkanSwapchain>,
+  offscreen: Option<OffscreenTarget>,
+  msaa_target: Option<AttachmentImage>,
+  depth_target: Option<AttachmentImage>,
+  renderpass: Option<vk::RenderPass>,
+  descriptors: Option<Descriptors>,
+  pipeline_cache: Option<PipelineCache>,
+  pipeline: Option<Pipeline>,
+  pools: Option<Pools>,
+  frames: Option<Frames>,
+  uploader: Option<Uploader>,
+  white_texture: Option<Texture>,
+  identity_instance: Option<InstanceBuffer>,
+  frame_uniforms: Vec<FrameUniforms>,
+}
+
+impl Drop for InitGuard {
+  fn drop(&mut self) {
+      unsafe {
+          if let Some(device) = &self.device {
+            if let Err(error) = device.device_wait_idle() {
+              log::error!("Failed to wait for device idle: {}", error);
+            }
+
+            if let Some(allocator) = &mut self.allocator {
+              if let Some(instance_buffer) = &mut self.identity_instance {
+                if let Err(error) = instance_buffer.destroy(device, allocator) {
+                  log::error!("Failed to destroy buffer: {}", error);
+                }
+              }
+              for frame_uniforms in &mut self.frame_uniforms {
+                if let Err(error) = frame_uniforms.camera_buffer.destroy(device, allocator) {
+                  log::error!("Failed to destroy uniform buffer: {}", error);
+                }
+              }
+              if let (Some(texture), Some(descriptors)) = (&mut self.white_texture, &mut self.descriptors) {
+                if let Err(error) = texture.destroy(device, allocator, descriptors) {
+                  log::error!("Failed to destroy texture: {}", error);
+                }
+              }
+              if let (Some(uploader), Some(pools)) = (&mut self.uploader, &self.pools) {
+                if let Err(error) = uploader.cleanup(device, allocator, pools) {
+                  log::error!("Failed to clean up uploader: {}", error);
+                }
+              }
+            }
+            if let (Some(frames), Some(pools)) = (&self.frames, &self.pools) {
+              frames.cleanup(device, pools);
+            }
+            if let Some(pools) = &self.pools {
+              pools.cleanup(device);
+            }
+            if let Some(pipeline) = &self.pipeline {
+              pipeline.cleanup(device);
+            }
+            if let Some(pipeline_cache) = &self.pipeline_cache { // Nothing worth saving was compiled yet
+              pipeline_cache.destroy(device);
+            }
+            if let Some(descriptors) = &mut self.descriptors {
+              descriptors.cleanup(device);
+            }
+            if let Some(renderpass) = self.renderpass {
+              device.destroy_render_pass(renderpass, None);
+            }
+            if let Some(swapchain) = &mut self.swapchain {
+              swapchain.cleanup(device);
+            }
+            if let Some(allocator) = &mut self.allocator {
+              if let Some(offscreen) = &mut self.offscreen {
+                if let Err(error) = offscreen.destroy(device, allocator) {
+                  log::error!("Failed to destroy offscreen target: {}", error);
+                }
+              }
+              for target in self.msaa_target.iter_mut().chain(self.depth_target.iter_mut()) {
+                if let Err(error) = target.destroy(device, allocator) {
+                  log::error!("Failed to destroy attachment image: {}", error);
+                }
+              }
+            }
+            self.allocator = None; // Before the device is destroyed
+            device.destroy_device(None);
+          }
+          self.surface = None;
+          self.debug = None;
+          if let Some(instance) = &self.instance {
+            instance.destroy_instance(None);
+          }
+      }
+  }
 }
\ No newline at end of file
@@ -0,0 +1,110 @@
+use ash::vk;
+use gpu_allocator::vulkan::*;
+use gpu_allocator::MemoryLocation;
+
+use super::error::*;
+
+// An image only used as a render pass attachment, such as the multisampled color image that gets resolved into the swapchain image.
+// It's the size of whatever we render to, so it has to be recreated along with the swapchain
+pub struct AttachmentImage {
+  pub image: vk::Image,
+  pub allocation: Allocation,
+  pub imageview: vk::ImageView,
+  pub format: vk::Format,
+  pub samples: vk::SampleCountFlags,
+}
+
+impl AttachmentImage {
+  #[allow(clippy::too_many_arguments)]
+  pub fn new(
+    logical_device: &ash::Device, allocator: &mut Allocator, extent: vk::Extent2D, format: vk::Format, samples: vk::SampleCountFlags,
+    usage: vk::ImageUsageFlags, aspect_mask: vk::ImageAspectFlags, name: &str,
+  ) -> Result<AttachmentImage, RendererError> {
+    let image_create_info = vk::ImageCreateInfo::builder()
+      .image_type(vk::ImageType::TYPE_2D)
+      .format(format)
+      .extent(vk::Extent3D { width: extent.width, height: extent.height, depth: 1 })
+      .mip_levels(1)
+      .array_layers(1)
+      .samples(samples)
+      .tiling(vk::ImageTiling::OPTIMAL)
+      .usage(usage)
+      .sharing_mode(vk::SharingMode::EXCLUSIVE)
+      .initial_layout(vk::ImageLayout::UNDEFINED);
+    let image = unsafe { logical_device.create_image(&image_create_info, None)? };
+
+    let allocation = allocator.allocate(&AllocationCreateDesc {
+      requirements: unsafe { logical_device.get_image_memory_requirements(image) },
+      location: MemoryLocation::GpuOnly,
+      linear: false, // Optimal tiling images are not linear
+      name,
+    })?;
+    unsafe { logical_device.bind_image_memory(image, allocation.memory(), allocation.offset())? };
+
+    let subresource_range = vk::ImageSubresourceRange::builder()
+      .aspect_mask(aspect_mask)
+      .base_mip_level(0)
+      .level_count(1)
+      .base_array_layer(0)
+      .layer_count(1);
+    let imageview_create_info = vk::ImageViewCreateInfo::builder()
+      .image(image)
+      .view_type(vk::ImageViewType::TYPE_2D)
+      .format(format)
+      .subresource_range(*subresource_range);
+    let imageview = unsafe { logical_device.create_image_view(&imageview_create_info, None)? };
+
+    Ok(AttachmentImage {
+      image,
+      allocation,
+      imageview,
+      format,
+      samples,
+    })
+  }
+
+  // A multisampled color image to render into, resolved into the single sampled target at the end of the render pass.
+  // Its contents are never needed afterwards, so it's transient (the driver may never actually write it to memory)
+  pub fn new_msaa_color(
+    logical_device: &ash::Device, allocator: &mut Allocator, extent: vk::Extent2D, format: vk::Format, samples: vk::SampleCountFlags,
+  ) -> Result<AttachmentImage, RendererError> {
+    AttachmentImage::new(
+      logical_device, allocator, extent, format, samples,
+      vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+      vk::ImageAspectFlags::COLOR,
+      "MSAA Color Image",
+    )
+  }
+
+  // A depth (and maybe stencil) image, recreated with the color target. Like the multisampled color image it's only needed during
+  // the render pass, so it's transient too
+  pub fn new_depth(
+    logical_device: &ash::Device, allocator: &mut Allocator, extent: vk::Extent2D, format: vk::Format, samples: vk::SampleCountFlags,
+  ) -> Result<AttachmentImage, RendererError> {
+    // A view used as a depth/stencil attachment has to include every aspect of the format
+    let mut aspect_mask = vk::ImageAspectFlags::DEPTH;
+    if AttachmentImage::has_stencil(format) {
+      aspect_mask |= vk::ImageAspectFlags::STENCIL;
+    }
+    AttachmentImage::new(
+      logical_device, allocator, extent, format, samples,
+      vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+      aspect_mask,
+      "Depth Image",
+    )
+  }
+
+  // Whether a depth format also has a stencil aspect
+  pub fn has_stencil(format: vk::Format) -> bool {
+    matches!(format, vk::Format::D16_UNORM_S8_UINT | vk::Format::D24_UNORM_S8_UINT | vk::Format::D32_SFLOAT_S8_UINT | vk::Format::S8_UINT)
+  }
+
+  pub fn destroy(&mut self, logical_device: &ash::Device, allocator: &mut Allocator) -> Result<(), RendererError> {
+    unsafe {
+      logical_device.destroy_image_view(self.imageview, None);
+      logical_device.destroy_image(self.image, None);
+    }
+    allocator.free(std::mem::take(&mut self.allocation))?;
+    Ok(())
+  }
+}
\ No newline at end of file
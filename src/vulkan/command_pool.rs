@@ -1,5 +1,6 @@
 use ash::vk;
 use super::queue::*;
+use super::error::*;
 
 // Command Pools are used to allocate command buffers and are associated with a QueueFamily
 // We batch commands into a command buffer and then submit them to the queue
@@ -10,15 +11,14 @@ pub struct Pools {
 
 impl Pools {
   // Create the command pools
-  pub fn init(logical_device: &ash::Device, queue_families: &QueueFamilies) -> Result<Pools, vk::Result> {
+  pub fn init(logical_device: &ash::Device, queue_families: &QueueFamilies) -> Result<Pools, RendererError> {
     // Create the graphics command pool
     let graphics_command_pool_info = vk::CommandPoolCreateInfo::builder()
         .queue_family_index(queue_families.graphics.unwrap())
         .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER);
     let graphics_command_pool = unsafe {
         logical_device
-            .create_command_pool(&graphics_command_pool_info, None)
-            .expect("A problem with the command pool creation")
+            .create_command_pool(&graphics_command_pool_info, None)?
     };
 
     // Create the transfer command pool
@@ -27,8 +27,7 @@ impl Pools {
         .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER);
     let transfer_command_pool = unsafe {
         logical_device
-            .create_command_pool(&transfer_command_pool_info, None)
-            .expect("A problem with the command pool creation")
+            .create_command_pool(&transfer_command_pool_info, None)?
     };
 
     Ok(Pools {
@@ -0,0 +1,168 @@
+use std::path::PathBuf;
+
+use ash::vk;
+
+// Settings chosen when the renderer is created. Anything the device doesn't support falls back to the closest thing it does
+#[derive(Clone, Debug)]
+pub struct RendererConfig {
+  pub msaa_samples: u32, // Samples per pixel for multisample anti-aliasing, 1 disables it. Clamped to what the device supports
+  pub depth_buffer: bool, // Whether to render with a depth attachment, so draws can be depth tested regardless of submission order
+  pub stencil_buffer: bool, // Whether the depth attachment should have a stencil aspect too (ignored without a depth buffer)
+  // Where to load the shaders from at runtime, and watch for changes so pipelines are rebuilt as soon as a shader is saved.
+  // None uses the shaders compiled into the binary
+  pub shader_directory: Option<PathBuf>,
+  // Where the pipeline cache is kept between runs, so pipelines don't have to be compiled from scratch every time. None disables it
+  pub pipeline_cache_directory: Option<PathBuf>,
+  // Which GPU to use, None picks the highest rated suitable one. The VULKAN_RENDERER_DEVICE environment variable overrides this
+  pub device: Option<DeviceSelection>,
+  pub swapchain: SwapchainConfig, // Ignored when headless
+  // Fail draw_frame with RendererError::Validation once the validation layer has reported an error, instead of only logging it.
+  // For tests, so they fail on validation errors
+  pub strict_validation: bool,
+}
+
+impl Default for RendererConfig {
+  fn default() -> Self {
+    RendererConfig {
+      msaa_samples: 4, // Supported by practically everything, and smooths 2D edges nicely
+      depth_buffer: true,
+      stencil_buffer: false,
+      shader_directory: None, // Where shaders live depends on the application, it has to opt in
+      pipeline_cache_directory: None, // Writing files is up to the application
+      device: None,
+      swapchain: SwapchainConfig::default(),
+      strict_validation: false,
+    }
+  }
+}
+
+// How to choose a physical device instead of taking the highest rated one. Only suitable devices are ever picked
+#[derive(Clone, Debug, PartialEq)]
+pub enum DeviceSelection {
+  Index(usize), // The device's index in the order the driver enumerates them (as logged by PhysicalDevice::list_physical_devices)
+  Name(String), // The highest rated device with this in its name (ignoring case), e.g. "llvmpipe" for lavapipe
+  Type(vk::PhysicalDeviceType), // The highest rated device of this type, e.g. INTEGRATED_GPU to save power on a laptop
+}
+
+impl DeviceSelection {
+  // The environment variable to pick a device with, e.g. VULKAN_RENDERER_DEVICE=integrated
+  pub const ENVIRONMENT_VARIABLE: &'static str = "VULKAN_RENDERER_DEVICE";
+
+  // A number is an index, discrete, integrated, virtual, cpu or other is a device type, anything else is part of a name
+  pub fn parse(text: &str) -> DeviceSelection {
+    let text = text.trim();
+    if let Ok(index) = text.parse() {
+      return DeviceSelection::Index(index);
+    }
+    match text.to_lowercase().as_str() {
+      "discrete" => DeviceSelection::Type(vk::PhysicalDeviceType::DISCRETE_GPU),
+      "integrated" => DeviceSelection::Type(vk::PhysicalDeviceType::INTEGRATED_GPU),
+      "virtual" => DeviceSelection::Type(vk::PhysicalDeviceType::VIRTUAL_GPU),
+      "cpu" => DeviceSelection::Type(vk::PhysicalDeviceType::CPU),
+      "other" => DeviceSelection::Type(vk::PhysicalDeviceType::OTHER),
+      _ => DeviceSelection::Name(text.to_string()),
+    }
+  }
+
+  // The selection from the environment variable, if it's set (and not empty)
+  pub fn from_env() -> Option<DeviceSelection> {
+    std::env::var(DeviceSelection::ENVIRONMENT_VARIABLE).ok()
+      .filter(|text| !text.trim().is_empty())
+      .map(|text| DeviceSelection::parse(&text))
+  }
+}
+
+impl std::fmt::Display for DeviceSelection {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      DeviceSelection::Index(index) => write!(f, "device {}", index),
+      DeviceSelection::Name(name) => write!(f, "a device named \"{}\"", name),
+      DeviceSelection::Type(device_type) => write!(f, "a {:?} device", device_type),
+    }
+  }
+}
+
+// The color space of the swapchain images, which decides how the colors the shaders output are shown.
+// Shaders always work with linear colors, the swapchain format takes care of encoding them (except for Hdr10)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SurfaceColorSpace {
+  Srgb, // 8 bits per channel, sRGB encoded when written. What every display can show
+  Hdr10, // 10 bits per channel, BT.2020 primaries with the PQ (ST 2084) transfer function. Shaders have to output PQ encoded colors
+  ScRgb, // 16 bit floats, linear with sRGB primaries. 1.0 is SDR white (80 nits), brighter colors go above it
+}
+
+// How images are presented to the window
+#[derive(Clone, Debug)]
+pub struct SwapchainConfig {
+  // The present modes to use, most preferred first. The first one the surface supports is used, FIFO (which every surface supports) if none are
+  pub present_modes: Vec<vk::PresentModeKHR>,
+  // How many images to ask for. More images let the CPU get further ahead (smoother, but more latency).
+  // Clamped to what the surface supports
+  pub image_count: u32,
+  // The color space to present in. Falls back to Srgb if the surface doesn't offer it
+  pub color_space: SurfaceColorSpace,
+}
+
+impl SwapchainConfig {
+  // Wait for the vertical blank, so there's no tearing and the frame rate is capped to the refresh rate
+  pub const VSYNC_PRESENT_MODES: [vk::PresentModeKHR; 1] = [vk::PresentModeKHR::FIFO];
+  // Present as soon as possible: MAILBOX replaces the queued image without tearing, IMMEDIATE may tear.
+  // Uncapped, so this is what to benchmark with
+  pub const NO_VSYNC_PRESENT_MODES: [vk::PresentModeKHR; 3] = [vk::PresentModeKHR::MAILBOX, vk::PresentModeKHR::IMMEDIATE, vk::PresentModeKHR::FIFO];
+
+  pub fn present_modes_for_vsync(vsync: bool) -> Vec<vk::PresentModeKHR> {
+    match vsync {
+      true => SwapchainConfig::VSYNC_PRESENT_MODES.to_vec(),
+      false => SwapchainConfig::NO_VSYNC_PRESENT_MODES.to_vec(),
+    }
+  }
+}
+
+impl Default for SwapchainConfig {
+  fn default() -> Self {
+    SwapchainConfig {
+      present_modes: SwapchainConfig::VSYNC_PRESENT_MODES.to_vec(),
+      image_count: 3, // Triple buffering
+      color_space: SurfaceColorSpace::Srgb,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse_index() {
+    assert_eq!(DeviceSelection::parse("0"), DeviceSelection::Index(0));
+    assert_eq!(DeviceSelection::parse(" 2\n"), DeviceSelection::Index(2));
+  }
+
+  #[test]
+  fn parse_type() {
+    // Trimmed and ignoring case
+    assert_eq!(DeviceSelection::parse(" Integrated "), DeviceSelection::Type(vk::PhysicalDeviceType::INTEGRATED_GPU));
+    assert_eq!(DeviceSelection::parse("DISCRETE"), DeviceSelection::Type(vk::PhysicalDeviceType::DISCRETE_GPU));
+    assert_eq!(DeviceSelection::parse("cpu"), DeviceSelection::Type(vk::PhysicalDeviceType::CPU));
+  }
+
+  #[test]
+  fn parse_name() {
+    assert_eq!(DeviceSelection::parse("llvmpipe"), DeviceSelection::Name("llvmpipe".to_string()));
+    assert_eq!(DeviceSelection::parse(" GeForce RTX "), DeviceSelection::Name("GeForce RTX".to_string()));
+    assert_eq!(DeviceSelection::parse("-1"), DeviceSelection::Name("-1".to_string())); // Not a valid index
+  }
+
+  #[test]
+  fn from_env() {
+    // The only test touching the variable, tests run in parallel in one process
+    std::env::set_var(DeviceSelection::ENVIRONMENT_VARIABLE, "");
+    assert_eq!(DeviceSelection::from_env(), None);
+    std::env::set_var(DeviceSelection::ENVIRONMENT_VARIABLE, "  ");
+    assert_eq!(DeviceSelection::from_env(), None);
+    std::env::set_var(DeviceSelection::ENVIRONMENT_VARIABLE, "1");
+    assert_eq!(DeviceSelection::from_env(), Some(DeviceSelection::Index(1)));
+    std::env::remove_var(DeviceSelection::ENVIRONMENT_VARIABLE);
+    assert_eq!(DeviceSelection::from_env(), None);
+  }
+}
\ No newline at end of file
@@ -1,33 +1,57 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
 use ash::vk;
+use super::error::*;
 
 // Stores the things needed for debugging with Vulkan Validation layers
 pub struct VulkanDebugInfo {
   pub loader: ash::extensions::ext::DebugUtils,
   pub messenger: vk::DebugUtilsMessengerEXT,
+  pub error_count: Arc<AtomicUsize>, // Counted by the callback, which has a pointer to it, so it has to live as long as the messenger
 }
 
 impl VulkanDebugInfo {
-  pub fn init(entry: &ash::Entry, instance: &ash::Instance) -> Result<VulkanDebugInfo, vk::Result> {
-      // Set the desired debug info
-      let debugcreateinfo = vk::DebugUtilsMessengerCreateInfoEXT::builder()
-          .message_severity(
-              vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
-                  //| vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE
-                  //| vk::DebugUtilsMessageSeverityFlagsEXT::INFO
-                  | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
-          )
+  pub fn init(entry: &ash::Entry, instance: &ash::Instance, error_count: &Arc<AtomicUsize>) -> Result<VulkanDebugInfo, RendererError> {
+      let debugcreateinfo = VulkanDebugInfo::messenger_create_info(error_count);
+      let loader = ash::extensions::ext::DebugUtils::new(entry, instance); // Create the debug loader
+      let messenger = unsafe { loader.create_debug_utils_messenger(&debugcreateinfo, None).map_err(RendererError::Instance)? }; // Create the debug messenger
+
+      Ok(VulkanDebugInfo { loader, messenger, error_count: error_count.clone() })
+  }
+
+  // The messenger settings, shared by the messenger created here and the one chained onto the instance create info (which
+  // reports problems with creating and destroying the instance itself). Validation errors are added to error_count, which must
+  // outlive the messenger. Messages below what the logger lets through aren't even asked for, as the layer has to produce them
+  pub fn messenger_create_info(error_count: &Arc<AtomicUsize>) -> vk::DebugUtilsMessengerCreateInfoEXT {
+      let mut message_severity = vk::DebugUtilsMessageSeverityFlagsEXT::WARNING | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR;
+      if log::max_level() >= log::LevelFilter::Debug {
+          message_severity |= vk::DebugUtilsMessageSeverityFlagsEXT::INFO;
+      }
+      if log::max_level() >= log::LevelFilter::Trace {
+          message_severity |= vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE;
+      }
+      vk::DebugUtilsMessengerCreateInfoEXT::builder()
+          .message_severity(message_severity)
           .message_type(
               vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
                   | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE
                   | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
           )
-          .pfn_user_callback(Some(vulkan_debug_utils_callback));
-
-      let loader = ash::extensions::ext::DebugUtils::new(entry, instance); // Create the debug loader
-      let messenger = unsafe { loader.create_debug_utils_messenger(&debugcreateinfo, None)? }; // Create the debug messenger
+          .pfn_user_callback(Some(vulkan_debug_utils_callback))
+          .user_data(Arc::as_ptr(error_count) as *mut std::ffi::c_void)
+          .build()
+  }
 
-      Ok(VulkanDebugInfo { loader, messenger })
+  // Give an object a name, which validation messages about it will include
+  pub fn set_object_name<T: vk::Handle>(&self, logical_device: &ash::Device, object: T, name: &str) -> Result<(), RendererError> {
+      let name = std::ffi::CString::new(name).unwrap_or_default();
+      let name_info = vk::DebugUtilsObjectNameInfoEXT::builder()
+          .object_type(T::TYPE)
+          .object_handle(object.as_raw())
+          .object_name(&name);
+      unsafe { self.loader.debug_utils_set_object_name(logical_device.handle(), &name_info)? };
+      Ok(())
   }
 }
 
@@ -39,16 +63,48 @@ impl Drop for VulkanDebugInfo {
   }
 }
 
-// Used for printing Vulkan debug layer messages
+// Used for logging Vulkan debug layer messages, at the log level matching their severity (verbose messages are traced).
+// Includes the message ID and the objects the message is about, with their names if they were given any.
+// Errors are counted in the AtomicUsize the user data points to
 pub unsafe extern "system" fn vulkan_debug_utils_callback(
   message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
   message_type: vk::DebugUtilsMessageTypeFlagsEXT,
   p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
-  _p_user_data: *mut std::ffi::c_void,
+  p_user_data: *mut std::ffi::c_void,
 ) -> vk::Bool32 {
-  let message = std::ffi::CStr::from_ptr((*p_callback_data).p_message);
-  let severity = format!("{:?}", message_severity).to_lowercase();
+  let level = match message_severity {
+    vk::DebugUtilsMessageSeverityFlagsEXT::ERROR => log::Level::Error,
+    vk::DebugUtilsMessageSeverityFlagsEXT::WARNING => log::Level::Warn,
+    vk::DebugUtilsMessageSeverityFlagsEXT::INFO => log::Level::Debug, // Mostly the loader talking about itself
+    _ => log::Level::Trace,
+  };
+  if level == log::Level::Error && !p_user_data.is_null() {
+    (*(p_user_data as *const AtomicUsize)).fetch_add(1, Ordering::Relaxed);
+  }
+  if !log::log_enabled!(level) {
+    return vk::FALSE;
+  }
+
+  let data = &*p_callback_data;
+  let c_str = |pointer: *const std::os::raw::c_char| match pointer.is_null() {
+    true => None,
+    false => Some(std::ffi::CStr::from_ptr(pointer).to_string_lossy()),
+  };
   let ty = format!("{:?}", message_type).to_lowercase();
-  println!("[Vulkan][{}][{}] {:?}", severity, ty, message);
+  let id_name = c_str(data.p_message_id_name).unwrap_or_default();
+  let message = c_str(data.p_message).unwrap_or_default();
+  let objects: Vec<String> = match data.p_objects.is_null() {
+    true => vec![],
+    false => std::slice::from_raw_parts(data.p_objects, data.object_count as usize).iter()
+      .map(|object| match c_str(object.p_object_name) {
+        Some(name) => format!("{:?} {:#x} \"{}\"", object.object_type, object.object_handle, name),
+        None => format!("{:?} {:#x}", object.object_type, object.object_handle),
+      })
+      .collect(),
+  };
+  match objects.is_empty() {
+    true => log::log!(level, "[Vulkan][{}] {} ({:#x}): {}", ty, id_name, data.message_id_number, message),
+    false => log::log!(level, "[Vulkan][{}] {} ({:#x}): {} Objects: {}", ty, id_name, data.message_id_number, message, objects.join(", ")),
+  }
   vk::FALSE
 }
\ No newline at end of file
@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+
+use ash::vk;
+
+use super::error::*;
+
+// How many of each descriptor type a pool holds, per set it can hold. A set rarely uses more than one or two of a type
+const POOL_SIZE_RATIOS: [(vk::DescriptorType, f32); 4] = [
+  (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 1.0),
+  (vk::DescriptorType::UNIFORM_BUFFER, 1.0),
+  (vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC, 0.5),
+  (vk::DescriptorType::STORAGE_BUFFER, 0.5),
+];
+const INITIAL_SETS_PER_POOL: u32 = 64;
+const MAX_SETS_PER_POOL: u32 = 4096;
+
+// Hands out descriptor sets from a list of pools, creating a new (bigger) pool whenever the current one is exhausted,
+// so there's no fixed limit on how many sets can be allocated.
+// A freeable allocator lets sets be freed one by one (for long lived sets, like a texture's). Otherwise sets can only be
+// freed all at once with reset, which is much cheaper (for sets only used by one frame)
+pub struct DescriptorAllocator {
+  freeable: bool,
+  pools: Vec<vk::DescriptorPool>,
+  current_pool: usize, // The pool we allocate from, the ones before it are full
+  sets_per_pool: u32, // The size of the next pool
+  set_pools: HashMap<vk::DescriptorSet, vk::DescriptorPool>, // Which pool each set came from, so it can be freed (only if freeable)
+}
+
+impl DescriptorAllocator {
+  pub fn new(freeable: bool) -> DescriptorAllocator {
+    DescriptorAllocator {
+      freeable,
+      pools: vec![],
+      current_pool: 0,
+      sets_per_pool: INITIAL_SETS_PER_POOL,
+      set_pools: HashMap::new(),
+    }
+  }
+
+  // Create the next pool, bigger than the last one
+  fn create_pool(&mut self, logical_device: &ash::Device) -> Result<vk::DescriptorPool, RendererError> {
+    let pool_sizes: Vec<vk::DescriptorPoolSize> = POOL_SIZE_RATIOS.iter()
+      .map(|&(ty, ratio)| vk::DescriptorPoolSize { ty, descriptor_count: ((self.sets_per_pool as f32 * ratio) as u32).max(1) })
+      .collect();
+    let flags = match self.freeable {
+      true => vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET,
+      false => vk::DescriptorPoolCreateFlags::empty(),
+    };
+    let pool_info = vk::DescriptorPoolCreateInfo::builder()
+      .flags(flags)
+      .max_sets(self.sets_per_pool)
+      .pool_sizes(&pool_sizes);
+    let pool = unsafe { logical_device.create_descriptor_pool(&pool_info, None)? };
+    self.pools.push(pool);
+    self.sets_per_pool = (self.sets_per_pool * 2).min(MAX_SETS_PER_POOL); // Needing another pool means we'll likely need even more
+    Ok(pool)
+  }
+
+  // Allocate a set with the given layout
+  pub fn allocate(&mut self, logical_device: &ash::Device, layout: vk::DescriptorSetLayout) -> Result<vk::DescriptorSet, RendererError> {
+    loop {
+      let (pool, is_new_pool) = match self.pools.get(self.current_pool) {
+        Some(&pool) => (pool, false),
+        None => (self.create_pool(logical_device)?, true),
+      };
+      match DescriptorAllocator::allocate_from(logical_device, pool, layout) {
+        Ok(descriptor_set) => {
+          if self.freeable {
+            self.set_pools.insert(descriptor_set, pool);
+          }
+          return Ok(descriptor_set);
+        }
+        // The pool is full (or too fragmented), move on to the next one. Pools we moved past may get room again as sets are freed,
+        // but we don't go back to them until a reset, it's not worth searching them on every allocation.
+        // If even a new pool is too small for the set, another one won't help
+        Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY) | Err(vk::Result::ERROR_FRAGMENTED_POOL) if !is_new_pool => self.current_pool += 1,
+        Err(error) => return Err(error.into()),
+      }
+    }
+  }
+
+  fn allocate_from(logical_device: &ash::Device, pool: vk::DescriptorPool, layout: vk::DescriptorSetLayout) -> Result<vk::DescriptorSet, vk::Result> {
+    let layouts = [layout];
+    let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+      .descriptor_pool(pool)
+      .set_layouts(&layouts);
+    Ok(unsafe { logical_device.allocate_descriptor_sets(&allocate_info)? }[0])
+  }
+
+  // Free a single set (only for freeable allocators). The set must no longer be in use by the GPU
+  pub fn free(&mut self, logical_device: &ash::Device, descriptor_set: vk::DescriptorSet) -> Result<(), RendererError> {
+    debug_assert!(self.freeable, "sets from this allocator can only be freed with reset");
+    let pool = self.set_pools.remove(&descriptor_set).ok_or(RendererError::InvalidHandle)?;
+    unsafe { logical_device.free_descriptor_sets(pool, &[descriptor_set])? };
+    Ok(())
+  }
+
+  // Free every set at once, keeping the pools for the next allocations. None of the sets may be in use by the GPU anymore
+  pub fn reset(&mut self, logical_device: &ash::Device) -> Result<(), RendererError> {
+    for &pool in &self.pools {
+      unsafe { logical_device.reset_descriptor_pool(pool, vk::DescriptorPoolResetFlags::empty())? };
+    }
+    self.set_pools.clear();
+    self.current_pool = 0; // Every pool is empty again
+    Ok(())
+  }
+
+  // Destroy every pool, which also frees every set allocated from them
+  pub fn cleanup(&mut self, logical_device: &ash::Device) {
+    for pool in self.pools.drain(..) {
+      unsafe { logical_device.destroy_descriptor_pool(pool, None) };
+    }
+    self.current_pool = 0;
+    self.set_pools.clear();
+  }
+}
\ No newline at end of file
@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+
+use ash::vk;
+
+use super::error::*;
+
+// One binding of a descriptor set layout. Unlike vk::DescriptorSetLayoutBinding it holds no pointers, so it can be hashed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DescriptorBinding {
+  pub binding: u32, // layout(binding=...) in the shader
+  pub descriptor_type: vk::DescriptorType,
+  pub count: u32, // Array size, 1 for a single resource
+  pub stages: vk::ShaderStageFlags, // The shader stages that use it
+}
+
+impl DescriptorBinding {
+  pub const fn new(binding: u32, descriptor_type: vk::DescriptorType, stages: vk::ShaderStageFlags) -> DescriptorBinding {
+    DescriptorBinding { binding, descriptor_type, count: 1, stages }
+  }
+}
+
+// Creates each distinct descriptor set layout once. Pipelines and sets asking for the same bindings get the same layout,
+// which also keeps their pipeline layouts compatible (so a set bound for one pipeline stays bound for the next)
+#[derive(Default)]
+pub struct DescriptorLayoutCache {
+  layouts: HashMap<Vec<DescriptorBinding>, vk::DescriptorSetLayout>,
+}
+
+impl DescriptorLayoutCache {
+  pub fn new() -> DescriptorLayoutCache {
+    DescriptorLayoutCache::default()
+  }
+
+  // The layout with the given bindings, created if it doesn't exist yet. The order of the bindings doesn't matter
+  pub fn get_layout(&mut self, logical_device: &ash::Device, bindings: &[DescriptorBinding]) -> Result<vk::DescriptorSetLayout, RendererError> {
+    let mut key = bindings.to_vec();
+    key.sort();
+    if let Some(&layout) = self.layouts.get(&key) {
+      return Ok(layout);
+    }
+
+    let layout_bindings: Vec<vk::DescriptorSetLayoutBinding> = key.iter()
+      .map(|binding| vk::DescriptorSetLayoutBinding::builder()
+        .binding(binding.binding)
+        .descriptor_type(binding.descriptor_type)
+        .descriptor_count(binding.count)
+        .stage_flags(binding.stages)
+        .build())
+      .collect();
+    let layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&layout_bindings);
+    let layout = unsafe { logical_device.create_descriptor_set_layout(&layout_info, None)? };
+    self.layouts.insert(key, layout);
+    Ok(layout)
+  }
+
+  // Destroy every layout. Nothing may use them anymore
+  pub fn cleanup(&mut self, logical_device: &ash::Device) {
+    for (_, layout) in self.layouts.drain() {
+      unsafe { logical_device.destroy_descriptor_set_layout(layout, None) };
+    }
+  }
+}
\ No newline at end of file
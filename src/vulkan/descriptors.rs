@@ -0,0 +1,112 @@
+use ash::vk;
+
+use super::descriptor_allocator::*;
+use super::descriptor_layout_cache::*;
+use super::error::*;
+
+// Descriptor sets are how shaders get at resources that aren't vertex data (textures, uniform buffers, ...).
+// Set 0 is the camera: a uniform buffer at binding 0, read by the vertex shader. It changes once per frame.
+// Set 1 is the texture: a combined image sampler at binding 0, read by the fragment shader. It can change every draw.
+// Layouts come from the layout cache. Long lived sets come from a freeable allocator, sets that are only used by one frame come
+// from that frame in flight's allocator, which is reset once the frame's fence has signalled
+pub struct Descriptors {
+  pub layout_cache: DescriptorLayoutCache,
+  pub allocator: DescriptorAllocator, // For sets that live until they're freed (e.g. a texture's)
+  pub frame_allocators: Vec<DescriptorAllocator>, // For sets only used by one frame, one allocator per frame in flight
+  pub camera_layout: vk::DescriptorSetLayout, // The layout of the set the camera uniform buffer is bound with (set 0 of the pipeline layout)
+  pub texture_layout: vk::DescriptorSetLayout, // The layout of the set a texture is bound with (set 1 of the pipeline layout)
+}
+
+impl Descriptors {
+  // layout(set=0, binding=0) in the shader
+  pub const CAMERA_BINDINGS: [DescriptorBinding; 1] = [
+    DescriptorBinding::new(0, vk::DescriptorType::UNIFORM_BUFFER, vk::ShaderStageFlags::VERTEX),
+  ];
+  // layout(set=1, binding=0) in the shader. The image and the sampler used to read it, bound together
+  pub const TEXTURE_BINDINGS: [DescriptorBinding; 1] = [
+    DescriptorBinding::new(0, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::ShaderStageFlags::FRAGMENT),
+  ];
+
+  pub fn init(logical_device: &ash::Device, frames_in_flight: usize) -> Result<Descriptors, RendererError> {
+    let mut layout_cache = DescriptorLayoutCache::new();
+    let camera_layout = layout_cache.get_layout(logical_device, &Descriptors::CAMERA_BINDINGS)?;
+    let texture_layout = layout_cache.get_layout(logical_device, &Descriptors::TEXTURE_BINDINGS)?;
+
+    Ok(Descriptors {
+      layout_cache,
+      allocator: DescriptorAllocator::new(true), // Textures can be destroyed individually, so their sets are too
+      frame_allocators: (0..frames_in_flight).map(|_| DescriptorAllocator::new(false)).collect(),
+      camera_layout,
+      texture_layout,
+    })
+  }
+
+  // Allocate a set that binds the given image (which must be in SHADER_READ_ONLY_OPTIMAL when drawn with) and sampler
+  pub fn allocate_texture_set(&mut self, logical_device: &ash::Device, imageview: vk::ImageView, sampler: vk::Sampler) -> Result<vk::DescriptorSet, RendererError> {
+    let descriptor_set = self.allocator.allocate(logical_device, self.texture_layout)?;
+    Descriptors::write_image(logical_device, descriptor_set, 0, imageview, sampler);
+    Ok(descriptor_set)
+  }
+
+  // Allocate a camera set for the given frame in flight that binds the whole of the given uniform buffer.
+  // It's only valid until the frame in flight comes around again (see reset_frame)
+  pub fn allocate_camera_set(
+    &mut self, logical_device: &ash::Device, frame: usize, buffer: vk::Buffer, range: vk::DeviceSize,
+  ) -> Result<vk::DescriptorSet, RendererError> {
+    let descriptor_set = self.frame_allocators[frame].allocate(logical_device, self.camera_layout)?;
+    Descriptors::write_uniform_buffer(logical_device, descriptor_set, 0, buffer, range);
+    Ok(descriptor_set)
+  }
+
+  // Point a combined image sampler binding of a set at the given image and sampler
+  pub fn write_image(logical_device: &ash::Device, descriptor_set: vk::DescriptorSet, binding: u32, imageview: vk::ImageView, sampler: vk::Sampler) {
+    let image_infos = [vk::DescriptorImageInfo {
+      sampler,
+      image_view: imageview,
+      image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+    }];
+    let writes = [vk::WriteDescriptorSet::builder()
+      .dst_set(descriptor_set)
+      .dst_binding(binding)
+      .dst_array_element(0)
+      .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+      .image_info(&image_infos)
+      .build()];
+    unsafe { logical_device.update_descriptor_sets(&writes, &[]) };
+  }
+
+  // Point a uniform buffer binding of a set at the first range bytes of the given buffer
+  pub fn write_uniform_buffer(logical_device: &ash::Device, descriptor_set: vk::DescriptorSet, binding: u32, buffer: vk::Buffer, range: vk::DeviceSize) {
+    let buffer_infos = [vk::DescriptorBufferInfo {
+      buffer,
+      offset: 0,
+      range,
+    }];
+    let writes = [vk::WriteDescriptorSet::builder()
+      .dst_set(descriptor_set)
+      .dst_binding(binding)
+      .dst_array_element(0)
+      .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+      .buffer_info(&buffer_infos)
+      .build()];
+    unsafe { logical_device.update_descriptor_sets(&writes, &[]) };
+  }
+
+  // Free a set from allocate_texture_set. The set must no longer be in use by the GPU
+  pub fn free_set(&mut self, logical_device: &ash::Device, descriptor_set: vk::DescriptorSet) -> Result<(), RendererError> {
+    self.allocator.free(logical_device, descriptor_set)
+  }
+
+  // Free every set allocated for the given frame in flight. Call once its fence has signalled, before allocating its sets again
+  pub fn reset_frame(&mut self, logical_device: &ash::Device, frame: usize) -> Result<(), RendererError> {
+    self.frame_allocators[frame].reset(logical_device)
+  }
+
+  pub fn cleanup(&mut self, logical_device: &ash::Device) {
+    self.allocator.cleanup(logical_device); // Also frees every set allocated from it
+    for frame_allocator in &mut self.frame_allocators {
+      frame_allocator.cleanup(logical_device);
+    }
+    self.layout_cache.cleanup(logical_device);
+  }
+}
\ No newline at end of file
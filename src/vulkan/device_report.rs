@@ -0,0 +1,272 @@
+use ash::vk;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use super::error::*;
+use super::physical_device::*;
+use super::surface::*;
+
+// Copies struct fields into a JSON object under their own names. json fields are serialized as they are, flags as the
+// names of the set bits, and bools from Bool32s
+macro_rules! insert_fields {
+  ($map:expr, $source:expr, json: [$($field:ident),* $(,)?]) => {
+    $( $map.insert(stringify!($field).to_string(), json!($source.$field)); )*
+  };
+  ($map:expr, $source:expr, flags: [$($field:ident),* $(,)?]) => {
+    $( $map.insert(stringify!($field).to_string(), json!(format!("{:?}", $source.$field))); )*
+  };
+  ($map:expr, $source:expr, bool: [$($field:ident),* $(,)?]) => {
+    $( $map.insert(stringify!($field).to_string(), json!($source.$field != vk::FALSE)); )*
+  };
+}
+
+// Everything Vulkan tells us about the system (like vulkaninfo), to attach to bug reports. Serialize it with to_json
+#[derive(Clone, Debug, Serialize)]
+pub struct DeviceReport {
+  pub instance_version: String, // The highest Vulkan version the loader supports
+  pub layers: Vec<LayerReport>,
+  pub instance_extensions: Vec<ExtensionReport>,
+  pub devices: Vec<PhysicalDeviceReport>, // In the driver's order, so the index matches DeviceSelection::Index
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct LayerReport {
+  pub name: String,
+  pub description: String,
+  pub spec_version: String,
+  pub implementation_version: u32,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ExtensionReport {
+  pub name: String,
+  pub spec_version: u32,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PhysicalDeviceReport {
+  pub index: usize,
+  pub name: String,
+  pub device_type: String,
+  pub api_version: String,
+  pub driver_version: u32, // Raw, as the encoding is up to the vendor
+  pub vendor_id: u32,
+  pub device_id: u32,
+  pub pipeline_cache_uuid: String,
+  pub score: Option<f32>, // What pick_physical_device rates the device, None if it's unsuitable
+  pub rejected: Option<String>, // Why the device is unsuitable
+  pub limits: Map<String, Value>,
+  pub features: Map<String, Value>,
+  pub queue_families: Vec<QueueFamilyReport>,
+  pub memory_heaps: Vec<MemoryHeapReport>,
+  pub memory_types: Vec<MemoryTypeReport>,
+  pub extensions: Vec<ExtensionReport>,
+  pub surface: Option<SurfaceReport>, // What the device supports presenting to the surface, None without one
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct QueueFamilyReport {
+  pub flags: String,
+  pub queue_count: u32,
+  pub timestamp_valid_bits: u32,
+  pub min_image_transfer_granularity: [u32; 3],
+  pub supports_present: Option<bool>, // None without a surface
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct MemoryHeapReport {
+  pub size: u64, // In bytes
+  pub flags: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct MemoryTypeReport {
+  pub heap_index: u32,
+  pub flags: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SurfaceReport {
+  pub min_image_count: u32,
+  pub max_image_count: u32, // 0 means no limit
+  pub current_extent: [u32; 2],
+  pub supported_usage_flags: String,
+  pub supported_composite_alpha: String,
+  pub formats: Vec<SurfaceFormatReport>,
+  pub present_modes: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SurfaceFormatReport {
+  pub format: String,
+  pub color_space: String,
+}
+
+impl DeviceReport {
+  // Gather the report for every physical device. With a surface the report includes what each device supports presenting to it
+  pub fn collect(entry: &ash::Entry, instance: &ash::Instance, surface: Option<&VulkanSurface>) -> Result<DeviceReport, RendererError> {
+    let instance_version = entry.try_enumerate_instance_version().map_err(RendererError::Instance)?
+      .unwrap_or_else(|| vk::make_api_version(0, 1, 0, 0)); // Vulkan 1.0 loaders can't tell us
+    let layers = entry.enumerate_instance_layer_properties().map_err(RendererError::Instance)?
+      .iter()
+      .map(|layer| LayerReport {
+        name: c_string(&layer.layer_name),
+        description: c_string(&layer.description),
+        spec_version: version_string(layer.spec_version),
+        implementation_version: layer.implementation_version,
+      })
+      .collect();
+    let instance_extensions = extension_reports(&entry.enumerate_instance_extension_properties(None).map_err(RendererError::Instance)?);
+
+    let mut devices = vec![];
+    for candidate in PhysicalDevice::enumerate_physical_devices(instance, surface)? {
+      devices.push(DeviceReport::collect_device(instance, surface, candidate)?);
+    }
+    Ok(DeviceReport { instance_version: version_string(instance_version), layers, instance_extensions, devices })
+  }
+
+  fn collect_device(instance: &ash::Instance, surface: Option<&VulkanSurface>, candidate: DeviceCandidate) -> Result<PhysicalDeviceReport, RendererError> {
+    let physical_device = candidate.physical_device;
+    let props = unsafe { instance.get_physical_device_properties(physical_device) };
+    let features = unsafe { instance.get_physical_device_features(physical_device) };
+    let memory = unsafe { instance.get_physical_device_memory_properties(physical_device) };
+    let queue_family_properties = unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+    let extensions = unsafe { instance.enumerate_device_extension_properties(physical_device).map_err(RendererError::Instance)? };
+
+    let mut queue_families = vec![];
+    for (index, family) in queue_family_properties.iter().enumerate() {
+      let supports_present = match surface {
+        Some(surface) => Some(surface.get_physical_device_surface_support(physical_device, index)?),
+        None => None,
+      };
+      let granularity = family.min_image_transfer_granularity;
+      queue_families.push(QueueFamilyReport {
+        flags: format!("{:?}", family.queue_flags),
+        queue_count: family.queue_count,
+        timestamp_valid_bits: family.timestamp_valid_bits,
+        min_image_transfer_granularity: [granularity.width, granularity.height, granularity.depth],
+        supports_present,
+      });
+    }
+    let memory_heaps = memory.memory_heaps[..memory.memory_heap_count as usize].iter()
+      .map(|heap| MemoryHeapReport { size: heap.size, flags: format!("{:?}", heap.flags) })
+      .collect();
+    let memory_types = memory.memory_types[..memory.memory_type_count as usize].iter()
+      .map(|memory_type| MemoryTypeReport { heap_index: memory_type.heap_index, flags: format!("{:?}", memory_type.property_flags) })
+      .collect();
+    let surface = match surface {
+      // Devices that can't present to the surface may fail the queries, that's what supports_present and rejected are for
+      Some(surface) if queue_families.iter().any(|family| family.supports_present == Some(true)) => Some(DeviceReport::collect_surface(surface, physical_device)?),
+      _ => None,
+    };
+
+    Ok(PhysicalDeviceReport {
+      index: candidate.index,
+      name: candidate.name,
+      device_type: format!("{:?}", props.device_type),
+      api_version: version_string(props.api_version),
+      driver_version: props.driver_version,
+      vendor_id: props.vendor_id,
+      device_id: props.device_id,
+      pipeline_cache_uuid: props.pipeline_cache_uuid.iter().map(|byte| format!("{:02x}", byte)).collect(),
+      score: candidate.rating.as_ref().ok().copied(),
+      rejected: candidate.rating.err(),
+      limits: limits_json(&props.limits),
+      features: features_json(&features),
+      queue_families,
+      memory_heaps,
+      memory_types,
+      extensions: extension_reports(&extensions),
+      surface,
+    })
+  }
+
+  fn collect_surface(surface: &VulkanSurface, physical_device: vk::PhysicalDevice) -> Result<SurfaceReport, RendererError> {
+    let capabilities = surface.get_capabilities(physical_device)?;
+    Ok(SurfaceReport {
+      min_image_count: capabilities.min_image_count,
+      max_image_count: capabilities.max_image_count,
+      current_extent: [capabilities.current_extent.width, capabilities.current_extent.height],
+      supported_usage_flags: format!("{:?}", capabilities.supported_usage_flags),
+      supported_composite_alpha: format!("{:?}", capabilities.supported_composite_alpha),
+      formats: surface.get_formats(physical_device)?.iter()
+        .map(|format| SurfaceFormatReport { format: format!("{:?}", format.format), color_space: format!("{:?}", format.color_space) })
+        .collect(),
+      present_modes: surface.get_present_modes(physical_device)?.iter().map(|mode| format!("{:?}", mode)).collect(),
+    })
+  }
+
+  pub fn to_json(&self) -> serde_json::Result<String> {
+    serde_json::to_string_pretty(self)
+  }
+}
+
+fn c_string(chars: &[std::os::raw::c_char]) -> String {
+  unsafe { std::ffi::CStr::from_ptr(chars.as_ptr()) }.to_string_lossy().into_owned()
+}
+
+// e.g. 1.3.250
+fn version_string(version: u32) -> String {
+  format!("{}.{}.{}", vk::api_version_major(version), vk::api_version_minor(version), vk::api_version_patch(version))
+}
+
+fn extension_reports(extensions: &[vk::ExtensionProperties]) -> Vec<ExtensionReport> {
+  extensions.iter()
+    .map(|extension| ExtensionReport { name: c_string(&extension.extension_name), spec_version: extension.spec_version })
+    .collect()
+}
+
+fn limits_json(limits: &vk::PhysicalDeviceLimits) -> Map<String, Value> {
+  let mut map = Map::new();
+  insert_fields!(map, limits, json: [
+    max_image_dimension1_d, max_image_dimension2_d, max_image_dimension3_d, max_image_dimension_cube, max_image_array_layers,
+    max_texel_buffer_elements, max_uniform_buffer_range, max_storage_buffer_range, max_push_constants_size, max_memory_allocation_count,
+    max_sampler_allocation_count, buffer_image_granularity, sparse_address_space_size, max_bound_descriptor_sets,
+    max_per_stage_descriptor_samplers, max_per_stage_descriptor_uniform_buffers, max_per_stage_descriptor_storage_buffers,
+    max_per_stage_descriptor_sampled_images, max_per_stage_descriptor_storage_images, max_per_stage_descriptor_input_attachments,
+    max_per_stage_resources, max_descriptor_set_samplers, max_descriptor_set_uniform_buffers, max_descriptor_set_uniform_buffers_dynamic,
+    max_descriptor_set_storage_buffers, max_descriptor_set_storage_buffers_dynamic, max_descriptor_set_sampled_images,
+    max_descriptor_set_storage_images, max_descriptor_set_input_attachments, max_vertex_input_attributes, max_vertex_input_bindings,
+    max_vertex_input_attribute_offset, max_vertex_input_binding_stride, max_vertex_output_components, max_tessellation_generation_level,
+    max_tessellation_patch_size, max_tessellation_control_per_vertex_input_components, max_tessellation_control_per_vertex_output_components,
+    max_tessellation_control_per_patch_output_components, max_tessellation_control_total_output_components,
+    max_tessellation_evaluation_input_components, max_tessellation_evaluation_output_components, max_geometry_shader_invocations,
+    max_geometry_input_components, max_geometry_output_components, max_geometry_output_vertices, max_geometry_total_output_components,
+    max_fragment_input_components, max_fragment_output_attachments, max_fragment_dual_src_attachments, max_fragment_combined_output_resources,
+    max_compute_shared_memory_size, max_compute_work_group_count, max_compute_work_group_invocations, max_compute_work_group_size,
+    sub_pixel_precision_bits, sub_texel_precision_bits, mipmap_precision_bits, max_draw_indexed_index_value, max_draw_indirect_count,
+    max_sampler_lod_bias, max_sampler_anisotropy, max_viewports, max_viewport_dimensions, viewport_bounds_range, viewport_sub_pixel_bits,
+    min_memory_map_alignment, min_texel_buffer_offset_alignment, min_uniform_buffer_offset_alignment, min_storage_buffer_offset_alignment,
+    min_texel_offset, max_texel_offset, min_texel_gather_offset, max_texel_gather_offset, min_interpolation_offset, max_interpolation_offset,
+    sub_pixel_interpolation_offset_bits, max_framebuffer_width, max_framebuffer_height, max_framebuffer_layers, max_color_attachments,
+    max_sample_mask_words, timestamp_period, max_clip_distances, max_cull_distances, max_combined_clip_and_cull_distances,
+    discrete_queue_priorities, point_size_range, line_width_range, point_size_granularity, line_width_granularity,
+    optimal_buffer_copy_offset_alignment, optimal_buffer_copy_row_pitch_alignment, non_coherent_atom_size,
+  ]);
+  insert_fields!(map, limits, flags: [
+    framebuffer_color_sample_counts, framebuffer_depth_sample_counts, framebuffer_stencil_sample_counts,
+    framebuffer_no_attachments_sample_counts, sampled_image_color_sample_counts, sampled_image_integer_sample_counts,
+    sampled_image_depth_sample_counts, sampled_image_stencil_sample_counts, storage_image_sample_counts,
+  ]);
+  insert_fields!(map, limits, bool: [timestamp_compute_and_graphics, strict_lines, standard_sample_locations]);
+  map
+}
+
+fn features_json(features: &vk::PhysicalDeviceFeatures) -> Map<String, Value> {
+  let mut map = Map::new();
+  insert_fields!(map, features, bool: [
+    robust_buffer_access, full_draw_index_uint32, image_cube_array, independent_blend, geometry_shader, tessellation_shader,
+    sample_rate_shading, dual_src_blend, logic_op, multi_draw_indirect, draw_indirect_first_instance, depth_clamp, depth_bias_clamp,
+    fill_mode_non_solid, depth_bounds, wide_lines, large_points, alpha_to_one, multi_viewport, sampler_anisotropy,
+    texture_compression_etc2, texture_compression_astc_ldr, texture_compression_bc, occlusion_query_precise, pipeline_statistics_query,
+    vertex_pipeline_stores_and_atomics, fragment_stores_and_atomics, shader_tessellation_and_geometry_point_size,
+    shader_image_gather_extended, shader_storage_image_extended_formats, shader_storage_image_multisample,
+    shader_storage_image_read_without_format, shader_storage_image_write_without_format, shader_uniform_buffer_array_dynamic_indexing,
+    shader_sampled_image_array_dynamic_indexing, shader_storage_buffer_array_dynamic_indexing, shader_storage_image_array_dynamic_indexing,
+    shader_clip_distance, shader_cull_distance, shader_float64, shader_int64, shader_int16, shader_resource_residency,
+    shader_resource_min_lod, sparse_binding, sparse_residency_buffer, sparse_residency_image2_d, sparse_residency_image3_d,
+    sparse_residency2_samples, sparse_residency4_samples, sparse_residency8_samples, sparse_residency16_samples,
+    sparse_residency_aliased, variable_multisample_rate, inherited_queries,
+  ]);
+  map
+}
\ No newline at end of file
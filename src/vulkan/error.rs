@@ -0,0 +1,93 @@
+use ash::vk;
+
+// Everything that can go wrong in the renderer. Returned from the public functions in src/vulkan instead of panicking,
+// so the application can recover (e.g. from an out of date swapchain) or show a useful message instead of aborting
+#[derive(Debug)]
+pub enum RendererError {
+  Instance(vk::Result), // Creating the instance (or something that hangs off it, like the debug messenger) failed
+  NoSuitableDevice, // None of the physical devices meet our requirements
+  NoMatchingDevice(String), // No suitable physical device matches the requested selection (described in the string)
+  Device(vk::Result), // Creating or using the logical device (or an object owned by it) failed
+  Surface(vk::Result), // Creating or querying the window surface failed
+  Swapchain(vk::Result), // Creating, acquiring from or presenting to the swapchain failed
+  OutOfDate, // The swapchain no longer matches the surface (e.g. after a resize) and has to be recreated
+  Allocation(gpu_allocator::AllocationError), // The allocator couldn't allocate or free memory
+  BufferNotMapped, // Tried to write to a buffer that isn't host visible
+  BufferTooSmall { capacity: u64, requested: u64 }, // Tried to write more data into a buffer than it can hold (in bytes)
+  Shader(vk::Result), // Creating a shader module failed
+  ShaderFile { path: std::path::PathBuf, error: std::io::Error }, // Reading a shader file failed (or it isn't valid SPIR-V)
+  ShaderCompile { path: std::path::PathBuf, message: String }, // Compiling a GLSL shader failed, message has the compiler's errors
+  ShaderReflection(String), // A shader's SPIR-V couldn't be reflected (it's invalid, or uses something we don't understand)
+  ShaderInterface(Vec<String>), // The shaders don't match the pipeline's vertex input or layout, one message per mismatch
+  Pipeline(vk::Result), // Creating a pipeline or pipeline layout failed
+  PipelineCacheFile { path: std::path::PathBuf, error: std::io::Error }, // Writing the pipeline cache to disk failed
+  Validation(usize), // The validation layer reported this many errors (only returned in strict mode, see RendererConfig::strict_validation)
+  InvalidHandle, // A handle given to the Renderer doesn't refer to a live object of the right kind
+  Image(image::ImageError), // Reading or decoding an image file failed
+  TextureDataSize { expected: usize, actual: usize }, // The pixels given for a texture don't match its size (in bytes)
+}
+
+impl std::fmt::Display for RendererError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      RendererError::Instance(result) => write!(f, "Failed to initialize the Vulkan instance: {}", result),
+      RendererError::NoSuitableDevice => write!(f, "No suitable physical device found"),
+      RendererError::NoMatchingDevice(selection) => write!(f, "No suitable physical device matches {}", selection),
+      RendererError::Device(result) => write!(f, "Logical device operation failed: {}", result),
+      RendererError::Surface(result) => write!(f, "Surface operation failed: {}", result),
+      RendererError::Swapchain(result) => write!(f, "Swapchain operation failed: {}", result),
+      RendererError::OutOfDate => write!(f, "The swapchain is out of date and must be recreated"),
+      RendererError::Allocation(error) => write!(f, "Memory allocation failed: {}", error),
+      RendererError::BufferNotMapped => write!(f, "The buffer is not mapped into host memory"),
+      RendererError::BufferTooSmall { capacity, requested } => write!(f, "Tried to write {} bytes into a buffer of {} bytes", requested, capacity),
+      RendererError::Shader(result) => write!(f, "Failed to create shader module: {}", result),
+      RendererError::ShaderFile { path, error } => write!(f, "Failed to read shader {}: {}", path.display(), error),
+      RendererError::ShaderCompile { path, message } => write!(f, "Failed to compile shader {}: {}", path.display(), message),
+      RendererError::ShaderReflection(message) => write!(f, "Failed to reflect shader: {}", message),
+      RendererError::ShaderInterface(mismatches) => write!(f, "The shaders don't match the pipeline: {}", mismatches.join("; ")),
+      RendererError::Pipeline(result) => write!(f, "Failed to create pipeline: {}", result),
+      RendererError::PipelineCacheFile { path, error } => write!(f, "Failed to save pipeline cache {}: {}", path.display(), error),
+      RendererError::Validation(count) => write!(f, "The validation layer reported {} error(s)", count),
+      RendererError::InvalidHandle => write!(f, "Invalid or destroyed renderer handle"),
+      RendererError::Image(error) => write!(f, "Failed to load image: {}", error),
+      RendererError::TextureDataSize { expected, actual } => write!(f, "Expected {} bytes of texture data but got {}", expected, actual),
+    }
+  }
+}
+
+impl std::error::Error for RendererError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      RendererError::Instance(result)
+      | RendererError::Device(result)
+      | RendererError::Surface(result)
+      | RendererError::Swapchain(result)
+      | RendererError::Shader(result)
+      | RendererError::Pipeline(result) => Some(result),
+      RendererError::Allocation(error) => Some(error),
+      RendererError::Image(error) => Some(error),
+      RendererError::ShaderFile { error, .. } | RendererError::PipelineCacheFile { error, .. } => Some(error),
+      _ => None,
+    }
+  }
+}
+
+// Most Vulkan calls we make go through the logical device, so that's what a bare vk::Result means.
+// Use map_err with a more specific variant where one applies.
+impl From<vk::Result> for RendererError {
+  fn from(result: vk::Result) -> Self {
+    RendererError::Device(result)
+  }
+}
+
+impl From<gpu_allocator::AllocationError> for RendererError {
+  fn from(error: gpu_allocator::AllocationError) -> Self {
+    RendererError::Allocation(error)
+  }
+}
+
+impl From<image::ImageError> for RendererError {
+  fn from(error: image::ImageError) -> Self {
+    RendererError::Image(error)
+  }
+}
@@ -0,0 +1,78 @@
+use ash::vk;
+
+use super::command_pool::*;
+use super::error::*;
+
+// How many frames the CPU may record ahead of the GPU. Each frame in flight has its own command buffer and sync objects,
+// independent of how many images the swapchain has. 2 lets the CPU work on the next frame while the GPU renders the current one
+// without adding much latency.
+pub const MAX_FRAMES_IN_FLIGHT: usize = 2;
+
+// The things each frame in flight needs for itself
+pub struct FrameData {
+  pub commandbuffer: vk::CommandBuffer, // Re-recorded every time this frame is drawn
+  pub image_available: vk::Semaphore, // Signalled when the swapchain image we acquired is ready to be drawn to
+  pub in_flight: vk::Fence, // Signalled when the GPU has finished this frame, so its command buffer (and resources) can be reused
+}
+
+// Stores the frames in flight and which one we're currently on
+pub struct Frames {
+  pub frames: Vec<FrameData>,
+  pub current_frame: usize,
+}
+
+impl Frames {
+  pub fn init(logical_device: &ash::Device, pools: &Pools, amount: usize) -> Result<Frames, RendererError> {
+    let command_buffer_allocate_info = vk::CommandBufferAllocateInfo::builder()
+      .command_pool(pools.graphics_command_pool) // The pool allows resetting individual command buffers, which we do every frame
+      .level(vk::CommandBufferLevel::PRIMARY)
+      .command_buffer_count(amount as u32);
+    let commandbuffers = unsafe { logical_device.allocate_command_buffers(&command_buffer_allocate_info)? };
+
+    let semaphoreinfo = vk::SemaphoreCreateInfo::builder();
+    let fenceinfo = vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED); // Start signalled so the first wait on each frame doesn't block forever
+    let mut frames = Vec::with_capacity(amount);
+    for commandbuffer in commandbuffers {
+      let image_available = unsafe { logical_device.create_semaphore(&semaphoreinfo, None)? };
+      let in_flight = unsafe { logical_device.create_fence(&fenceinfo, None)? };
+      frames.push(FrameData { commandbuffer, image_available, in_flight });
+    }
+
+    Ok(Frames {
+      frames,
+      current_frame: 0,
+    })
+  }
+
+  pub fn current(&self) -> &FrameData {
+    &self.frames[self.current_frame]
+  }
+
+  // Move on to the next frame in flight (call once the current frame has been submitted)
+  pub fn advance(&mut self) {
+    self.current_frame = (self.current_frame + 1) % self.frames.len();
+  }
+
+  // Wait until the GPU has finished with the current frame
+  pub fn wait_for_current(&self, logical_device: &ash::Device) -> Result<(), RendererError> {
+    unsafe {
+      logical_device.wait_for_fences(
+        &[self.current().in_flight], // The fence to wait for
+        true, // If true wait for all fences, if false wait for at least one fence
+        std::u64::MAX, // How long to wait for the fences (nanoseconds)
+      )?;
+    }
+    Ok(())
+  }
+
+  pub fn cleanup(&self, logical_device: &ash::Device, pools: &Pools) {
+    unsafe {
+      for frame in &self.frames {
+        logical_device.destroy_fence(frame.in_flight, None);
+        logical_device.destroy_semaphore(frame.image_available, None);
+      }
+      let commandbuffers: Vec<vk::CommandBuffer> = self.frames.iter().map(|frame| frame.commandbuffer).collect();
+      logical_device.free_command_buffers(pools.graphics_command_pool, &commandbuffers);
+    }
+  }
+}
@@ -2,6 +2,9 @@ use ash::{vk};
 use gpu_allocator::vulkan::*;
 use gpu_allocator::MemoryLocation;
 
+use super::error::*;
+use super::upload::*;
+
 pub struct IndexBuffer {
   buffer: vk::Buffer,
   allocation: Allocation,
@@ -9,47 +12,61 @@ pub struct IndexBuffer {
 }
 
 impl IndexBuffer {
-  pub fn new(device: &ash::Device, allocator: &mut Allocator, size: u64) -> IndexBuffer {
+  // Create a host visible index buffer that can be updated every frame
+  pub fn new(device: &ash::Device, allocator: &mut Allocator, size: u64) -> Result<IndexBuffer, RendererError> {
+    IndexBuffer::create(device, allocator, size, vk::BufferUsageFlags::INDEX_BUFFER, MemoryLocation::CpuToGpu)
+  }
+
+  // Create an index buffer in device local memory (fastest for the GPU to read) holding the given indices.
+  // It can't be updated afterwards, and the indices aren't there until the uploader has been flushed
+  pub fn new_static(device: &ash::Device, allocator: &mut Allocator, uploader: &mut Uploader, data: &[u32]) -> Result<IndexBuffer, RendererError> {
+    let size = IndexBuffer::get_size_for_num_indices(data.len());
+    let mut index_buffer = IndexBuffer::create(device, allocator, size, vk::BufferUsageFlags::INDEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST, MemoryLocation::GpuOnly)?;
+    let bytes = unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, size as usize) };
+    uploader.upload_to_buffer(device, allocator, index_buffer.buffer, vk::PipelineStageFlags::VERTEX_INPUT, vk::AccessFlags::INDEX_READ, bytes)?;
+    index_buffer.indice_count = data.len() as u32;
+    Ok(index_buffer)
+  }
+
+  fn create(device: &ash::Device, allocator: &mut Allocator, size: u64, usage: vk::BufferUsageFlags, location: MemoryLocation) -> Result<IndexBuffer, RendererError> {
     let index_buffer_create_info = vk::BufferCreateInfo::builder()
-      .size(size)
-      .usage(vk::BufferUsageFlags::INDEX_BUFFER)
+      .size(size.max(1)) // Can't be empty, see VertexBuffer::create
+      .usage(usage)
       .sharing_mode(vk::SharingMode::EXCLUSIVE);
 
     let index_buffer = unsafe {
         device
-            .create_buffer(&index_buffer_create_info, None)
-            .expect("Failed to create index buffer")
+            .create_buffer(&index_buffer_create_info, None)?
     };
 
     let mem_requirements = unsafe { device.get_buffer_memory_requirements(index_buffer) };
-    let location = MemoryLocation::CpuToGpu;
 
     let allocation = allocator.allocate(&AllocationCreateDesc {
       requirements: mem_requirements,
       location,
       linear: true, // Buffers are always linear
       name: "Index Buffer",
-    }).expect("Failed to allocate memory for index buffer!");
+    })?;
 
     unsafe {
         // Bind the vertex buffer memory to the vertex buffer
         device
-            .bind_buffer_memory(index_buffer,  allocation.memory(), allocation.offset())
-            .expect("Failed to bind index buffer");
+            .bind_buffer_memory(index_buffer,  allocation.memory(), allocation.offset())?;
     }
 
-    IndexBuffer {
+    Ok(IndexBuffer {
       buffer: index_buffer,
-      allocation: allocation,
+      allocation,
       indice_count: 0,
-    }
+    })
   }
 
-  pub fn destroy(&mut self, device: &ash::Device, allocator: &mut Allocator) {
-    allocator.free(std::mem::take(&mut self.allocation)).expect("Failed to free vertex buffer memory!");
+  pub fn destroy(&mut self, device: &ash::Device, allocator: &mut Allocator) -> Result<(), RendererError> {
+    allocator.free(std::mem::take(&mut self.allocation))?;
     unsafe {
       device.destroy_buffer(self.buffer, None);
     }
+    Ok(())
   }
 
   /// Returns the size for the number of u32 indices (in bytes)
@@ -57,8 +74,12 @@ impl IndexBuffer {
     (num_indices * std::mem::size_of::<u32>()) as u64
   }
 
-  pub fn update_buffer(&mut self, device: &ash::Device, data: &[u32]) {
-    let dst = self.allocation.mapped_ptr().unwrap().cast().as_ptr();
+  pub fn update_buffer(&mut self, _device: &ash::Device, data: &[u32]) -> Result<(), RendererError> {
+    let requested = IndexBuffer::get_size_for_num_indices(data.len());
+    if requested > self.allocation.size() {
+      return Err(RendererError::BufferTooSmall { capacity: self.allocation.size(), requested });
+    }
+    let dst = self.allocation.mapped_ptr().ok_or(RendererError::BufferNotMapped)?.cast().as_ptr();
     unsafe {
       std::ptr::copy_nonoverlapping(
           data.as_ptr(),
@@ -68,6 +89,7 @@ impl IndexBuffer {
     }
     self.indice_count = data.len() as u32;
     //println!("Updated index buffer with {} indices", self.indice_count);
+    Ok(())
   }
 
   pub fn get_buffer(&self) -> vk::Buffer {
@@ -0,0 +1,114 @@
+use ash::{vk};
+use gpu_allocator::vulkan::*;
+use gpu_allocator::MemoryLocation;
+
+use super::{vertex::InstanceData, error::*, upload::*};
+
+// A vertex buffer holding per instance data (bound to binding 1) instead of vertices
+pub struct InstanceBuffer {
+  pub buffer: vk::Buffer,
+  pub allocation: Allocation,
+  instance_count: u32,
+}
+
+impl InstanceBuffer {
+  // Create a host visible instance buffer that can be updated every frame
+  pub fn new(device: &ash::Device, allocator: &mut Allocator, size: u64) -> Result<InstanceBuffer, RendererError> {
+    InstanceBuffer::create(device, allocator, size, vk::BufferUsageFlags::VERTEX_BUFFER, MemoryLocation::CpuToGpu)
+  }
+
+  // Create an instance buffer in device local memory (fastest for the GPU to read) holding the given instances.
+  // It can't be updated afterwards, and the instances aren't there until the uploader has been flushed
+  pub fn new_static(device: &ash::Device, allocator: &mut Allocator, uploader: &mut Uploader, data: &[InstanceData]) -> Result<InstanceBuffer, RendererError> {
+    let size = InstanceBuffer::get_size_for_num_instances(data.len());
+    let mut instance_buffer = InstanceBuffer::create(device, allocator, size, vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST, MemoryLocation::GpuOnly)?;
+    let bytes = unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, size as usize) };
+    uploader.upload_to_buffer(device, allocator, instance_buffer.buffer, vk::PipelineStageFlags::VERTEX_INPUT, vk::AccessFlags::VERTEX_ATTRIBUTE_READ, bytes)?;
+    instance_buffer.instance_count = data.len() as u32;
+    Ok(instance_buffer)
+  }
+
+  fn create(device: &ash::Device, allocator: &mut Allocator, size: u64, usage: vk::BufferUsageFlags, location: MemoryLocation) -> Result<InstanceBuffer, RendererError> {
+    let instance_buffer_create_info = vk::BufferCreateInfo::builder()
+      .size(size.max(1)) // Can't be empty, see VertexBuffer::create
+      .usage(usage)
+      .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+    let inst_buff = unsafe {
+        device
+            .create_buffer(&instance_buffer_create_info, None)?
+    };
+
+    let mem_requirements = unsafe { device.get_buffer_memory_requirements(inst_buff) };
+
+    let allocation = allocator.allocate(&AllocationCreateDesc {
+      requirements: mem_requirements,
+      location,
+      linear: true, // Buffers are always linear
+      name: "Instance Buffer",
+    })?;
+
+    unsafe {
+        // Bind the instance buffer memory to the instance buffer
+        device
+            .bind_buffer_memory(inst_buff,  allocation.memory(), allocation.offset())?;
+    }
+
+    Ok(InstanceBuffer {
+      buffer: inst_buff,
+      allocation,
+      instance_count: 0,
+    })
+  }
+
+  pub fn destroy(&mut self, device: &ash::Device, allocator: &mut Allocator) -> Result<(), RendererError> {
+    allocator.free(std::mem::take(&mut self.allocation))?;
+    unsafe {
+      device.destroy_buffer(self.buffer, None);
+      //device.free_memory(self.memory, None);
+    }
+    Ok(())
+  }
+
+  /// Returns the size for the number of instances (in bytes)
+  pub fn get_size_for_num_instances(num_instances: usize) -> u64 {
+    (num_instances * std::mem::size_of::<InstanceData>()) as u64
+  }
+
+  pub fn update_buffer(&mut self, _device: &ash::Device, data: &[InstanceData]) -> Result<(), RendererError> {
+    let requested = InstanceBuffer::get_size_for_num_instances(data.len());
+    if requested > self.allocation.size() {
+      return Err(RendererError::BufferTooSmall { capacity: self.allocation.size(), requested });
+    }
+    let dst = self.allocation.mapped_ptr().ok_or(RendererError::BufferNotMapped)?.cast().as_ptr();
+    unsafe {
+      std::ptr::copy_nonoverlapping(
+          data.as_ptr(),
+          dst,
+          data.len(),
+      );
+    }
+    self.instance_count = data.len() as u32;
+    Ok(())
+  }
+
+  pub fn get_buffer(&self) -> vk::Buffer {
+    self.buffer
+  }
+
+  pub fn get_memory(&self) -> vk::DeviceMemory {
+    unsafe { self.allocation.memory() }
+  }
+
+  pub fn get_size(&self) -> vk::DeviceSize {
+    self.allocation.size()
+  }
+
+  pub fn get_offset(&self) -> vk::DeviceSize {
+    self.allocation.offset()
+  }
+
+  pub fn get_instance_count(&self) -> u32 {
+    self.instance_count
+  }
+}
\ No newline at end of file
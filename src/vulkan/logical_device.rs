@@ -6,11 +6,15 @@ use ash::vk::{
 };
 
 use super::queue::*;
+use super::error::*;
 
 pub struct LogicalDevice {}
 
 impl LogicalDevice {
-  pub fn init_device_and_queues(instance: &ash::Instance, physical_device: vk::PhysicalDevice, queue_families: &QueueFamilies, layer_names: &[&str]) -> Result<(ash::Device, Queues), vk::Result> {
+  pub fn init_device_and_queues(
+    instance: &ash::Instance, physical_device: vk::PhysicalDevice, queue_families: &QueueFamilies, layer_names: &[&str], enable_swapchain: bool,
+    features: &vk::PhysicalDeviceFeatures,
+  ) -> Result<(ash::Device, Queues), RendererError> {
     // Turn the layer names into proper format
     let layer_names_c: Vec<std::ffi::CString> = layer_names
         .iter()
@@ -22,30 +26,34 @@ impl LogicalDevice {
         .collect();
 
     let priorities = [1.0f32]; // We only have one queue of each type, so we set the priority to 1.0. Priority is a float between 0.0 and 1.0, with 0.0 being the lowest priority.
-    let queue_infos = [ // We want a graphics and transfer queue
-        vk::DeviceQueueCreateInfo::builder()
-            .queue_family_index(queue_families.graphics.unwrap())
+    // We want a graphics, transfer and (with a surface) present queue. Each family may only be listed once, families used for more than one
+    // of them share the one queue
+    let mut families: Vec<u32> = [queue_families.graphics, queue_families.transfer, queue_families.present].iter().flatten().copied().collect();
+    families.sort_unstable();
+    families.dedup();
+    let queue_infos: Vec<vk::DeviceQueueCreateInfo> = families.iter()
+        .map(|&family| vk::DeviceQueueCreateInfo::builder()
+            .queue_family_index(family)
             .queue_priorities(&priorities)
-            .build(),
-        vk::DeviceQueueCreateInfo::builder()
-            .queue_family_index(queue_families.transfer.unwrap())
-            .queue_priorities(&priorities)
-            .build(),
-    ];
+            .build())
+        .collect();
 
     // Get info about device extensions
-    let device_extension_name_pointers: Vec<*const i8> =
+    let mut device_extension_name_pointers: Vec<*const i8> =
         vec![
-            ash::extensions::khr::Swapchain::name().as_ptr(),
             #[cfg(any(target_os = "macos", target_os = "ios"))]
             KhrPortabilitySubsetFn::name().as_ptr(),
         ];
+    if enable_swapchain { // Headless rendering has nothing to present to, so doesn't need (and may not have) the swapchain extension
+        device_extension_name_pointers.push(ash::extensions::khr::Swapchain::name().as_ptr());
+    }
 
     // Create the logical device
     let device_create_info = vk::DeviceCreateInfo::builder()
         .queue_create_infos(&queue_infos)
         .enabled_extension_names(&device_extension_name_pointers)
-        .enabled_layer_names(&layer_name_pointers);
+        .enabled_layer_names(&layer_name_pointers)
+        .enabled_features(features);
     let logical_device =
         unsafe { instance.create_device(physical_device, &device_create_info, None)? };
 
@@ -54,12 +62,17 @@ impl LogicalDevice {
         unsafe { logical_device.get_device_queue(queue_families.graphics.unwrap(), 0) };
     let transfer_queue =
         unsafe { logical_device.get_device_queue(queue_families.transfer.unwrap(), 0) };
+    let present_queue = match queue_families.present {
+        Some(present_family) => unsafe { logical_device.get_device_queue(present_family, 0) },
+        None => vk::Queue::null(),
+    };
 
     Ok((
         logical_device,
         Queues {
             graphics_queue,
             transfer_queue,
+            present_queue,
         },
     ))
   }
@@ -1,16 +1,35 @@
 // Publically Mod all files in the vulkan directory
+pub mod error;
+pub mod config;
 pub mod surface;
 pub mod command_pool;
 pub mod queue;
 pub mod pipeline;
+pub mod pipeline_cache;
+pub mod shader;
+pub mod shader_watcher;
+pub mod reflection;
 pub mod swapchain;
+pub mod offscreen;
+pub mod attachment;
 pub mod debug_utils;
 pub mod vertex_buffer;
+pub mod instance_buffer;
 pub mod index_buffer;
+pub mod uniform_buffer;
+pub mod upload;
+pub mod descriptor_layout_cache;
+pub mod descriptor_allocator;
+pub mod descriptors;
+pub mod texture;
 pub mod physical_device;
+pub mod device_report;
 pub mod logical_device;
 pub mod render_pass;
 pub mod renderable;
+pub mod frames;
 pub mod app;
+pub mod renderer;
 
-pub mod vertex;
\ No newline at end of file
+pub mod vertex;
+pub mod uniforms;
\ No newline at end of file
@@ -0,0 +1,174 @@
+use ash::vk;
+use gpu_allocator::vulkan::*;
+use gpu_allocator::MemoryLocation;
+
+use super::error::*;
+
+// Stores the things needed to render without a window (headless), i.e. a color image we render into and
+// a host visible buffer we copy the finished image into so the pixels can be read back on the CPU.
+// This takes the place of the surface + swapchain, so it works on build servers and with software ICDs (like lavapipe)
+pub struct OffscreenTarget {
+  pub image: vk::Image,
+  pub image_allocation: Allocation,
+  pub imageview: vk::ImageView,
+  pub framebuffer: vk::Framebuffer,
+  pub readback_buffer: vk::Buffer,
+  pub readback_allocation: Allocation,
+  pub format: vk::Format,
+  pub extent: vk::Extent2D,
+}
+
+impl OffscreenTarget {
+  // The format of the offscreen color image (and so the format of the pixels handed back), 4 bytes per pixel.
+  // sRGB like the default swapchain format, so headless frames look the same as on screen (and can be saved as images as is)
+  pub const FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+  pub fn init(logical_device: &ash::Device, allocator: &mut Allocator, extent: vk::Extent2D) -> Result<OffscreenTarget, RendererError> {
+    // Create the color image we render into
+    let image_create_info = vk::ImageCreateInfo::builder()
+      .image_type(vk::ImageType::TYPE_2D)
+      .format(OffscreenTarget::FORMAT)
+      .extent(vk::Extent3D { width: extent.width, height: extent.height, depth: 1 })
+      .mip_levels(1)
+      .array_layers(1)
+      .samples(vk::SampleCountFlags::TYPE_1)
+      .tiling(vk::ImageTiling::OPTIMAL)
+      .usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC) // We draw to it and then copy out of it
+      .sharing_mode(vk::SharingMode::EXCLUSIVE)
+      .initial_layout(vk::ImageLayout::UNDEFINED);
+    let image = unsafe { logical_device.create_image(&image_create_info, None)? };
+
+    let image_allocation = allocator.allocate(&AllocationCreateDesc {
+      requirements: unsafe { logical_device.get_image_memory_requirements(image) },
+      location: MemoryLocation::GpuOnly, // Only the GPU touches the image, we read the pixels through the readback buffer
+      linear: false, // Optimal tiling images are not linear
+      name: "Offscreen Color Image",
+    })?;
+    unsafe { logical_device.bind_image_memory(image, image_allocation.memory(), image_allocation.offset())? };
+
+    let subresource_range = vk::ImageSubresourceRange::builder()
+      .aspect_mask(vk::ImageAspectFlags::COLOR)
+      .base_mip_level(0)
+      .level_count(1)
+      .base_array_layer(0)
+      .layer_count(1);
+    let imageview_create_info = vk::ImageViewCreateInfo::builder()
+      .image(image)
+      .view_type(vk::ImageViewType::TYPE_2D)
+      .format(OffscreenTarget::FORMAT)
+      .subresource_range(*subresource_range);
+    let imageview = unsafe { logical_device.create_image_view(&imageview_create_info, None)? };
+
+    // Create the buffer the finished image gets copied into (tightly packed rows)
+    let readback_buffer_create_info = vk::BufferCreateInfo::builder()
+      .size(OffscreenTarget::get_size_for_extent(extent))
+      .usage(vk::BufferUsageFlags::TRANSFER_DST)
+      .sharing_mode(vk::SharingMode::EXCLUSIVE);
+    let readback_buffer = unsafe { logical_device.create_buffer(&readback_buffer_create_info, None)? };
+
+    let readback_allocation = allocator.allocate(&AllocationCreateDesc {
+      requirements: unsafe { logical_device.get_buffer_memory_requirements(readback_buffer) },
+      location: MemoryLocation::GpuToCpu, // Host visible (and ideally cached) so reading it back is fast
+      linear: true, // Buffers are always linear
+      name: "Offscreen Readback Buffer",
+    })?;
+    unsafe { logical_device.bind_buffer_memory(readback_buffer, readback_allocation.memory(), readback_allocation.offset())? };
+
+    Ok(OffscreenTarget {
+      image,
+      image_allocation,
+      imageview,
+      framebuffer: vk::Framebuffer::null(),
+      readback_buffer,
+      readback_allocation,
+      format: OffscreenTarget::FORMAT,
+      extent,
+    })
+  }
+
+  // The multisampled color image (resolved into our image) and depth image, when used (see VulkanSwapchain::create_framebuffers)
+  pub fn create_framebuffer(
+    &mut self, logical_device: &ash::Device, renderpass: vk::RenderPass, msaa_view: Option<vk::ImageView>, depth_view: Option<vk::ImageView>,
+  ) -> Result<(), RendererError> {
+    let iview: Vec<vk::ImageView> = msaa_view.into_iter().chain([self.imageview]).chain(depth_view).collect();
+    let framebuffer_info = vk::FramebufferCreateInfo::builder()
+      .render_pass(renderpass)
+      .attachments(&iview)
+      .width(self.extent.width)
+      .height(self.extent.height)
+      .layers(1);
+    self.framebuffer = unsafe { logical_device.create_framebuffer(&framebuffer_info, None)? };
+    Ok(())
+  }
+
+  /// Returns the size of the readback buffer for the given extent (in bytes)
+  pub fn get_size_for_extent(extent: vk::Extent2D) -> u64 {
+    extent.width as u64 * extent.height as u64 * 4
+  }
+
+  // Record the copy of the rendered image into the readback buffer. Must come after the renderpass has ended
+  // (the renderpass leaves the image in TRANSFER_SRC_OPTIMAL and makes the color writes visible to transfers).
+  pub fn cmd_copy_to_readback(&self, logical_device: &ash::Device, commandbuffer: vk::CommandBuffer) {
+    let region = vk::BufferImageCopy::builder()
+      .buffer_offset(0)
+      .buffer_row_length(0) // 0 means tightly packed according to the image extent
+      .buffer_image_height(0)
+      .image_subresource(vk::ImageSubresourceLayers {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        mip_level: 0,
+        base_array_layer: 0,
+        layer_count: 1,
+      })
+      .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+      .image_extent(vk::Extent3D { width: self.extent.width, height: self.extent.height, depth: 1 });
+
+    // Make the copied data visible to the host once the fence signals
+    let host_barrier = vk::BufferMemoryBarrier::builder()
+      .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+      .dst_access_mask(vk::AccessFlags::HOST_READ)
+      .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+      .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+      .buffer(self.readback_buffer)
+      .offset(0)
+      .size(vk::WHOLE_SIZE);
+
+    unsafe {
+      logical_device.cmd_copy_image_to_buffer(
+        commandbuffer,
+        self.image,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        self.readback_buffer,
+        &[region.build()],
+      );
+      logical_device.cmd_pipeline_barrier(
+        commandbuffer,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::HOST,
+        vk::DependencyFlags::empty(),
+        &[],
+        &[host_barrier.build()],
+        &[],
+      );
+    }
+  }
+
+  // Copy the pixels out of the readback buffer (tightly packed RGBA8 rows, top row first).
+  // Only valid once the fence for the frame that rendered them has signalled.
+  pub fn read_pixels(&self) -> Result<Vec<u8>, RendererError> {
+    let size = OffscreenTarget::get_size_for_extent(self.extent) as usize;
+    let src = self.readback_allocation.mapped_slice().ok_or(RendererError::BufferNotMapped)?;
+    Ok(src[..size].to_vec())
+  }
+
+  pub fn destroy(&mut self, logical_device: &ash::Device, allocator: &mut Allocator) -> Result<(), RendererError> {
+    unsafe {
+      logical_device.destroy_framebuffer(self.framebuffer, None);
+      logical_device.destroy_image_view(self.imageview, None);
+      logical_device.destroy_buffer(self.readback_buffer, None);
+      logical_device.destroy_image(self.image, None);
+    }
+    allocator.free(std::mem::take(&mut self.readback_allocation))?;
+    allocator.free(std::mem::take(&mut self.image_allocation))?;
+    Ok(())
+  }
+}
@@ -1,49 +1,145 @@
 use ash::vk;
 
+use super::error::*;
+use super::attachment::*;
+use super::config::*;
+use super::surface::*;
+
+// A physical device as the driver enumerated it, with its score or the reason it can't be used
+#[derive(Clone, Debug)]
+pub struct DeviceCandidate {
+  pub index: usize, // Position in the driver's enumeration order, which is what DeviceSelection::Index refers to
+  pub physical_device: vk::PhysicalDevice,
+  pub name: String,
+  pub device_type: vk::PhysicalDeviceType,
+  pub rating: Result<f32, String>, // The score from rate_physical_device, or why the device is unsuitable
+}
+
+impl DeviceCandidate {
+  pub fn matches(&self, selection: &DeviceSelection) -> bool {
+    match selection {
+      DeviceSelection::Index(index) => self.index == *index,
+      DeviceSelection::Name(name) => self.name.to_lowercase().contains(&name.to_lowercase()),
+      DeviceSelection::Type(device_type) => self.device_type == *device_type,
+    }
+  }
+}
+
 pub struct PhysicalDevice {}
 
 impl PhysicalDevice {
-  // Pick the best available Vulkan physical device. This means the highest rated one that is suitable.
-  pub fn pick_physical_device(instance: &ash::Instance) -> Option<(vk::PhysicalDevice, vk::PhysicalDeviceProperties, vk::PhysicalDeviceFeatures)> {
-    let phys_devs = unsafe { instance.enumerate_physical_devices().expect("Could not enumerate physical devices!") }; // Get all physical devices
-    let mut phys_dev: vk::PhysicalDevice = vk::PhysicalDevice::null(); // Create a null physical device
-    let mut current_score = 0.0; // Create a score variable
-    for p in &phys_devs { // For each physical device
-        let score = PhysicalDevice::rate_physical_device(instance, p);
-        if score > current_score { // If the score is higher than the current score, set the physical device to this one
-            current_score = score;
-            phys_dev = *p;
+  // Pick the best available Vulkan physical device. This means the highest rated one that is suitable (and matches the selection, if there is one).
+  // With a surface, devices that can't present to it are unsuitable
+  pub fn pick_physical_device(instance: &ash::Instance, surface: Option<&VulkanSurface>, selection: Option<&DeviceSelection>) -> Result<(vk::PhysicalDevice, vk::PhysicalDeviceProperties, vk::PhysicalDeviceFeatures), RendererError> {
+    let candidates = PhysicalDevice::enumerate_physical_devices(instance, surface)?;
+    let mut best: Option<(&DeviceCandidate, f32)> = None;
+    for candidate in &candidates {
+        if selection.is_some_and(|selection| !candidate.matches(selection)) {
+            continue;
+        }
+        if let Ok(score) = candidate.rating {
+            if best.is_none_or(|(_, current_score)| score > current_score) { // If the score is higher than the current score, use this one
+                best = Some((candidate, score));
+            }
         }
     }
-    if phys_dev == vk::PhysicalDevice::null() { // If the physical device is null, return None (this means no suitable devices were found)
-        return None;
-    } else {
-        let props = unsafe { instance.get_physical_device_properties(phys_dev) }; // Get the properties of the physical device
-        let feats = unsafe { instance.get_physical_device_features(phys_dev) }; // Get the features of the physical device
-        let device_name = String::from(
-            unsafe { std::ffi::CStr::from_ptr(props.device_name.as_ptr()) }
-                .to_str()
-                .unwrap(),
-        ); // Get the name of the physical device
-
-        let driver_major = props.driver_version >> 22; // Get the major version of the driver
-        let driver_minor = (props.driver_version >> 12) & 0x3ff; // Get the minor version of the driver
-        let driver_patch = props.driver_version & 0xfff; // Get the patch version of the driver
-
-        let api_major = vk::api_version_major(props.api_version);
-        let api_minor = vk::api_version_minor(props.api_version);
-        let api_patch = vk::api_version_patch(props.api_version);
-        let api_variant = vk::api_version_variant(props.api_version);
-
-        println!("[Vulkan-render][info] Using {:?} device {} (driver v{}.{}.{}) with score {}.", props.device_type, device_name, driver_major, driver_minor, driver_patch, current_score);
-        println!("[Vulkan-render][info] Device supports Vulkan v{}.{}.{} (variant {}).", api_major, api_minor, api_patch, api_variant);
-        return Some((phys_dev, props, feats));
+    let (candidate, current_score) = match best {
+        Some(best) => best,
+        None => { // No suitable devices were found, show what there is to explain why
+            log::error!("No suitable device found, the available devices are:");
+            PhysicalDevice::log_candidates(&candidates, log::Level::Error);
+            return Err(match selection {
+                Some(selection) => RendererError::NoMatchingDevice(selection.to_string()),
+                None => RendererError::NoSuitableDevice,
+            });
+        }
+    };
+    let phys_dev = candidate.physical_device;
+    let props = unsafe { instance.get_physical_device_properties(phys_dev) }; // Get the properties of the physical device
+    let feats = unsafe { instance.get_physical_device_features(phys_dev) }; // Get the features of the physical device
+
+    let driver_major = props.driver_version >> 22; // Get the major version of the driver
+    let driver_minor = (props.driver_version >> 12) & 0x3ff; // Get the minor version of the driver
+    let driver_patch = props.driver_version & 0xfff; // Get the patch version of the driver
+
+    let api_major = vk::api_version_major(props.api_version);
+    let api_minor = vk::api_version_minor(props.api_version);
+    let api_patch = vk::api_version_patch(props.api_version);
+    let api_variant = vk::api_version_variant(props.api_version);
+
+    log::info!("Using {:?} device {} (driver v{}.{}.{}) with score {}.", props.device_type, candidate.name, driver_major, driver_minor, driver_patch, current_score);
+    log::info!("Device supports Vulkan v{}.{}.{} (variant {}).", api_major, api_minor, api_patch, api_variant);
+    Ok((phys_dev, props, feats))
+  }
+
+  // Every physical device in the driver's order, rated (against the surface, if there is one)
+  pub fn enumerate_physical_devices(instance: &ash::Instance, surface: Option<&VulkanSurface>) -> Result<Vec<DeviceCandidate>, RendererError> {
+    let phys_devs = unsafe { instance.enumerate_physical_devices().map_err(RendererError::Instance)? }; // Get all physical devices
+    Ok(phys_devs.iter().enumerate().map(|(index, &physical_device)| {
+      let props = unsafe { instance.get_physical_device_properties(physical_device) };
+      DeviceCandidate {
+        index,
+        physical_device,
+        name: unsafe { std::ffi::CStr::from_ptr(props.device_name.as_ptr()) }.to_string_lossy().into_owned(),
+        device_type: props.device_type,
+        rating: PhysicalDevice::rate_physical_device(instance, &physical_device, surface),
+      }
+    }).collect())
+  }
+
+  // Log every physical device with its score, or why it was rejected. Useful for finding what to put in a DeviceSelection
+  pub fn list_physical_devices(instance: &ash::Instance, surface: Option<&VulkanSurface>) -> Result<Vec<DeviceCandidate>, RendererError> {
+    let candidates = PhysicalDevice::enumerate_physical_devices(instance, surface)?;
+    PhysicalDevice::log_candidates(&candidates, log::Level::Info);
+    Ok(candidates)
+  }
+
+  fn log_candidates(candidates: &[DeviceCandidate], level: log::Level) {
+    if candidates.is_empty() {
+      log::log!(level, "No Vulkan devices found.");
+    }
+    for candidate in candidates {
+      match &candidate.rating {
+        Ok(score) => log::log!(level, "Device {}: {} ({:?}), score {}.", candidate.index, candidate.name, candidate.device_type, score),
+        Err(reason) => log::log!(level, "Device {}: {} ({:?}), rejected: {}.", candidate.index, candidate.name, candidate.device_type, reason),
+      }
     }
   }
 
+  // The highest sample count the device can render color attachments (and depth_format attachments, if given) with that's
+  // no higher than requested (1 if nothing else fits)
+  pub fn pick_sample_count(props: &vk::PhysicalDeviceProperties, requested: u32, depth_format: Option<vk::Format>) -> vk::SampleCountFlags {
+    let mut supported = props.limits.framebuffer_color_sample_counts;
+    if let Some(depth_format) = depth_format {
+      supported &= props.limits.framebuffer_depth_sample_counts;
+      if AttachmentImage::has_stencil(depth_format) {
+        supported &= props.limits.framebuffer_stencil_sample_counts;
+      }
+    }
+    [64, 32, 16, 8, 4, 2].iter()
+      .map(|&count| vk::SampleCountFlags::from_raw(count)) // The flag bits are the sample counts themselves
+      .find(|&samples| samples.as_raw() <= requested && supported.contains(samples))
+      .unwrap_or(vk::SampleCountFlags::TYPE_1)
+  }
+
+  // The most precise depth format the device can use as a depth attachment, None if it supports none of them.
+  // With stencil only formats that have a stencil aspect are considered
+  pub fn pick_depth_format(instance: &ash::Instance, physical_device: vk::PhysicalDevice, with_stencil: bool) -> Option<vk::Format> {
+    let candidates: &[vk::Format] = if with_stencil {
+      &[vk::Format::D32_SFLOAT_S8_UINT, vk::Format::D24_UNORM_S8_UINT, vk::Format::D16_UNORM_S8_UINT]
+    } else {
+      &[vk::Format::D32_SFLOAT, vk::Format::X8_D24_UNORM_PACK32, vk::Format::D16_UNORM] // Every device supports D16, so this always finds one
+    };
+    candidates.iter().copied().find(|&format| {
+      let props = unsafe { instance.get_physical_device_format_properties(physical_device, format) };
+      props.optimal_tiling_features.contains(vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+    })
+  }
+
   // Rate device based on its properties (whether its discrete, integrated, etc; how many queues it has, etc)
-  // We also check if the device is suitable at all for our needs (Check for hard requirements [things like if it supports geometry shaders, certain extensions, etc])
-  pub fn rate_physical_device(instance: &ash::Instance, device: &vk::PhysicalDevice) -> f32 {
+  // We also check if the device is suitable at all for our needs (Check for hard requirements [things like if it supports geometry shaders, certain extensions, etc]),
+  // returning why it isn't if it's not. With a surface, the device has to be able to present to it
+  pub fn rate_physical_device(instance: &ash::Instance, device: &vk::PhysicalDevice, surface: Option<&VulkanSurface>) -> Result<f32, String> {
     let props = unsafe { instance.get_physical_device_properties(*device) }; // Get the properties of the physical device
     //dbg!(props);
     let features = unsafe { instance.get_physical_device_features(*device) }; // Get the features of the physical device
@@ -69,8 +165,7 @@ impl PhysicalDevice {
     // TODO: Actually this is not true. And MoltenVK doesn't support geometry shaders. We might need these for certain features
     // (especially 3D games) so this should be conditionally added if we are actually utilizing those features.
     /*if features.geometry_shader < 1 { // Features are either 0 (not supported) or 1 (supported)
-        println!("Device missing geometry shader support, thus your system is not supported!");
-        return 0.0;
+        return Err("No geometry shader support".to_string());
     }*/
 
     let mut found_graphics_queue = false; // We need a graphics queue
@@ -84,11 +179,111 @@ impl PhysicalDevice {
         }
     }
 
-    if !found_graphics_queue || !found_transfer_queue {
-        println!("Phys device missing queues");
-        return 0.0;
+    if !found_graphics_queue {
+        return Err("No graphics queue".to_string());
+    }
+    if !found_transfer_queue {
+        return Err("No transfer queue".to_string());
     }
 
-    score
+    if let Some(surface) = surface { // Presenting needs the swapchain extension and a queue family that can present to the surface
+        let extensions = unsafe { instance.enumerate_device_extension_properties(*device) }
+            .map_err(|error| format!("Failed to get the device extensions: {}", error))?;
+        let has_swapchain = extensions.iter().any(|extension| {
+            let name = unsafe { std::ffi::CStr::from_ptr(extension.extension_name.as_ptr()) };
+            name == ash::extensions::khr::Swapchain::name()
+        });
+        if !has_swapchain {
+            return Err(format!("No {} support", ash::extensions::khr::Swapchain::name().to_string_lossy()));
+        }
+        let mut can_present = false;
+        for index in 0..queuefamilyproperties.len() {
+            can_present |= surface.get_physical_device_surface_support(*device, index)
+                .map_err(|error| format!("Failed to check presentation support: {}", error))?;
+        }
+        if !can_present {
+            return Err("Can't present to the window's surface".to_string());
+        }
+    }
+
+    Ok(score)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn candidate(index: usize, name: &str, device_type: vk::PhysicalDeviceType) -> DeviceCandidate {
+    DeviceCandidate { index, physical_device: vk::PhysicalDevice::null(), name: name.to_string(), device_type, rating: Ok(1.0) }
+  }
+
+  // Limits where color attachments go up to 8 samples, depth to 4 and stencil to 2
+  fn properties() -> vk::PhysicalDeviceProperties {
+    let counts = |max: u32| vk::SampleCountFlags::from_raw((max << 1) - 1); // Every power of two up to max
+    vk::PhysicalDeviceProperties {
+      limits: vk::PhysicalDeviceLimits {
+        framebuffer_color_sample_counts: counts(8),
+        framebuffer_depth_sample_counts: counts(4),
+        framebuffer_stencil_sample_counts: counts(2),
+        ..Default::default()
+      },
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn sample_count_above_maximum() {
+    assert_eq!(PhysicalDevice::pick_sample_count(&properties(), 8, None), vk::SampleCountFlags::TYPE_8);
+    assert_eq!(PhysicalDevice::pick_sample_count(&properties(), 64, None), vk::SampleCountFlags::TYPE_8);
+  }
+
+  #[test]
+  fn sample_count_rounds_down() {
+    assert_eq!(PhysicalDevice::pick_sample_count(&properties(), 3, None), vk::SampleCountFlags::TYPE_2);
+    assert_eq!(PhysicalDevice::pick_sample_count(&properties(), 7, None), vk::SampleCountFlags::TYPE_4);
+  }
+
+  #[test]
+  fn sample_count_limited_by_depth() {
+    let properties = properties();
+    assert_eq!(PhysicalDevice::pick_sample_count(&properties, 8, Some(vk::Format::D32_SFLOAT)), vk::SampleCountFlags::TYPE_4);
+    assert_eq!(PhysicalDevice::pick_sample_count(&properties, 8, Some(vk::Format::D24_UNORM_S8_UINT)), vk::SampleCountFlags::TYPE_2);
+
+    // Only counts supported by both are used, even if that's none above 1
+    let mut properties = properties;
+    properties.limits.framebuffer_color_sample_counts = vk::SampleCountFlags::TYPE_1 | vk::SampleCountFlags::TYPE_8;
+    assert_eq!(PhysicalDevice::pick_sample_count(&properties, 8, None), vk::SampleCountFlags::TYPE_8);
+    assert_eq!(PhysicalDevice::pick_sample_count(&properties, 8, Some(vk::Format::D32_SFLOAT)), vk::SampleCountFlags::TYPE_1);
+  }
+
+  #[test]
+  fn sample_count_of_one() {
+    assert_eq!(PhysicalDevice::pick_sample_count(&properties(), 1, None), vk::SampleCountFlags::TYPE_1);
+    assert_eq!(PhysicalDevice::pick_sample_count(&properties(), 1, Some(vk::Format::D24_UNORM_S8_UINT)), vk::SampleCountFlags::TYPE_1);
+    assert_eq!(PhysicalDevice::pick_sample_count(&properties(), 0, None), vk::SampleCountFlags::TYPE_1);
+  }
+
+  #[test]
+  fn matches_name_ignoring_case() {
+    let candidate = candidate(0, "llvmpipe (LLVM 15.0.7, 256 bits)", vk::PhysicalDeviceType::CPU);
+    assert!(candidate.matches(&DeviceSelection::Name("llvmpipe".to_string())));
+    assert!(candidate.matches(&DeviceSelection::Name("LLVMpipe".to_string())));
+    assert!(candidate.matches(&DeviceSelection::Name("llvm 15".to_string())));
+    assert!(!candidate.matches(&DeviceSelection::Name("GeForce".to_string())));
+  }
+
+  #[test]
+  fn matches_index() {
+    let candidate = candidate(1, "NVIDIA GeForce RTX 4090", vk::PhysicalDeviceType::DISCRETE_GPU);
+    assert!(candidate.matches(&DeviceSelection::Index(1)));
+    assert!(!candidate.matches(&DeviceSelection::Index(0)));
+  }
+
+  #[test]
+  fn matches_type() {
+    let candidate = candidate(0, "Intel(R) UHD Graphics 620", vk::PhysicalDeviceType::INTEGRATED_GPU);
+    assert!(candidate.matches(&DeviceSelection::Type(vk::PhysicalDeviceType::INTEGRATED_GPU)));
+    assert!(!candidate.matches(&DeviceSelection::Type(vk::PhysicalDeviceType::DISCRETE_GPU)));
   }
 }
\ No newline at end of file
@@ -1,10 +1,72 @@
+use std::path::{Path, PathBuf};
+
 use ash::vk;
-use super::swapchain::*;
 use super::vertex::*;
+use super::error::*;
+use super::shader::*;
+use super::reflection::*;
+use super::descriptor_layout_cache::*;
+use crate::renderer::{PipelineDescription, ShaderSource, Topology, CullMode};
+
+// How a pipeline uses the depth buffer. Ignored when the render pass has no depth attachment
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DepthSettings {
+  pub test: bool, // Discard fragments that fail compare_op against the depth buffer
+  pub write: bool, // Write the depth of fragments that pass into the depth buffer
+  pub compare_op: vk::CompareOp,
+}
+
+impl DepthSettings {
+  // Drawn in submission order, without touching the depth buffer (e.g. UI on top of everything)
+  pub const DISABLED: DepthSettings = DepthSettings { test: false, write: false, compare_op: vk::CompareOp::ALWAYS };
+}
+
+impl Default for DepthSettings {
+  // Closer fragments win, fragments at the same depth are drawn in submission order (so flat 2D scenes look the same as without depth)
+  fn default() -> Self {
+    DepthSettings { test: true, write: true, compare_op: vk::CompareOp::LESS_OR_EQUAL }
+  }
+}
+
+// How a pipeline's output is combined with what's already in the color attachment
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlendMode {
+  Opaque, // Overwrite
+  Alpha, // Blend by the output's alpha, for sprites and anything else with transparency
+  Additive, // Add the output (scaled by its alpha), for glows and particles
+  Multiply, // Multiply with the output, for shadows and tinting
+}
+
+impl BlendMode {
+  pub fn get_attachment_state(self) -> vk::PipelineColorBlendAttachmentState {
+    let (blend_enable, src_color, dst_color) = match self {
+      BlendMode::Opaque => (false, vk::BlendFactor::ONE, vk::BlendFactor::ZERO),
+      // αsrc+(1-α)dst is essentially linearly blending the source and destination by the alpha
+      BlendMode::Alpha => (true, vk::BlendFactor::SRC_ALPHA, vk::BlendFactor::ONE_MINUS_SRC_ALPHA),
+      BlendMode::Additive => (true, vk::BlendFactor::SRC_ALPHA, vk::BlendFactor::ONE),
+      BlendMode::Multiply => (true, vk::BlendFactor::DST_COLOR, vk::BlendFactor::ZERO),
+    };
+    vk::PipelineColorBlendAttachmentState::builder()
+      .blend_enable(blend_enable)
+      .src_color_blend_factor(src_color)
+      .dst_color_blend_factor(dst_color)
+      .color_blend_op(vk::BlendOp::ADD)
+      .src_alpha_blend_factor(vk::BlendFactor::SRC_ALPHA)
+      .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+      .alpha_blend_op(vk::BlendOp::ADD)
+      .color_write_mask(
+          vk::ColorComponentFlags::R
+              | vk::ColorComponentFlags::G
+              | vk::ColorComponentFlags::B
+              | vk::ColorComponentFlags::A,
+      )
+      .build()
+  }
+}
 
 // The pipeline defines the shaders, input and output data, and the pipeline layout
 // which defines the binding of the shaders to the pipeline.
-// Pipelines are fixed after creation, but you can have multiple pipelines
+// Pipelines are fixed after creation, but you can have multiple pipelines (see PipelineBuilder)
 pub struct Pipeline {
   pub pipeline: vk::Pipeline,
   pub layout: vk::PipelineLayout
@@ -18,18 +80,230 @@ impl Pipeline {
     }
   }
 
-  pub fn init(logical_device: &ash::Device, swapchain: &VulkanSwapchain, renderpass: &vk::RenderPass) -> Result<Pipeline, vk::Result> {
+  // The pipeline everything is drawn with for now: shader.vert and shader.frag with alpha blending, and the rest of PipelineBuilder's defaults.
+  // With a shader directory the shaders are loaded from it, so the pipeline can be rebuilt when they change.
+  // If they can't be loaded the copies compiled into the binary are used instead
+  pub fn default_builder(shader_directory: Option<&Path>) -> PipelineBuilder {
+    let builder = PipelineBuilder::new(
+      vk_shader_macros::include_glsl!("./shaders/shader.vert", kind: vert), // Kind is redundant with the file extension, but it's here for clarity
+      vk_shader_macros::include_glsl!("./shaders/shader.frag", kind: frag),
+    );
+    match shader_directory {
+      Some(directory) => match builder.clone().shader_files(&directory.join("shader.vert"), &directory.join("shader.frag")) {
+        Ok(builder) => builder,
+        Err(error) => {
+          log::warn!("{}, using the built in shaders instead. They won't be reloaded when changed.", error);
+          builder
+        }
+      },
+      None => builder,
+    }
+  }
+
+  // A builder with the settings of a backend neutral description. The default shaders come from the shader directory like in
+  // default_builder, but custom shader files that can't be loaded are an error. The samples and layout still need to be set
+  pub fn description_builder(description: &PipelineDescription, shader_directory: Option<&Path>) -> Result<PipelineBuilder, RendererError> {
+    let builder = match &description.shaders {
+      ShaderSource::Default => Pipeline::default_builder(shader_directory),
+      ShaderSource::Files { vertex, fragment } => PipelineBuilder::new(&[], &[]).shader_files(vertex, fragment)?,
+      ShaderSource::Spirv { vertex, fragment } => PipelineBuilder::new(vertex, fragment),
+    };
+    let topology = match description.topology {
+      Topology::TriangleList => vk::PrimitiveTopology::TRIANGLE_LIST,
+      Topology::TriangleStrip => vk::PrimitiveTopology::TRIANGLE_STRIP,
+      Topology::LineList => vk::PrimitiveTopology::LINE_LIST,
+      Topology::LineStrip => vk::PrimitiveTopology::LINE_STRIP,
+      Topology::PointList => vk::PrimitiveTopology::POINT_LIST,
+    };
+    let cull_mode = match description.cull_mode {
+      CullMode::None => vk::CullModeFlags::NONE,
+      CullMode::Front => vk::CullModeFlags::FRONT,
+      CullMode::Back => vk::CullModeFlags::BACK,
+    };
+    Ok(builder
+      .blend_mode(description.blend_mode)
+      .depth(DepthSettings { test: description.depth_test, write: description.depth_write, ..DepthSettings::default() })
+      .topology(topology)
+      .cull_mode(cull_mode, vk::FrontFace::COUNTER_CLOCKWISE)
+      .polygon_mode(if description.wireframe { vk::PolygonMode::LINE } else { vk::PolygonMode::FILL }))
+  }
+}
+
+// Describes a graphics pipeline, so pipelines that only differ in a few settings (a wireframe view, lines, another material)
+// don't each need their own copy of the whole setup. Everything but the shaders has a default:
+// Vertex and InstanceData input, triangle lists, filled polygons, no culling, counter-clockwise front faces, alpha blending,
+// the default DepthSettings, 1 sample per pixel and no descriptor sets or push constants.
+// The viewport and scissor are always dynamic state, see VulkanApp::fill_commandbuffer
+#[derive(Clone, Debug)]
+pub struct PipelineBuilder {
+  vertex_shader: Vec<u32>, // SPIR-V
+  fragment_shader: Vec<u32>, // SPIR-V
+  shader_paths: Option<(PathBuf, PathBuf)>, // The (canonicalized) files the vertex and fragment shaders were loaded from, if they were
+  vertex_bindings: Vec<vk::VertexInputBindingDescription>,
+  vertex_attributes: Vec<vk::VertexInputAttributeDescription>,
+  topology: vk::PrimitiveTopology,
+  polygon_mode: vk::PolygonMode,
+  cull_mode: vk::CullModeFlags,
+  front_face: vk::FrontFace,
+  blend_mode: BlendMode,
+  depth: DepthSettings,
+  samples: vk::SampleCountFlags,
+  set_layouts: Vec<vk::DescriptorSetLayout>,
+  set_bindings: Vec<Option<Vec<DescriptorBinding>>>, // The bindings of each set layout, if known, to check the shaders against
+  push_constant_ranges: Vec<vk::PushConstantRange>,
+}
+
+impl PipelineBuilder {
+  // vertex_shader and fragment_shader are SPIR-V, e.g. from vk_shader_macros::include_glsl! or load_shader. Both use main as their entry point
+  pub fn new(vertex_shader: &[u32], fragment_shader: &[u32]) -> PipelineBuilder {
+    PipelineBuilder {
+      vertex_shader: vertex_shader.to_vec(),
+      fragment_shader: fragment_shader.to_vec(),
+      shader_paths: None,
+      // Binding 0 is per vertex, binding 1 per instance
+      vertex_bindings: vec![Vertex::get_binding_description()[0], InstanceData::get_binding_description()],
+      vertex_attributes: Vertex::get_attribute_descriptions().iter().chain(InstanceData::get_attribute_descriptions().iter()).copied().collect(),
+      topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+      polygon_mode: vk::PolygonMode::FILL,
+      cull_mode: vk::CullModeFlags::NONE,
+      front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+      blend_mode: BlendMode::Alpha,
+      depth: DepthSettings::default(),
+      samples: vk::SampleCountFlags::TYPE_1,
+      set_layouts: vec![],
+      set_bindings: vec![],
+      push_constant_ranges: vec![],
+    }
+  }
+
+  pub fn shaders(mut self, vertex_shader: &[u32], fragment_shader: &[u32]) -> PipelineBuilder {
+    self.vertex_shader = vertex_shader.to_vec();
+    self.fragment_shader = fragment_shader.to_vec();
+    self.shader_paths = None;
+    self
+  }
+
+  // Load the shaders from files (see load_shader), remembering where they came from so they can be reloaded
+  pub fn shader_files(mut self, vertex_path: &Path, fragment_path: &Path) -> Result<PipelineBuilder, RendererError> {
+    self.vertex_shader = load_shader(vertex_path)?;
+    self.fragment_shader = load_shader(fragment_path)?;
+    // Loading succeeded so the files exist, canonicalizing them only fails in odd cases (like a file being removed right after)
+    let canonicalize = |path: &Path| std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    self.shader_paths = Some((canonicalize(vertex_path), canonicalize(fragment_path)));
+    Ok(self)
+  }
+
+  // Whether the shaders were loaded from the given (canonicalized) file
+  pub fn uses_shader_file(&self, path: &Path) -> bool {
+    match &self.shader_paths {
+      Some((vertex_path, fragment_path)) => vertex_path == path || fragment_path == path,
+      None => false,
+    }
+  }
+
+  // A copy of the builder with its shaders loaded from their files again. Builders whose shaders didn't come from files are just copied
+  pub fn reload_shaders(&self) -> Result<PipelineBuilder, RendererError> {
+    match &self.shader_paths {
+      Some((vertex_path, fragment_path)) => self.clone().shader_files(vertex_path, fragment_path),
+      None => Ok(self.clone()),
+    }
+  }
+
+  // What to pass as input to the vertex shader: the vertex buffers that will be bound and the attributes read from them
+  pub fn vertex_layout(mut self, bindings: &[vk::VertexInputBindingDescription], attributes: &[vk::VertexInputAttributeDescription]) -> PipelineBuilder {
+    self.vertex_bindings = bindings.to_vec();
+    self.vertex_attributes = attributes.to_vec();
+    self
+  }
+
+  // How the vertices make up primitives (e.g. LINE_LIST for line drawing)
+  pub fn topology(mut self, topology: vk::PrimitiveTopology) -> PipelineBuilder {
+    self.topology = topology;
+    self
+  }
+
+  // LINE draws wireframes. Anything but FILL needs the fillModeNonSolid device feature
+  pub fn polygon_mode(mut self, polygon_mode: vk::PolygonMode) -> PipelineBuilder {
+    self.polygon_mode = polygon_mode;
+    self
+  }
+
+  // Which faces to skip (e.g. BACK for closed 3D meshes), and which winding order counts as the front
+  pub fn cull_mode(mut self, cull_mode: vk::CullModeFlags, front_face: vk::FrontFace) -> PipelineBuilder {
+    self.cull_mode = cull_mode;
+    self.front_face = front_face;
+    self
+  }
+
+  pub fn blend_mode(mut self, blend_mode: BlendMode) -> PipelineBuilder {
+    self.blend_mode = blend_mode;
+    self
+  }
+
+  pub fn depth(mut self, depth: DepthSettings) -> PipelineBuilder {
+    self.depth = depth;
+    self
+  }
+
+  // Must match the attachments of the render pass the pipeline is used in
+  pub fn samples(mut self, samples: vk::SampleCountFlags) -> PipelineBuilder {
+    self.samples = samples;
+    self
+  }
+
+  // The layouts of the descriptor sets the shaders use (in set order), and the push constants they use.
+  // The shaders can only be checked against the sets' bindings with descriptor_layout
+  pub fn layout(mut self, set_layouts: &[vk::DescriptorSetLayout], push_constant_ranges: &[vk::PushConstantRange]) -> PipelineBuilder {
+    self.set_layouts = set_layouts.to_vec();
+    self.set_bindings = vec![None; set_layouts.len()];
+    self.push_constant_ranges = push_constant_ranges.to_vec();
+    self
+  }
+
+  // Like layout, but with the bindings of each descriptor set (in set order), their layouts coming from the cache
+  pub fn descriptor_layout(
+    mut self, logical_device: &ash::Device, layout_cache: &mut DescriptorLayoutCache, sets: &[&[DescriptorBinding]],
+    push_constant_ranges: &[vk::PushConstantRange],
+  ) -> Result<PipelineBuilder, RendererError> {
+    self.set_layouts = sets.iter().map(|bindings| layout_cache.get_layout(logical_device, bindings)).collect::<Result<_, _>>()?;
+    self.set_bindings = sets.iter().map(|bindings| Some(bindings.to_vec())).collect();
+    self.push_constant_ranges = push_constant_ranges.to_vec();
+    Ok(self)
+  }
+
+  // The layout generated from what the shaders declare: a set layout for every set they use and a push constant range covering
+  // their push constants. Stages get exactly what they use, so the sets are only compatible with pipelines whose shaders use them the same way
+  pub fn reflected_layout(self, logical_device: &ash::Device, layout_cache: &mut DescriptorLayoutCache) -> Result<PipelineBuilder, RendererError> {
+    let shaders = self.reflect()?;
+    let sets = ShaderReflection::descriptor_sets(&shaders);
+    let sets: Vec<&[DescriptorBinding]> = sets.iter().map(|bindings| bindings.as_slice()).collect();
+    self.descriptor_layout(logical_device, layout_cache, &sets, &ShaderReflection::push_constant_ranges(&shaders))
+  }
+
+  // What the vertex and fragment shaders declare
+  pub fn reflect(&self) -> Result<[ShaderReflection; 2], RendererError> {
+    Ok([ShaderReflection::reflect(&self.vertex_shader)?, ShaderReflection::reflect(&self.fragment_shader)?])
+  }
+
+  // Create the pipeline (and its layout) for subpass 0 of renderpass, through pipeline_cache (which can be null). The builder can be kept around
+  // to build it again (e.g. after a resize), which the cache makes cheap.
+  // The shaders are checked against the vertex input and layout first, so a mismatch is an error instead of garbage on screen (or a crash)
+  pub fn build(&self, logical_device: &ash::Device, renderpass: vk::RenderPass, pipeline_cache: vk::PipelineCache) -> Result<Pipeline, RendererError> {
+    ShaderReflection::validate(&self.reflect()?, &self.vertex_bindings, &self.vertex_attributes, &self.set_bindings, &self.push_constant_ranges)?;
+
     let mainfunctionname = std::ffi::CString::new("main").unwrap();
 
     // Define the items being included in the pipeline
-    let vertexshader_createinfo = vk::ShaderModuleCreateInfo::builder().code(
-      vk_shader_macros::include_glsl!("./shaders/shader.vert", kind: vert), // Kind is redundant with the file extension, but it's here for clarity
-    );
-    let vertexshader_module = unsafe { logical_device.create_shader_module(&vertexshader_createinfo, None)? };
-    let fragmentshader_createinfo = vk::ShaderModuleCreateInfo::builder().code(
-      vk_shader_macros::include_glsl!("./shaders/shader.frag", kind: frag), // Kind is redundant with the file extension, but it's here for clarity
-    );
-    let fragmentshader_module = unsafe { logical_device.create_shader_module(&fragmentshader_createinfo, None)? };
+    let vertexshader_createinfo = vk::ShaderModuleCreateInfo::builder().code(&self.vertex_shader);
+    let vertexshader_module = unsafe { logical_device.create_shader_module(&vertexshader_createinfo, None).map_err(RendererError::Shader)? };
+    let fragmentshader_createinfo = vk::ShaderModuleCreateInfo::builder().code(&self.fragment_shader);
+    let fragmentshader_module = match unsafe { logical_device.create_shader_module(&fragmentshader_createinfo, None) } {
+      Ok(module) => module,
+      Err(error) => {
+        unsafe { logical_device.destroy_shader_module(vertexshader_module, None) };
+        return Err(RendererError::Shader(error));
+      }
+    };
     let vertexshader_stage = vk::PipelineShaderStageCreateInfo::builder()
       .stage(vk::ShaderStageFlags::VERTEX)
       .module(vertexshader_module)
@@ -43,83 +317,60 @@ impl Pipeline {
     let shader_stages = [vertexshader_stage.build(), fragmentshader_stage.build()];
 
     // What to pass as input to the vertex shader
-    let vertex_attrib_descs = Vertex::get_attribute_descriptions(); /*[vk::VertexInputAttributeDescription {
-        location: 0, // Location of the attribute in the shader
-        binding: 0, // Binding of the attribute in the shader (e.g. different for color and position for example)
-        offset: 0, // Offset of the attribute in the vertex struct (in bytes)
-        format: vk::Format::R32G32B32A32_SFLOAT, // Four 32-bit floats (R G B A)
-    }];*/
-
-    // What to pass as input to the vertex shader
-    let vertex_binding_descs = Vertex::get_binding_description(); /*[vk::VertexInputBindingDescription {
-        binding: 0, // Binding of the attribute in the shader (e.g. different for color and position for example)
-        stride: 16, // Stride of the attribute in the vertex struct (in bytes)
-        input_rate: vk::VertexInputRate::VERTEX, // Data changes from vertex to vertex, other option is INSTANCE for instanced rendering
-    }];*/
-
     let vertex_input_info = vk::PipelineVertexInputStateCreateInfo::builder()
-      .vertex_attribute_descriptions(&vertex_attrib_descs)
-      .vertex_binding_descriptions(&vertex_binding_descs);
+      .vertex_attribute_descriptions(&self.vertex_attributes)
+      .vertex_binding_descriptions(&self.vertex_bindings);
 
     // Specify how to interpret the vertex data
     let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo::builder()
-      .topology(vk::PrimitiveTopology::TRIANGLE_LIST); // Switch between POINT_LIST and TRIANGLE_LIST
-
-    // Create the viewport info
-    let viewports = [vk::Viewport {
-      x: 0.0,
-      y: 0.0,
-      width: swapchain.extent.width as f32,
-      height: swapchain.extent.height as f32,
-      min_depth: 0.0,
-      max_depth: 1.0,
-    }];
-
-    // Create the scissor info (disables drawing outside of the viewport)
-    let scissors = [vk::Rect2D {
-      offset: vk::Offset2D { x: 0, y: 0 },
-      extent: swapchain.extent,
-    }];
-
-    // Set the viewport
+      .topology(self.topology);
+
+    // One viewport and scissor. Their values are dynamic (set in the command buffer), so the pipeline doesn't depend on the
+    // framebuffer size and survives a resize, and the scissor can change between draws (e.g. to clip UI)
     let viewport_info = vk::PipelineViewportStateCreateInfo::builder()
-      .viewports(&viewports)
-      .scissors(&scissors);
+      .viewport_count(1)
+      .scissor_count(1);
+    let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+    let dynamic_state_info = vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_states);
 
     // Create the rasterizer info (defines how the pixels are rasterized / how to draw the polygons)
     let rasterizer_info = vk::PipelineRasterizationStateCreateInfo::builder()
       .line_width(1.0) // Set the line width
-      .front_face(vk::FrontFace::COUNTER_CLOCKWISE) // Set the front face to be counter-clockwise
-      .cull_mode(vk::CullModeFlags::NONE) // We don't want to cull (ignore) anything
-      .polygon_mode(vk::PolygonMode::FILL); // We want to fill the polygons, we could also draw wireframe polygons using lines
-  
-    // Create the multisampling info (defines how to sample the pixels), we don't want to use multisampling (1 sample per pixel)
+      .front_face(self.front_face)
+      .cull_mode(self.cull_mode)
+      .polygon_mode(self.polygon_mode);
+
+    // Create the multisampling info (defines how to sample the pixels), 1 sample per pixel means no multisampling
     let multisampler_info = vk::PipelineMultisampleStateCreateInfo::builder()
-      .rasterization_samples(vk::SampleCountFlags::TYPE_1);
-    
-    // Create the depth stencil info (defines how to handle the depth buffer). Essentially, we want alpha/trasparency to be handled as normal
-    let colourblend_attachments = [vk::PipelineColorBlendAttachmentState::builder()
-      .blend_enable(true)
-      .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
-      .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA) // αsrc+(1-α)dst is essentially linearly blending the source and destination by the alpha
-      .color_blend_op(vk::BlendOp::ADD)
-      .src_alpha_blend_factor(vk::BlendFactor::SRC_ALPHA)
-      .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
-      .alpha_blend_op(vk::BlendOp::ADD)
-      .color_write_mask(
-          vk::ColorComponentFlags::R
-              | vk::ColorComponentFlags::G
-              | vk::ColorComponentFlags::B
-              | vk::ColorComponentFlags::A,
-      )
-      .build()];
-    
+      .rasterization_samples(self.samples);
+
+    // Create the depth stencil info (defines how to handle the depth buffer)
+    let depthstencil_info = vk::PipelineDepthStencilStateCreateInfo::builder()
+      .depth_test_enable(self.depth.test)
+      .depth_write_enable(self.depth.write)
+      .depth_compare_op(self.depth.compare_op)
+      .depth_bounds_test_enable(false)
+      .stencil_test_enable(false);
+
+    // Create the colour blend info
+    let colourblend_attachments = [self.blend_mode.get_attachment_state()];
     let colourblend_info =
       vk::PipelineColorBlendStateCreateInfo::builder().attachments(&colourblend_attachments);
 
     // Create the pipeline layout info (defines data attached to the pipeline but not the vertices)
-    let pipelinelayout_info = vk::PipelineLayoutCreateInfo::builder();
-    let pipelinelayout = unsafe { logical_device.create_pipeline_layout(&pipelinelayout_info, None) }?;
+    let pipelinelayout_info = vk::PipelineLayoutCreateInfo::builder()
+      .set_layouts(&self.set_layouts)
+      .push_constant_ranges(&self.push_constant_ranges);
+    let pipelinelayout = match unsafe { logical_device.create_pipeline_layout(&pipelinelayout_info, None) } {
+      Ok(pipelinelayout) => pipelinelayout,
+      Err(error) => {
+        unsafe {
+          logical_device.destroy_shader_module(fragmentshader_module, None);
+          logical_device.destroy_shader_module(vertexshader_module, None);
+        }
+        return Err(RendererError::Pipeline(error));
+      }
+    };
     // Create the pipeline info (defines the data attached to the pipeline and the vertices)
     let pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
       .stages(&shader_stages)
@@ -128,28 +379,35 @@ impl Pipeline {
       .viewport_state(&viewport_info)
       .rasterization_state(&rasterizer_info)
       .multisample_state(&multisampler_info)
+      .depth_stencil_state(&depthstencil_info)
       .color_blend_state(&colourblend_info)
+      .dynamic_state(&dynamic_state_info)
       .layout(pipelinelayout)
-      .render_pass(*renderpass)
+      .render_pass(renderpass)
       .subpass(0);
-  
+
     // Create the pipeline
-    let graphicspipeline = unsafe {
+    let graphicspipelines = unsafe {
       logical_device
         .create_graphics_pipelines(
-            vk::PipelineCache::null(),
+            pipeline_cache,
             &[pipeline_info.build()],
             None,
-        )
-        .expect("A problem with the pipeline creation") // Note that we can create multiple pipelines here, but we only need one right now
-        // Note this is expensive to do, we should do it only during start up and loading screens if possible
-        // We can even cache old pipelines and reuse them, but we aren't for now
-    }[0];
+        ) // Note that we can create multiple pipelines here, but we only need one right now
+        // Note this is expensive to do (unless it's in the cache), we should do it only during start up and loading screens if possible
+    };
     unsafe {
       // Destroy the shader modules, they are engrained into the pipeline and thus no longer needed
       logical_device.destroy_shader_module(fragmentshader_module, None);
       logical_device.destroy_shader_module(vertexshader_module, None);
     }
+    let graphicspipeline = match graphicspipelines {
+      Ok(pipelines) => pipelines[0],
+      Err((_, result)) => {
+        unsafe { logical_device.destroy_pipeline_layout(pipelinelayout, None) };
+        return Err(RendererError::Pipeline(result));
+      }
+    };
     Ok(Pipeline {
       pipeline: graphicspipeline,
       layout: pipelinelayout,
@@ -0,0 +1,175 @@
+use std::path::{Path, PathBuf};
+
+use ash::vk;
+
+use super::error::*;
+
+// The size of the header the driver puts at the start of the cache data (VK_PIPELINE_CACHE_HEADER_VERSION_ONE)
+const HEADER_SIZE: usize = 16 + vk::UUID_SIZE;
+
+// A pipeline cache shared by every pipeline creation, so pipelines that were built before (in this run or a previous one)
+// don't have to be compiled again by the driver. It's loaded from a file at startup and written back with save.
+// The file name includes the vendor and device IDs and the driver's pipelineCacheUUID, so each GPU and driver version
+// gets its own cache, and data from anything else is never handed to the driver
+pub struct PipelineCache {
+  pub cache: vk::PipelineCache,
+  path: Option<PathBuf>, // Where the cache is loaded from and saved to, None to only cache for this run
+}
+
+impl PipelineCache {
+  // Create the cache, with what was saved in directory for this device (if anything). A missing, unreadable or stale file just
+  // means starting with an empty cache. Without a directory nothing is loaded or saved
+  pub fn load(logical_device: &ash::Device, properties: &vk::PhysicalDeviceProperties, directory: Option<&Path>) -> Result<PipelineCache, RendererError> {
+    let path = directory.map(|directory| directory.join(PipelineCache::file_name(properties)));
+    let data = match &path {
+      Some(path) => match std::fs::read(path) {
+        Ok(data) if PipelineCache::is_valid(&data, properties) => data,
+        Ok(_) => {
+          log::warn!("Ignoring pipeline cache {}, it was made by another device or driver.", path.display());
+          vec![]
+        }
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => vec![], // Nothing saved yet
+        Err(error) => {
+          log::warn!("Failed to read pipeline cache {}: {}", path.display(), error);
+          vec![]
+        }
+      },
+      None => vec![],
+    };
+
+    let cache_info = vk::PipelineCacheCreateInfo::builder().initial_data(&data);
+    let cache = match unsafe { logical_device.create_pipeline_cache(&cache_info, None) } {
+      Ok(cache) => cache,
+      Err(_) if !data.is_empty() => {
+        // The header matched, but the driver still didn't like the data (e.g. the file got truncated)
+        log::warn!("The driver rejected the pipeline cache, starting with an empty one.");
+        unsafe { logical_device.create_pipeline_cache(&vk::PipelineCacheCreateInfo::default(), None)? }
+      }
+      Err(error) => return Err(error.into()),
+    };
+    if !data.is_empty() {
+      log::info!("Loaded pipeline cache ({} bytes).", data.len());
+    }
+    Ok(PipelineCache { cache, path })
+  }
+
+  // e.g. pipeline_cache_10de_2684_<32 hex digits of the UUID>.bin
+  fn file_name(properties: &vk::PhysicalDeviceProperties) -> String {
+    let uuid: String = properties.pipeline_cache_uuid.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("pipeline_cache_{:04x}_{:04x}_{}.bin", properties.vendor_id, properties.device_id, uuid)
+  }
+
+  // Whether data starts with a header for this device and driver. Drivers are meant to check this themselves, but not all do
+  fn is_valid(data: &[u8], properties: &vk::PhysicalDeviceProperties) -> bool {
+    if data.len() < HEADER_SIZE {
+      return false;
+    }
+    let word = |index: usize| u32::from_le_bytes([data[index * 4], data[index * 4 + 1], data[index * 4 + 2], data[index * 4 + 3]]);
+    word(0) as usize >= HEADER_SIZE // The header's length
+      && word(1) == vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32
+      && word(2) == properties.vendor_id
+      && word(3) == properties.device_id
+      && data[16..HEADER_SIZE] == properties.pipeline_cache_uuid
+  }
+
+  // Write the cache to its file, so the next run can use it. Does nothing if it has no file
+  pub fn save(&self, logical_device: &ash::Device) -> Result<(), RendererError> {
+    let path = match &self.path {
+      Some(path) => path,
+      None => return Ok(()),
+    };
+    let data = unsafe { logical_device.get_pipeline_cache_data(self.cache)? };
+    let save_error = |error| RendererError::PipelineCacheFile { path: path.clone(), error };
+    if let Some(directory) = path.parent() {
+      std::fs::create_dir_all(directory).map_err(save_error)?;
+    }
+    // Write a temporary file and move it into place, so a crash halfway through can't leave a corrupt cache behind
+    let temporary_path = path.with_extension("tmp");
+    std::fs::write(&temporary_path, &data).map_err(save_error)?;
+    std::fs::rename(&temporary_path, path).map_err(save_error)?;
+    Ok(())
+  }
+
+  pub fn destroy(&self, logical_device: &ash::Device) {
+    unsafe { logical_device.destroy_pipeline_cache(self.cache, None) };
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn properties() -> vk::PhysicalDeviceProperties {
+    vk::PhysicalDeviceProperties {
+      vendor_id: 0x10de,
+      device_id: 0x2684,
+      pipeline_cache_uuid: [7; vk::UUID_SIZE],
+      ..Default::default()
+    }
+  }
+
+  // What a driver would hand back from vkGetPipelineCacheData, a header followed by some data of its own
+  fn cache_data(version: u32, vendor_id: u32, device_id: u32, uuid: [u8; vk::UUID_SIZE]) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(&(HEADER_SIZE as u32).to_le_bytes());
+    data.extend_from_slice(&version.to_le_bytes());
+    data.extend_from_slice(&vendor_id.to_le_bytes());
+    data.extend_from_slice(&device_id.to_le_bytes());
+    data.extend_from_slice(&uuid);
+    data.extend_from_slice(&[1, 2, 3, 4]);
+    data
+  }
+
+  fn matching_data() -> Vec<u8> {
+    let properties = properties();
+    cache_data(1, properties.vendor_id, properties.device_id, properties.pipeline_cache_uuid)
+  }
+
+  #[test]
+  fn matching_header() {
+    assert!(PipelineCache::is_valid(&matching_data(), &properties()));
+    assert!(PipelineCache::is_valid(&matching_data()[..HEADER_SIZE], &properties())); // Just the header is still valid
+  }
+
+  #[test]
+  fn too_short() {
+    assert!(!PipelineCache::is_valid(&[], &properties()));
+    assert!(!PipelineCache::is_valid(&matching_data()[..HEADER_SIZE - 1], &properties()));
+  }
+
+  #[test]
+  fn wrong_header_version() {
+    let properties = properties();
+    let data = cache_data(2, properties.vendor_id, properties.device_id, properties.pipeline_cache_uuid);
+    assert!(!PipelineCache::is_valid(&data, &properties));
+  }
+
+  #[test]
+  fn wrong_vendor() {
+    let properties = properties();
+    let data = cache_data(1, 0x1002, properties.device_id, properties.pipeline_cache_uuid);
+    assert!(!PipelineCache::is_valid(&data, &properties));
+  }
+
+  #[test]
+  fn wrong_device() {
+    let properties = properties();
+    let data = cache_data(1, properties.vendor_id, 0x2704, properties.pipeline_cache_uuid);
+    assert!(!PipelineCache::is_valid(&data, &properties));
+  }
+
+  #[test]
+  fn wrong_uuid() {
+    // e.g. the driver was updated since the cache was saved
+    let properties = properties();
+    let mut uuid = properties.pipeline_cache_uuid;
+    uuid[vk::UUID_SIZE - 1] = 8;
+    let data = cache_data(1, properties.vendor_id, properties.device_id, uuid);
+    assert!(!PipelineCache::is_valid(&data, &properties));
+  }
+
+  #[test]
+  fn file_name_includes_device_and_driver() {
+    assert_eq!(PipelineCache::file_name(&properties()), format!("pipeline_cache_10de_2684_{}.bin", "07".repeat(vk::UUID_SIZE)));
+  }
+}
\ No newline at end of file
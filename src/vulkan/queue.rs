@@ -1,29 +1,42 @@
 use ash::vk;
 use super::surface::*;
+use super::error::*;
 
 // Stores the specified queue families for a physical device.
 // Recommened use is to find prefer queue family for each use case and store their index in the struct.
 pub struct QueueFamilies {
   pub graphics: Option<u32>,
   pub transfer: Option<u32>,
+  pub present: Option<u32>, // Can present to the surface. Usually the graphics family, but not on every device. None when headless
 }
 
 impl QueueFamilies {
-  pub fn init(instance: &ash::Instance, physical_device: vk::PhysicalDevice, surface: &VulkanSurface) -> Result<QueueFamilies, vk::Result> {
+  pub fn init(instance: &ash::Instance, physical_device: vk::PhysicalDevice, surface: Option<&VulkanSurface>) -> Result<QueueFamilies, RendererError> {
     let mut queue_families = QueueFamilies {
       graphics: None,
       transfer: None,
+      present: None,
     };
 
     let queue_family_properties = unsafe { instance.get_physical_device_queue_family_properties(physical_device) }; // Get the queue family properties
     //dbg!(&queuefamilyproperties);
     let mut found_graphics_q_index = None; // We need a graphics queue
     let mut found_transfer_q_index = None; // We need a transfer queue
+    let mut found_present_q_index = None; // We need a queue that can present (when there's a surface)
     for (index, qfam) in queue_family_properties.iter().enumerate() {
-      if qfam.queue_count > 0 && qfam.queue_flags.contains(vk::QueueFlags::GRAPHICS) && // We need a graphics queue with at least one queue
-        unsafe { surface.loader.get_physical_device_surface_support(physical_device, index as u32, surface.surface).unwrap() } // Make sure we have surface support (not it's possible that the graphics queue doesn't support this, only the graphics queue)
-      {
-              found_graphics_q_index = Some(index as u32);
+      let supports_surface = match surface { // Whether the family can present to the surface (it's possible that the graphics family can't, and another one can)
+        Some(surface) => qfam.queue_count > 0 && surface.get_physical_device_surface_support(physical_device, index)?,
+        None => false, // Headless has nothing to present to
+      };
+      if qfam.queue_count > 0 && qfam.queue_flags.contains(vk::QueueFlags::GRAPHICS) { // We need a graphics queue with at least one queue
+        // Prefer a graphics family that can present too, so both can use the same queue and swapchain images never change families
+        let graphics_presents = found_graphics_q_index.is_some() && found_graphics_q_index == found_present_q_index;
+        if found_graphics_q_index.is_none() || (supports_surface && !graphics_presents) {
+          found_graphics_q_index = Some(index as u32);
+        }
+      }
+      if supports_surface && (found_present_q_index.is_none() || found_graphics_q_index == Some(index as u32)) {
+        found_present_q_index = Some(index as u32);
       }
       if qfam.queue_count > 0 && qfam.queue_flags.contains(vk::QueueFlags::TRANSFER) { // We need a transfer queue with at least one queue
         // Use first transfer queue found, if there are multiple then prefer the one without graphics support as it's likely to be faster/dedicated hardware
@@ -34,8 +47,16 @@ impl QueueFamilies {
       }
     }
 
+    if found_graphics_q_index.is_none() || found_transfer_q_index.is_none() { // Everything else relies on having both of these
+      return Err(RendererError::NoSuitableDevice);
+    }
+    if surface.is_some() && found_present_q_index.is_none() { // Nothing can present to the window
+      return Err(RendererError::NoSuitableDevice);
+    }
+
     queue_families.graphics = found_graphics_q_index;
     queue_families.transfer = found_transfer_q_index;
+    queue_families.present = found_present_q_index;
 
     Ok(queue_families)
   }
@@ -45,4 +66,5 @@ impl QueueFamilies {
 pub struct Queues {
   pub graphics_queue: vk::Queue,
   pub transfer_queue: vk::Queue,
+  pub present_queue: vk::Queue, // The same queue as graphics_queue when the families are the same, null when headless
 }
\ No newline at end of file
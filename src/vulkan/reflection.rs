@@ -0,0 +1,631 @@
+use std::collections::HashMap;
+
+use ash::vk;
+use rspirv::dr::{Instruction, Operand};
+use rspirv::spirv;
+
+use super::descriptor_layout_cache::*;
+use super::error::*;
+
+// Reflection reads what a shader declares (its vertex inputs, descriptor bindings and push constants) out of its SPIR-V,
+// so it can be checked against what a pipeline provides, or the pipeline layout can be generated from it.
+// Only the entry point called main is reflected, like PipelineBuilder only uses main
+
+// Whether a value is read as a float, a signed or an unsigned integer. Vertex attribute formats have to match the shader input
+// (normalized and scaled formats are read as floats)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NumericType {
+  Float,
+  SInt,
+  UInt,
+}
+
+impl NumericType {
+  // How values of the given vertex attribute format are read by a shader, None for formats that aren't used for vertex input
+  pub fn of_format(format: vk::Format) -> Option<NumericType> {
+    use vk::Format as F;
+    match format {
+      F::R8_UNORM | F::R8_SNORM | F::R8_USCALED | F::R8_SSCALED
+      | F::R8G8_UNORM | F::R8G8_SNORM | F::R8G8_USCALED | F::R8G8_SSCALED
+      | F::R8G8B8_UNORM | F::R8G8B8_SNORM | F::R8G8B8_USCALED | F::R8G8B8_SSCALED
+      | F::B8G8R8_UNORM | F::B8G8R8_SNORM | F::B8G8R8_USCALED | F::B8G8R8_SSCALED
+      | F::R8G8B8A8_UNORM | F::R8G8B8A8_SNORM | F::R8G8B8A8_USCALED | F::R8G8B8A8_SSCALED
+      | F::B8G8R8A8_UNORM | F::B8G8R8A8_SNORM | F::B8G8R8A8_USCALED | F::B8G8R8A8_SSCALED
+      | F::A8B8G8R8_UNORM_PACK32 | F::A8B8G8R8_SNORM_PACK32 | F::A8B8G8R8_USCALED_PACK32 | F::A8B8G8R8_SSCALED_PACK32
+      | F::A2R10G10B10_UNORM_PACK32 | F::A2R10G10B10_SNORM_PACK32 | F::A2R10G10B10_USCALED_PACK32 | F::A2R10G10B10_SSCALED_PACK32
+      | F::A2B10G10R10_UNORM_PACK32 | F::A2B10G10R10_SNORM_PACK32 | F::A2B10G10R10_USCALED_PACK32 | F::A2B10G10R10_SSCALED_PACK32
+      | F::R16_UNORM | F::R16_SNORM | F::R16_USCALED | F::R16_SSCALED | F::R16_SFLOAT
+      | F::R16G16_UNORM | F::R16G16_SNORM | F::R16G16_USCALED | F::R16G16_SSCALED | F::R16G16_SFLOAT
+      | F::R16G16B16_UNORM | F::R16G16B16_SNORM | F::R16G16B16_USCALED | F::R16G16B16_SSCALED | F::R16G16B16_SFLOAT
+      | F::R16G16B16A16_UNORM | F::R16G16B16A16_SNORM | F::R16G16B16A16_USCALED | F::R16G16B16A16_SSCALED | F::R16G16B16A16_SFLOAT
+      | F::R32_SFLOAT | F::R32G32_SFLOAT | F::R32G32B32_SFLOAT | F::R32G32B32A32_SFLOAT
+      | F::R64_SFLOAT | F::R64G64_SFLOAT | F::R64G64B64_SFLOAT | F::R64G64B64A64_SFLOAT
+      | F::B10G11R11_UFLOAT_PACK32 => Some(NumericType::Float), // Normalized and scaled formats are converted to floats
+      F::R8_SINT | F::R8G8_SINT | F::R8G8B8_SINT | F::B8G8R8_SINT | F::R8G8B8A8_SINT | F::B8G8R8A8_SINT | F::A8B8G8R8_SINT_PACK32
+      | F::A2R10G10B10_SINT_PACK32 | F::A2B10G10R10_SINT_PACK32
+      | F::R16_SINT | F::R16G16_SINT | F::R16G16B16_SINT | F::R16G16B16A16_SINT
+      | F::R32_SINT | F::R32G32_SINT | F::R32G32B32_SINT | F::R32G32B32A32_SINT
+      | F::R64_SINT | F::R64G64_SINT | F::R64G64B64_SINT | F::R64G64B64A64_SINT => Some(NumericType::SInt),
+      F::R8_UINT | F::R8G8_UINT | F::R8G8B8_UINT | F::B8G8R8_UINT | F::R8G8B8A8_UINT | F::B8G8R8A8_UINT | F::A8B8G8R8_UINT_PACK32
+      | F::A2R10G10B10_UINT_PACK32 | F::A2B10G10R10_UINT_PACK32
+      | F::R16_UINT | F::R16G16_UINT | F::R16G16B16_UINT | F::R16G16B16A16_UINT
+      | F::R32_UINT | F::R32G32_UINT | F::R32G32B32_UINT | F::R32G32B32A32_UINT
+      | F::R64_UINT | F::R64G64_UINT | F::R64G64B64_UINT | F::R64G64B64A64_UINT => Some(NumericType::UInt),
+      _ => None,
+    }
+  }
+}
+
+// One location the vertex shader reads. A matrix or array input takes up a location per column or element
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ShaderInput {
+  pub location: u32,
+  pub numeric_type: NumericType,
+  pub components: u32, // 1 for a scalar, otherwise the vector size
+}
+
+// A descriptor a shader uses
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ShaderBinding {
+  pub set: u32,
+  pub binding: DescriptorBinding, // Its stages are the stage of the shader
+}
+
+// What a single shader declares
+#[derive(Clone, Debug)]
+pub struct ShaderReflection {
+  pub stage: vk::ShaderStageFlags,
+  pub inputs: Vec<ShaderInput>, // Only for vertex shaders, the inputs of other stages come from the previous stage
+  pub bindings: Vec<ShaderBinding>,
+  pub push_constants: Option<(u32, u32)>, // The offset and size (in bytes) of the push constant block, if it has one
+}
+
+// The parts of a SPIR-V type we care about, by the id of the instruction declaring it
+enum SpirvType {
+  Bool,
+  Int { width: u32, signed: bool },
+  Float { width: u32 },
+  Vector { component: u32, count: u32 },
+  Matrix { column: u32, count: u32 },
+  Array { element: u32, length: u32 },
+  RuntimeArray { element: u32 },
+  Struct { members: Vec<u32> },
+  Pointer { pointee: u32 },
+  Image { dim: spirv::Dim, sampled: u32 }, // sampled is 1 for images read through a sampler, 2 for storage images
+  Sampler,
+  SampledImage,
+  AccelerationStructure,
+  Other,
+}
+
+// Decorations of an id (or of a struct member)
+#[derive(Default)]
+struct Decorations {
+  location: Option<u32>,
+  binding: Option<u32>,
+  set: Option<u32>,
+  offset: Option<u32>,
+  array_stride: Option<u32>,
+  matrix_stride: Option<u32>,
+  built_in: bool,
+  block: bool,
+  buffer_block: bool,
+}
+
+impl Decorations {
+  fn apply(&mut self, decoration: spirv::Decoration, value: Option<u32>) {
+    match decoration {
+      spirv::Decoration::Location => self.location = value,
+      spirv::Decoration::Binding => self.binding = value,
+      spirv::Decoration::DescriptorSet => self.set = value,
+      spirv::Decoration::Offset => self.offset = value,
+      spirv::Decoration::ArrayStride => self.array_stride = value,
+      spirv::Decoration::MatrixStride => self.matrix_stride = value,
+      spirv::Decoration::BuiltIn => self.built_in = true,
+      spirv::Decoration::Block => self.block = true,
+      spirv::Decoration::BufferBlock => self.buffer_block = true,
+      _ => {}
+    }
+  }
+}
+
+fn id_operand(instruction: &Instruction, index: usize) -> Option<u32> {
+  match instruction.operands.get(index) {
+    Some(Operand::IdRef(id)) => Some(*id),
+    _ => None,
+  }
+}
+
+fn literal_operand(instruction: &Instruction, index: usize) -> Option<u32> {
+  match instruction.operands.get(index) {
+    Some(Operand::LiteralInt32(value)) => Some(*value),
+    _ => None,
+  }
+}
+
+// The module, indexed so types and decorations can be looked up by id
+struct Module {
+  types: HashMap<u32, SpirvType>,
+  constants: HashMap<u32, u32>, // 32 bit integer constants (array lengths)
+  decorations: HashMap<u32, Decorations>,
+  member_decorations: HashMap<(u32, u32), Decorations>,
+}
+
+impl Module {
+  fn decorations(&self, id: u32) -> Option<&Decorations> {
+    self.decorations.get(&id)
+  }
+
+  // Follow arrays down to the type they hold, multiplying up how many there are (a runtime sized array counts as one)
+  fn strip_arrays(&self, mut type_id: u32) -> (u32, u32) {
+    let mut count = 1;
+    loop {
+      match self.types.get(&type_id) {
+        Some(SpirvType::Array { element, length }) => {
+          count *= length;
+          type_id = *element;
+        }
+        Some(SpirvType::RuntimeArray { element }) => type_id = *element,
+        _ => return (type_id, count),
+      }
+    }
+  }
+
+  // The numeric type and component count of a scalar or vector
+  fn numeric_type(&self, type_id: u32) -> Option<(NumericType, u32)> {
+    match self.types.get(&type_id)? {
+      SpirvType::Float { .. } => Some((NumericType::Float, 1)),
+      SpirvType::Int { signed: true, .. } => Some((NumericType::SInt, 1)),
+      SpirvType::Int { signed: false, .. } => Some((NumericType::UInt, 1)),
+      SpirvType::Vector { component, count } => self.numeric_type(*component).map(|(numeric_type, _)| (numeric_type, *count)),
+      _ => None,
+    }
+  }
+
+  // The locations a vertex input of the given type takes up, starting at location
+  fn inputs(&self, type_id: u32, location: u32) -> Result<Vec<ShaderInput>, RendererError> {
+    let (type_id, count) = self.strip_arrays(type_id);
+    let (column_type, columns) = match self.types.get(&type_id) {
+      Some(SpirvType::Matrix { column, count }) => (*column, *count),
+      _ => (type_id, 1),
+    };
+    let (numeric_type, components) = self.numeric_type(column_type)
+      .ok_or_else(|| RendererError::ShaderReflection(format!("Unsupported type for the vertex input at location {}", location)))?;
+    Ok((0..count * columns).map(|index| ShaderInput { location: location + index, numeric_type, components }).collect())
+  }
+
+  // The size of a type in bytes, as laid out in a block (going by its Offset, ArrayStride and MatrixStride decorations)
+  fn size(&self, type_id: u32, matrix_stride: Option<u32>) -> u32 {
+    match self.types.get(&type_id) {
+      Some(SpirvType::Bool) => 4,
+      Some(SpirvType::Int { width, .. }) | Some(SpirvType::Float { width }) => width / 8,
+      Some(SpirvType::Vector { component, count }) => self.size(*component, None) * count,
+      Some(SpirvType::Matrix { column, count }) => matrix_stride.unwrap_or_else(|| self.size(*column, None)) * count,
+      Some(SpirvType::Array { element, length }) => {
+        let stride = self.decorations(type_id).and_then(|decorations| decorations.array_stride);
+        stride.unwrap_or_else(|| self.size(*element, matrix_stride)) * length
+      }
+      Some(SpirvType::Struct { members }) => members.iter().enumerate()
+        .map(|(index, &member)| {
+          let decorations = self.member_decorations.get(&(type_id, index as u32));
+          let offset = decorations.and_then(|decorations| decorations.offset).unwrap_or(0);
+          offset + self.size(member, decorations.and_then(|decorations| decorations.matrix_stride))
+        })
+        .max()
+        .unwrap_or(0),
+      _ => 0, // Runtime sized arrays and opaque types don't take up any space in a block
+    }
+  }
+
+  // The descriptor type a resource variable in the given storage class binds with
+  fn descriptor_type(&self, storage_class: spirv::StorageClass, type_id: u32) -> Option<vk::DescriptorType> {
+    let block = |type_id| self.decorations(type_id).map_or((false, false), |decorations| (decorations.block, decorations.buffer_block));
+    match (storage_class, self.types.get(&type_id)?) {
+      (spirv::StorageClass::StorageBuffer, SpirvType::Struct { .. }) => Some(vk::DescriptorType::STORAGE_BUFFER),
+      (spirv::StorageClass::Uniform, SpirvType::Struct { .. }) => match block(type_id) {
+        (_, true) => Some(vk::DescriptorType::STORAGE_BUFFER), // The old way of declaring storage buffers (before SPIR-V 1.3)
+        _ => Some(vk::DescriptorType::UNIFORM_BUFFER),
+      },
+      (_, SpirvType::SampledImage) => Some(vk::DescriptorType::COMBINED_IMAGE_SAMPLER),
+      (_, SpirvType::Sampler) => Some(vk::DescriptorType::SAMPLER),
+      (_, SpirvType::Image { dim: spirv::Dim::DimBuffer, sampled: 2 }) => Some(vk::DescriptorType::STORAGE_TEXEL_BUFFER),
+      (_, SpirvType::Image { dim: spirv::Dim::DimBuffer, .. }) => Some(vk::DescriptorType::UNIFORM_TEXEL_BUFFER),
+      (_, SpirvType::Image { dim: spirv::Dim::DimSubpassData, .. }) => Some(vk::DescriptorType::INPUT_ATTACHMENT),
+      (_, SpirvType::Image { sampled: 2, .. }) => Some(vk::DescriptorType::STORAGE_IMAGE),
+      (_, SpirvType::Image { .. }) => Some(vk::DescriptorType::SAMPLED_IMAGE),
+      (_, SpirvType::AccelerationStructure) => Some(vk::DescriptorType::ACCELERATION_STRUCTURE_KHR),
+      _ => None,
+    }
+  }
+}
+
+impl ShaderReflection {
+  // Reflect a SPIR-V shader
+  pub fn reflect(spirv: &[u32]) -> Result<ShaderReflection, RendererError> {
+    let module = rspirv::dr::load_words(spirv).map_err(|error| RendererError::ShaderReflection(format!("Invalid SPIR-V: {}", error)))?;
+
+    let entry_point = module.entry_points.iter()
+      .find(|entry_point| matches!(entry_point.operands.get(2), Some(Operand::LiteralString(name)) if name == "main"))
+      .ok_or_else(|| RendererError::ShaderReflection("The shader has no main entry point".to_string()))?;
+    let stage = match entry_point.operands.first() {
+      Some(Operand::ExecutionModel(spirv::ExecutionModel::Vertex)) => vk::ShaderStageFlags::VERTEX,
+      Some(Operand::ExecutionModel(spirv::ExecutionModel::Fragment)) => vk::ShaderStageFlags::FRAGMENT,
+      Some(Operand::ExecutionModel(spirv::ExecutionModel::GLCompute)) => vk::ShaderStageFlags::COMPUTE,
+      Some(Operand::ExecutionModel(spirv::ExecutionModel::Geometry)) => vk::ShaderStageFlags::GEOMETRY,
+      Some(Operand::ExecutionModel(spirv::ExecutionModel::TessellationControl)) => vk::ShaderStageFlags::TESSELLATION_CONTROL,
+      Some(Operand::ExecutionModel(spirv::ExecutionModel::TessellationEvaluation)) => vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+      _ => return Err(RendererError::ShaderReflection("Unsupported shader stage".to_string())),
+    };
+    // The variables the entry point uses. Before SPIR-V 1.4 only inputs and outputs are listed, so resources are taken from the whole module
+    let interface: Vec<u32> = (3..entry_point.operands.len()).filter_map(|index| id_operand(entry_point, index)).collect();
+
+    let mut reflected = Module {
+      types: HashMap::new(),
+      constants: HashMap::new(),
+      decorations: HashMap::new(),
+      member_decorations: HashMap::new(),
+    };
+    for annotation in &module.annotations {
+      match annotation.class.opcode {
+        spirv::Op::Decorate => {
+          if let (Some(target), Some(Operand::Decoration(decoration))) = (id_operand(annotation, 0), annotation.operands.get(1)) {
+            reflected.decorations.entry(target).or_default().apply(*decoration, literal_operand(annotation, 2));
+          }
+        }
+        spirv::Op::MemberDecorate => {
+          if let (Some(target), Some(member), Some(Operand::Decoration(decoration))) =
+            (id_operand(annotation, 0), literal_operand(annotation, 1), annotation.operands.get(2)) {
+            reflected.member_decorations.entry((target, member)).or_default().apply(*decoration, literal_operand(annotation, 3));
+          }
+        }
+        _ => {}
+      }
+    }
+
+    let mut variables = vec![]; // (id, storage class, pointer type)
+    for instruction in &module.types_global_values {
+      let id = match instruction.result_id {
+        Some(id) => id,
+        None => continue,
+      };
+      let spirv_type = match instruction.class.opcode {
+        spirv::Op::TypeBool => SpirvType::Bool,
+        spirv::Op::TypeInt => SpirvType::Int {
+          width: literal_operand(instruction, 0).unwrap_or(32),
+          signed: literal_operand(instruction, 1) == Some(1),
+        },
+        spirv::Op::TypeFloat => SpirvType::Float { width: literal_operand(instruction, 0).unwrap_or(32) },
+        spirv::Op::TypeVector => SpirvType::Vector { component: id_operand(instruction, 0).unwrap_or(0), count: literal_operand(instruction, 1).unwrap_or(1) },
+        spirv::Op::TypeMatrix => SpirvType::Matrix { column: id_operand(instruction, 0).unwrap_or(0), count: literal_operand(instruction, 1).unwrap_or(1) },
+        spirv::Op::TypeArray => SpirvType::Array {
+          element: id_operand(instruction, 0).unwrap_or(0),
+          length: id_operand(instruction, 1).and_then(|length| reflected.constants.get(&length).copied()).unwrap_or(1), // Specialization constants count as 1
+        },
+        spirv::Op::TypeRuntimeArray => SpirvType::RuntimeArray { element: id_operand(instruction, 0).unwrap_or(0) },
+        spirv::Op::TypeStruct => SpirvType::Struct { members: (0..instruction.operands.len()).filter_map(|index| id_operand(instruction, index)).collect() },
+        spirv::Op::TypePointer => SpirvType::Pointer { pointee: id_operand(instruction, 1).unwrap_or(0) },
+        spirv::Op::TypeImage => SpirvType::Image {
+          dim: match instruction.operands.get(1) {
+            Some(Operand::Dim(dim)) => *dim,
+            _ => spirv::Dim::Dim2D,
+          },
+          sampled: literal_operand(instruction, 5).unwrap_or(1),
+        },
+        spirv::Op::TypeSampler => SpirvType::Sampler,
+        spirv::Op::TypeSampledImage => SpirvType::SampledImage,
+        spirv::Op::TypeAccelerationStructureKHR => SpirvType::AccelerationStructure,
+        spirv::Op::Constant => {
+          if let Some(value) = literal_operand(instruction, 0) {
+            reflected.constants.insert(id, value);
+          }
+          continue;
+        }
+        spirv::Op::Variable => {
+          if let (Some(pointer), Some(Operand::StorageClass(storage_class))) = (instruction.result_type, instruction.operands.first()) {
+            variables.push((id, *storage_class, pointer));
+          }
+          continue;
+        }
+        _ => SpirvType::Other,
+      };
+      reflected.types.insert(id, spirv_type);
+    }
+
+    let mut inputs = vec![];
+    let mut bindings = vec![];
+    let mut push_constants = None;
+    for (id, storage_class, pointer) in variables {
+      let type_id = match reflected.types.get(&pointer) {
+        Some(SpirvType::Pointer { pointee }) => *pointee,
+        _ => continue,
+      };
+      let decorations = reflected.decorations(id);
+      match storage_class {
+        spirv::StorageClass::Input if stage == vk::ShaderStageFlags::VERTEX && interface.contains(&id) => {
+          // Built in inputs (like gl_VertexIndex) don't come from vertex attributes
+          if decorations.is_some_and(|decorations| decorations.built_in) {
+            continue;
+          }
+          let location = decorations.and_then(|decorations| decorations.location)
+            .ok_or_else(|| RendererError::ShaderReflection("A vertex input has no location".to_string()))?;
+          inputs.extend(reflected.inputs(type_id, location)?);
+        }
+        spirv::StorageClass::Uniform | spirv::StorageClass::UniformConstant | spirv::StorageClass::StorageBuffer => {
+          let (set, binding) = match decorations.map(|decorations| (decorations.set, decorations.binding)) {
+            Some((Some(set), Some(binding))) => (set, binding),
+            _ => continue, // Not a descriptor
+          };
+          let (resource_type, count) = reflected.strip_arrays(type_id);
+          let descriptor_type = reflected.descriptor_type(storage_class, resource_type)
+            .ok_or_else(|| RendererError::ShaderReflection(format!("Unsupported resource type at set {} binding {}", set, binding)))?;
+          bindings.push(ShaderBinding {
+            set,
+            binding: DescriptorBinding { binding, descriptor_type, count, stages: stage },
+          });
+        }
+        spirv::StorageClass::PushConstant => {
+          let members = match reflected.types.get(&type_id) {
+            Some(SpirvType::Struct { members }) => members.len() as u32,
+            _ => 0,
+          };
+          // The block starts at its first member, which doesn't have to be at 0 (when stages share the push constants)
+          let offset = (0..members)
+            .filter_map(|member| reflected.member_decorations.get(&(type_id, member)).and_then(|decorations| decorations.offset))
+            .min()
+            .unwrap_or(0);
+          let end = reflected.size(type_id, None).div_ceil(4) * 4; // Push constant ranges are in multiples of 4 bytes
+          push_constants = Some((offset, end - offset));
+        }
+        _ => {}
+      }
+    }
+    inputs.sort_by_key(|input| input.location);
+    bindings.sort_by_key(|binding| (binding.set, binding.binding.binding));
+
+    Ok(ShaderReflection {
+      stage,
+      inputs,
+      bindings,
+      push_constants,
+    })
+  }
+
+  // The descriptor set layouts (indexed by set) the given shaders need together. Sets no shader uses are left empty
+  pub fn descriptor_sets(shaders: &[ShaderReflection]) -> Vec<Vec<DescriptorBinding>> {
+    let mut sets: Vec<Vec<DescriptorBinding>> = vec![];
+    for shader_binding in shaders.iter().flat_map(|shader| &shader.bindings) {
+      let set = shader_binding.set as usize;
+      if sets.len() <= set {
+        sets.resize(set + 1, vec![]);
+      }
+      match sets[set].iter_mut().find(|binding| binding.binding == shader_binding.binding.binding) {
+        Some(binding) => binding.stages |= shader_binding.binding.stages, // Used by more than one stage
+        None => sets[set].push(shader_binding.binding),
+      }
+    }
+    sets
+  }
+
+  // The push constant range the given shaders need together (a single range covering every stage's block), if any of them use push constants
+  pub fn push_constant_ranges(shaders: &[ShaderReflection]) -> Vec<vk::PushConstantRange> {
+    let blocks: Vec<(vk::ShaderStageFlags, u32, u32)> = shaders.iter()
+      .filter_map(|shader| shader.push_constants.map(|(offset, size)| (shader.stage, offset, offset + size)))
+      .collect();
+    if blocks.is_empty() {
+      return vec![];
+    }
+    let stage_flags = blocks.iter().fold(vk::ShaderStageFlags::empty(), |stages, &(stage, _, _)| stages | stage);
+    let offset = blocks.iter().map(|&(_, offset, _)| offset).min().unwrap_or(0);
+    let end = blocks.iter().map(|&(_, _, end)| end).max().unwrap_or(0);
+    vec![vk::PushConstantRange { stage_flags, offset, size: end - offset }]
+  }
+
+  // Check the shaders of a pipeline against its vertex input and layout (see mismatches), failing with every mismatch found
+  pub fn validate(
+    shaders: &[ShaderReflection], vertex_bindings: &[vk::VertexInputBindingDescription], vertex_attributes: &[vk::VertexInputAttributeDescription],
+    set_bindings: &[Option<Vec<DescriptorBinding>>], push_constant_ranges: &[vk::PushConstantRange],
+  ) -> Result<(), RendererError> {
+    let mismatches: Vec<String> = shaders.iter()
+      .flat_map(|shader| shader.mismatches(vertex_bindings, vertex_attributes, set_bindings, push_constant_ranges))
+      .collect();
+    match mismatches.is_empty() {
+      true => Ok(()),
+      false => Err(RendererError::ShaderInterface(mismatches)),
+    }
+  }
+
+  // Every way the shader doesn't match what a pipeline provides: the vertex attributes (for vertex shaders), the bindings of each
+  // descriptor set (None for a set whose bindings aren't known, only its existence is checked) and the push constant ranges
+  pub fn mismatches(
+    &self, vertex_bindings: &[vk::VertexInputBindingDescription], vertex_attributes: &[vk::VertexInputAttributeDescription],
+    set_bindings: &[Option<Vec<DescriptorBinding>>], push_constant_ranges: &[vk::PushConstantRange],
+  ) -> Vec<String> {
+    let stage = format!("{:?}", self.stage).to_lowercase();
+    let mut mismatches = vec![];
+
+    // Every location the shader reads needs an attribute (from a bound vertex buffer) of the same numeric type.
+    // The number of components may differ, missing ones are filled in with 0 (and 1 for alpha)
+    for input in &self.inputs {
+      let attribute = match vertex_attributes.iter().find(|attribute| attribute.location == input.location) {
+        Some(attribute) => attribute,
+        None => {
+          mismatches.push(format!("the {} shader reads location {}, but no vertex attribute provides it", stage, input.location));
+          continue;
+        }
+      };
+      if !vertex_bindings.iter().any(|binding| binding.binding == attribute.binding) {
+        mismatches.push(format!("the vertex attribute at location {} uses binding {}, which isn't a vertex input binding", input.location, attribute.binding));
+      }
+      match NumericType::of_format(attribute.format) {
+        Some(numeric_type) if numeric_type != input.numeric_type => mismatches.push(format!(
+          "the {} shader reads location {} as {:?}, but the vertex attribute's format is {:?}", stage, input.location, input.numeric_type, attribute.format,
+        )),
+        _ => {}
+      }
+    }
+
+    for shader_binding in &self.bindings {
+      let (set, binding) = (shader_binding.set, shader_binding.binding);
+      let layout_bindings = match set_bindings.get(set as usize) {
+        Some(Some(layout_bindings)) => layout_bindings,
+        Some(None) => continue, // We don't know what's in the set's layout
+        None => {
+          mismatches.push(format!("the {} shader uses set {}, but the pipeline layout only has {} sets", stage, set, set_bindings.len()));
+          continue;
+        }
+      };
+      let layout_binding = match layout_bindings.iter().find(|layout_binding| layout_binding.binding == binding.binding) {
+        Some(layout_binding) => layout_binding,
+        None => {
+          mismatches.push(format!("the {} shader uses set {} binding {}, which isn't in the set's layout", stage, set, binding.binding));
+          continue;
+        }
+      };
+      // The shader can't tell whether a buffer is bound with a dynamic offset
+      let compatible_type = layout_binding.descriptor_type == binding.descriptor_type || matches!(
+        (binding.descriptor_type, layout_binding.descriptor_type),
+        (vk::DescriptorType::UNIFORM_BUFFER, vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+          | (vk::DescriptorType::STORAGE_BUFFER, vk::DescriptorType::STORAGE_BUFFER_DYNAMIC)
+      );
+      if !compatible_type {
+        mismatches.push(format!(
+          "the {} shader uses set {} binding {} as {:?}, but the layout has {:?}", stage, set, binding.binding, binding.descriptor_type, layout_binding.descriptor_type,
+        ));
+      }
+      if layout_binding.count < binding.count {
+        mismatches.push(format!(
+          "the {} shader uses {} descriptors at set {} binding {}, but the layout only has {}", stage, binding.count, set, binding.binding, layout_binding.count,
+        ));
+      }
+      if !layout_binding.stages.contains(self.stage) {
+        mismatches.push(format!("set {} binding {} isn't visible to the {} shader that uses it", set, binding.binding, stage));
+      }
+    }
+
+    if let Some((offset, size)) = self.push_constants {
+      let covered = push_constant_ranges.iter()
+        .any(|range| range.stage_flags.contains(self.stage) && range.offset <= offset && range.offset + range.size >= offset + size);
+      if !covered {
+        mismatches.push(format!("the {} shader uses push constants {}..{}, but no push constant range for it covers them", stage, offset, offset + size));
+      }
+    }
+    mismatches
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::vulkan::descriptors::Descriptors;
+  use crate::vulkan::uniforms::PushConstants;
+  use crate::vulkan::vertex::{InstanceData, Vertex};
+
+  // The built in shaders, the ones Pipeline::default_builder uses
+  fn default_shaders() -> [ShaderReflection; 2] {
+    let vertex_shader = vk_shader_macros::include_glsl!("./shaders/shader.vert", kind: vert);
+    let fragment_shader = vk_shader_macros::include_glsl!("./shaders/shader.frag", kind: frag);
+    [ShaderReflection::reflect(vertex_shader).unwrap(), ShaderReflection::reflect(fragment_shader).unwrap()]
+  }
+
+  // What the default pipeline gives the shaders
+  fn default_interface() -> (Vec<vk::VertexInputBindingDescription>, Vec<vk::VertexInputAttributeDescription>, Vec<Option<Vec<DescriptorBinding>>>) {
+    let bindings = vec![Vertex::get_binding_description()[0], InstanceData::get_binding_description()];
+    let attributes = Vertex::get_attribute_descriptions().iter().chain(InstanceData::get_attribute_descriptions().iter()).copied().collect();
+    let sets = vec![Some(Descriptors::CAMERA_BINDINGS.to_vec()), Some(Descriptors::TEXTURE_BINDINGS.to_vec())];
+    (bindings, attributes, sets)
+  }
+
+  fn validate_default(
+    attributes: &[vk::VertexInputAttributeDescription], sets: &[Option<Vec<DescriptorBinding>>],
+  ) -> Result<(), RendererError> {
+    let (bindings, _, _) = default_interface();
+    ShaderReflection::validate(&default_shaders(), &bindings, attributes, sets, &[PushConstants::get_range()])
+  }
+
+  fn assert_mismatch(result: Result<(), RendererError>, expected: &str) {
+    match result {
+      Err(RendererError::ShaderInterface(mismatches)) => assert!(
+        mismatches.iter().any(|mismatch| mismatch.contains(expected)), "expected a mismatch about \"{}\", got {:?}", expected, mismatches,
+      ),
+      other => panic!("expected a ShaderInterface error, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn reflects_vertex_inputs() {
+    let [vertex, fragment] = default_shaders();
+    assert_eq!(vertex.stage, vk::ShaderStageFlags::VERTEX);
+    let mut inputs = vertex.inputs.clone();
+    inputs.sort_by_key(|input| input.location);
+    let float = |location, components| ShaderInput { location, numeric_type: NumericType::Float, components };
+    assert_eq!(inputs, vec![
+      float(0, 4), float(1, 4), float(2, 2), // Vertex
+      float(3, 4), float(4, 4), float(5, 4), float(6, 4), float(7, 4), float(8, 4), // InstanceData, the model matrix takes a location per column
+    ]);
+    assert_eq!(fragment.stage, vk::ShaderStageFlags::FRAGMENT);
+    assert!(fragment.inputs.is_empty()); // They come from the vertex shader, not a vertex buffer
+  }
+
+  #[test]
+  fn reflects_bindings() {
+    let [vertex, fragment] = default_shaders();
+    assert_eq!(vertex.bindings, vec![ShaderBinding { set: 0, binding: Descriptors::CAMERA_BINDINGS[0] }]);
+    assert_eq!(fragment.bindings, vec![ShaderBinding { set: 1, binding: Descriptors::TEXTURE_BINDINGS[0] }]);
+    assert_eq!(
+      ShaderReflection::descriptor_sets(&default_shaders()),
+      vec![Descriptors::CAMERA_BINDINGS.to_vec(), Descriptors::TEXTURE_BINDINGS.to_vec()],
+    );
+  }
+
+  #[test]
+  fn reflects_push_constants() {
+    let [vertex, fragment] = default_shaders();
+    let size = std::mem::size_of::<PushConstants>() as u32;
+    assert_eq!(vertex.push_constants, Some((0, size)));
+    assert_eq!(fragment.push_constants, None);
+
+    let ranges = ShaderReflection::push_constant_ranges(&default_shaders());
+    let expected = PushConstants::get_range();
+    assert_eq!(ranges.len(), 1);
+    assert_eq!((ranges[0].stage_flags, ranges[0].offset, ranges[0].size), (expected.stage_flags, expected.offset, expected.size));
+  }
+
+  #[test]
+  fn default_interface_matches() {
+    let (_, attributes, sets) = default_interface();
+    validate_default(&attributes, &sets).unwrap();
+  }
+
+  #[test]
+  fn wrong_attribute_location_is_reported() {
+    let (_, mut attributes, sets) = default_interface();
+    attributes.iter_mut().find(|attribute| attribute.location == 8).unwrap().location = 9;
+    assert_mismatch(validate_default(&attributes, &sets), "reads location 8");
+  }
+
+  #[test]
+  fn wrong_attribute_format_is_reported() {
+    let (_, mut attributes, sets) = default_interface();
+    attributes.iter_mut().find(|attribute| attribute.location == 2).unwrap().format = vk::Format::R32G32_UINT;
+    assert_mismatch(validate_default(&attributes, &sets), "reads location 2 as Float");
+  }
+
+  #[test]
+  fn wrong_descriptor_binding_is_reported() {
+    let (_, attributes, mut sets) = default_interface();
+    sets[1] = Some(vec![DescriptorBinding::new(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::ShaderStageFlags::FRAGMENT)]);
+    assert_mismatch(validate_default(&attributes, &sets), "set 1 binding 0");
+
+    sets[1] = Some(vec![DescriptorBinding::new(0, vk::DescriptorType::UNIFORM_BUFFER, vk::ShaderStageFlags::FRAGMENT)]);
+    assert_mismatch(validate_default(&attributes, &sets), "as COMBINED_IMAGE_SAMPLER");
+  }
+
+  #[test]
+  fn vertex_formats_have_numeric_types() {
+    assert_eq!(NumericType::of_format(vk::Format::R32G32B32A32_SFLOAT), Some(NumericType::Float));
+    assert_eq!(NumericType::of_format(vk::Format::R8G8B8A8_UNORM), Some(NumericType::Float));
+    assert_eq!(NumericType::of_format(vk::Format::R16G16_SINT), Some(NumericType::SInt));
+    assert_eq!(NumericType::of_format(vk::Format::A2B10G10R10_UINT_PACK32), Some(NumericType::UInt));
+    assert_eq!(NumericType::of_format(vk::Format::BC1_RGB_UNORM_BLOCK), None);
+  }
+}
\ No newline at end of file
@@ -1,42 +1,115 @@
 use ash::vk;
 
+use super::error::*;
+
 pub struct RenderPass {}
 
 impl RenderPass {
-  pub fn init_renderpass(logical_device: &ash::Device, physical_device: vk::PhysicalDevice, format: vk::Format) -> Result<vk::RenderPass, vk::Result> {
-    let attachments = [vk::AttachmentDescription::builder()
+  // final_layout is PRESENT_SRC_KHR when rendering to the swapchain, or TRANSFER_SRC_OPTIMAL when rendering offscreen (so we can copy the image out).
+  // With more than one sample we render into a multisampled color attachment (attachment 0), which gets resolved into the
+  // single sampled target image (attachment 1) at the end of the subpass.
+  // With a depth_format the depth attachment comes last, after the color (and resolve) attachments
+  pub fn init_renderpass(
+    logical_device: &ash::Device, physical_device: vk::PhysicalDevice, format: vk::Format, final_layout: vk::ImageLayout, samples: vk::SampleCountFlags,
+    depth_format: Option<vk::Format>,
+  ) -> Result<vk::RenderPass, RendererError> {
+    let is_multisampled = samples != vk::SampleCountFlags::TYPE_1;
+    let mut attachments = vec![vk::AttachmentDescription::builder()
         .format(format) // Format must be sample as the swapchain
         .load_op(vk::AttachmentLoadOp::CLEAR) // What to do when the attachment is first loaded (clear it)
-        .store_op(vk::AttachmentStoreOp::STORE) // What to do when the renderpass is complete (store it)
+        // What to do when the renderpass is complete (store it, unless it's multisampled, then only the resolved image is kept)
+        .store_op(if is_multisampled { vk::AttachmentStoreOp::DONT_CARE } else { vk::AttachmentStoreOp::STORE })
         .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
         .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
         .initial_layout(vk::ImageLayout::UNDEFINED) // The initial layout of the attachment (how the data is stored in memory)
-        .final_layout(vk::ImageLayout::PRESENT_SRC_KHR) // The final layout of the attachment (ready for presentation)
-        .samples(vk::SampleCountFlags::TYPE_1) // Samples per pixel for the attachment (1 means no anti-aliasing)
+        // The final layout of the attachment (ready for presentation, or to be copied out). A multisampled one is never used after the renderpass
+        .final_layout(if is_multisampled { vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL } else { final_layout })
+        .samples(samples) // Samples per pixel for the attachment (1 means no anti-aliasing)
         .build()
     ];
+    if is_multisampled {
+      attachments.push(vk::AttachmentDescription::builder()
+        .format(format)
+        .load_op(vk::AttachmentLoadOp::DONT_CARE) // Every pixel gets overwritten by the resolve
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .final_layout(final_layout)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .build());
+    }
+    if let Some(depth_format) = depth_format {
+      attachments.push(vk::AttachmentDescription::builder()
+        .format(depth_format)
+        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .store_op(vk::AttachmentStoreOp::DONT_CARE) // Depth is only needed while drawing
+        .stencil_load_op(vk::AttachmentLoadOp::CLEAR)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+        .samples(samples) // Must match the color attachment
+        .build());
+    }
 
     let color_attachment_references = [vk::AttachmentReference {
         attachment: 0,
         layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL, // Use a layout that is optimal for color attachments
     }]; // Attach this attachment to the color attachment point as attachment 0
+    let resolve_attachment_references = [vk::AttachmentReference {
+        attachment: 1,
+        layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+    }]; // Color attachment 0 is resolved into attachment 1
+    let depth_attachment_reference = vk::AttachmentReference {
+        attachment: attachments.len() as u32 - 1,
+        layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+    };
 
     // Grab a subpass (a render pass is a collection of subpasses), FYI this is only for graphics pipelines, not for compute pipelines
-    let subpasses = [vk::SubpassDescription::builder()
+    let mut subpass = vk::SubpassDescription::builder()
             .color_attachments(&color_attachment_references)
-            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS).build()];
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS);
+    if is_multisampled {
+      subpass = subpass.resolve_attachments(&resolve_attachment_references);
+    }
+    if depth_format.is_some() {
+      subpass = subpass.depth_stencil_attachment(&depth_attachment_reference);
+    }
+    let subpasses = [subpass.build()];
 
-    // Define subpass dependencies (how the subpasses are connected if we have multiple subpasses)
-    let subpass_dependencies = [vk::SubpassDependency::builder()
+    // Define subpass dependencies (how the subpasses are connected if we have multiple subpasses).
+    // Every frame in flight shares the one depth image, so clearing it has to wait for the depth writes of the previous frame
+    let (mut stages, mut src_access, mut dst_access) = (
+      vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+      vk::AccessFlags::empty(),
+      vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+    );
+    if depth_format.is_some() {
+      stages |= vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS;
+      src_access |= vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE;
+      dst_access |= vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE;
+    }
+    let mut subpass_dependencies = vec![vk::SubpassDependency::builder()
         .src_subpass(vk::SUBPASS_EXTERNAL)
-        .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+        .src_stage_mask(stages)
+        .src_access_mask(src_access)
         .dst_subpass(0)
-        .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
-        .dst_access_mask(
-            vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
-        )
+        .dst_stage_mask(stages)
+        .dst_access_mask(dst_access)
         .build()];
 
+    // When the image gets copied out afterwards, the color writes must be finished (and visible) before the transfer reads them
+    if final_layout == vk::ImageLayout::TRANSFER_SRC_OPTIMAL {
+      subpass_dependencies.push(vk::SubpassDependency::builder()
+        .src_subpass(0)
+        .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+        .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+        .dst_subpass(vk::SUBPASS_EXTERNAL)
+        .dst_stage_mask(vk::PipelineStageFlags::TRANSFER)
+        .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+        .build());
+    }
+
     // Set up the render pass
     let renderpass_info = vk::RenderPassCreateInfo::builder()
         .attachments(&attachments)
@@ -49,6 +122,20 @@ impl RenderPass {
     Ok(renderpass)
   }
 
+  // One clear value per attachment of a render pass made by init_renderpass, in the same order
+  pub fn clear_values(color: [f32; 4], samples: vk::SampleCountFlags, has_depth: bool) -> Vec<vk::ClearValue> {
+    let mut clear_values = vec![vk::ClearValue { color: vk::ClearColorValue { float32: color } }];
+    if samples != vk::SampleCountFlags::TYPE_1 {
+      clear_values.push(vk::ClearValue::default()); // The resolve attachment isn't cleared
+    }
+    if has_depth {
+      clear_values.push(vk::ClearValue {
+        depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 }, // 1 is the far plane
+      });
+    }
+    clear_values
+  }
+
   pub fn cleanup_renderpass(logical_device: &ash::Device, renderpass: vk::RenderPass) {
     unsafe {
         logical_device.destroy_render_pass(renderpass, None);
@@ -1,11 +1,11 @@
-use ash::vk;
 use gpu_allocator::vulkan::Allocator;
 
-use super::{vertex_buffer::VertexBuffer, index_buffer::IndexBuffer, vertex::Vertex};
+use super::{vertex_buffer::VertexBuffer, index_buffer::IndexBuffer, instance_buffer::InstanceBuffer, vertex::*, error::*};
 
 pub struct Renderable {
   pub vertex_buffers: Vec<VertexBuffer>,
   pub index_buffer: Option<IndexBuffer>,
+  pub instance_buffer: Option<InstanceBuffer>, // When set, the vertices are drawn once per instance in it
 }
 
 impl Renderable {
@@ -14,46 +14,70 @@ impl Renderable {
     allocator: &mut Allocator,
     vertex_count: usize,
     index_count: usize,
-  ) -> Result<Renderable, vk::Result> {
+  ) -> Result<Renderable, RendererError> {
     let mut vertex_buffers = vec![];
-    let mut vert_buff = VertexBuffer::new(device, allocator, VertexBuffer::get_size_for_num_verts(vertex_count));
+    let vert_buff = VertexBuffer::new(device, allocator, VertexBuffer::get_size_for_num_verts(vertex_count))?;
     vertex_buffers.push(vert_buff);
     if index_count > 0 {
-        let mut index_buff = IndexBuffer::new(device, allocator, IndexBuffer::get_size_for_num_indices(index_count));
+        let index_buff = IndexBuffer::new(device, allocator, IndexBuffer::get_size_for_num_indices(index_count))?;
         Ok(Renderable {
           vertex_buffers,
           index_buffer: Some(index_buff),
+          instance_buffer: None,
         })
     } else {
       Ok(Renderable {
         vertex_buffers,
         index_buffer: None,
+        instance_buffer: None,
       })
     }
   }
 
-  pub fn update_vertices_buffer(&mut self, device: &ash::Device, data: &[Vertex]) {
-    self.vertex_buffers[0].update_buffer(device, data);
+  // Create a renderable that draws its vertices once for every instance written with update_instances_buffer
+  pub fn new_instanced(
+    device: &ash::Device,
+    allocator: &mut Allocator,
+    vertex_count: usize,
+    index_count: usize,
+    instance_count: usize,
+  ) -> Result<Renderable, RendererError> {
+    let mut renderable = Renderable::new(device, allocator, vertex_count, index_count)?;
+    renderable.instance_buffer = Some(InstanceBuffer::new(device, allocator, InstanceBuffer::get_size_for_num_instances(instance_count))?);
+    Ok(renderable)
+  }
+
+  pub fn update_vertices_buffer(&mut self, device: &ash::Device, data: &[Vertex]) -> Result<(), RendererError> {
+    self.vertex_buffers[0].update_buffer(device, data)
   }
 
-  pub fn update_indices_buffer(&mut self, device: &ash::Device, data: &[u32]) {
+  // Fails with InvalidHandle if the renderable was created without an index buffer (an index_count of 0)
+  pub fn update_indices_buffer(&mut self, device: &ash::Device, data: &[u32]) -> Result<(), RendererError> {
     match self.index_buffer {
-      Some(ref mut index_buff) => {
-        index_buff.update_buffer(device, data);
-      },
-      None => {
-        println!("Tried to update indices buffer on a renderable created with an index buffer!");
-      },
+      Some(ref mut index_buff) => index_buff.update_buffer(device, data),
+      None => Err(RendererError::InvalidHandle),
     }
   }
 
-  pub fn destroy(&mut self, device: &ash::Device, allocator: &mut Allocator) {
+  // Fails with InvalidHandle if the renderable wasn't created with new_instanced
+  pub fn update_instances_buffer(&mut self, device: &ash::Device, data: &[InstanceData]) -> Result<(), RendererError> {
+    match self.instance_buffer {
+      Some(ref mut instance_buff) => instance_buff.update_buffer(device, data),
+      None => Err(RendererError::InvalidHandle),
+    }
+  }
+
+  pub fn destroy(&mut self, device: &ash::Device, allocator: &mut Allocator) -> Result<(), RendererError> {
     for vertex_buffer in &mut self.vertex_buffers {
-      vertex_buffer.destroy(device, allocator);
+      vertex_buffer.destroy(device, allocator)?;
     }
     if let Some(index_buffer) = &mut self.index_buffer {
-      index_buffer.destroy(device, allocator);
+      index_buffer.destroy(device, allocator)?;
+    }
+    if let Some(instance_buffer) = &mut self.instance_buffer {
+      instance_buffer.destroy(device, allocator)?;
     }
+    Ok(())
   }
 
   pub fn get_vertex_buffers(&self) -> Vec<&VertexBuffer> {
@@ -0,0 +1,343 @@
+use ash::vk;
+
+use crate::renderer::*;
+use super::app::*;
+use super::error::*;
+use super::vertex_buffer::*;
+use super::index_buffer::*;
+use super::instance_buffer::*;
+use super::texture::*;
+use super::uniforms::*;
+use super::pipeline::*;
+
+// A buffer owned by the renderer on behalf of a BufferHandle
+pub enum BufferSlot {
+  Vertex(VertexBuffer),
+  Index(IndexBuffer),
+  Instance(InstanceBuffer),
+}
+
+// A pipeline owned by the renderer on behalf of a PipelineHandle, with the builder it was built from so it can be rebuilt
+// (when the render pass is replaced or its shaders change)
+pub struct PipelineSlot {
+  pub pipeline: Pipeline,
+  pub builder: PipelineBuilder,
+}
+
+// A draw queued through the Renderer trait, already resolved to the Vulkan buffers it uses. The pipeline is looked up when the
+// frame is recorded, as it may be rebuilt before then
+#[derive(Clone, Copy, Debug)]
+pub struct DrawCall {
+  pub pipeline: PipelineHandle,
+  pub vertex_buffer: vk::Buffer,
+  pub vertex_count: u32,
+  pub index_buffer: Option<vk::Buffer>,
+  pub first_index: u32,
+  pub index_count: u32,
+  pub texture_set: vk::DescriptorSet,
+  pub instance_buffer: vk::Buffer,
+  pub instance_count: u32,
+  pub push_constants: PushConstants,
+  pub scissor: Option<vk::Rect2D>, // Pixels outside it are left alone, None for the whole framebuffer
+}
+
+// The camera set of the frame being drawn, and what draws are drawn with when they don't bring their own texture or instances
+#[derive(Clone, Copy, Debug)]
+pub struct DrawDefaults {
+  pub camera_set: vk::DescriptorSet,
+  pub texture_set: vk::DescriptorSet,
+  pub instance_buffer: vk::Buffer,
+}
+
+impl VulkanApp {
+  // Store a buffer and hand out a handle to it. Slots of destroyed buffers aren't reused, so stale handles can't alias new buffers
+  fn insert_buffer(&mut self, buffer: BufferSlot) -> BufferHandle {
+    self.buffers.push(Some(buffer));
+    BufferHandle(self.buffers.len() - 1)
+  }
+
+  fn get_buffer_mut(&mut self, handle: BufferHandle) -> Result<&mut BufferSlot, RendererError> {
+    self.buffers.get_mut(handle.0).and_then(|slot| slot.as_mut()).ok_or(RendererError::InvalidHandle)
+  }
+
+  fn get_vertex_buffer(&mut self, handle: BufferHandle) -> Result<&VertexBuffer, RendererError> {
+    match self.get_buffer_mut(handle)? {
+      BufferSlot::Vertex(vertex_buffer) => Ok(vertex_buffer),
+      _ => Err(RendererError::InvalidHandle),
+    }
+  }
+
+  fn get_instance_buffer(&mut self, handle: BufferHandle) -> Result<&InstanceBuffer, RendererError> {
+    match self.get_buffer_mut(handle)? {
+      BufferSlot::Instance(instance_buffer) => Ok(instance_buffer),
+      _ => Err(RendererError::InvalidHandle),
+    }
+  }
+
+  fn insert_texture(&mut self, texture: Texture) -> TextureHandle {
+    self.textures.push(Some(texture));
+    TextureHandle(self.textures.len() - 1)
+  }
+
+  // The descriptor set to bind for a draw with the given texture (the white texture if there is none)
+  fn get_texture_set(&self, texture: Option<TextureHandle>) -> Result<vk::DescriptorSet, RendererError> {
+    match texture {
+      Some(texture) => self.textures.get(texture.0)
+        .and_then(|slot| slot.as_ref())
+        .map(|texture| texture.descriptor_set)
+        .ok_or(RendererError::InvalidHandle),
+      None => Ok(self.white_texture.descriptor_set),
+    }
+  }
+
+  fn get_index_buffer(&mut self, handle: BufferHandle) -> Result<&IndexBuffer, RendererError> {
+    match self.get_buffer_mut(handle)? {
+      BufferSlot::Index(index_buffer) => Ok(index_buffer),
+      _ => Err(RendererError::InvalidHandle),
+    }
+  }
+
+  // Takes the table instead of self, so it can be used while other fields are borrowed (e.g. when recording)
+  pub fn get_pipeline(pipelines: &[Option<PipelineSlot>], handle: PipelineHandle) -> Result<&Pipeline, RendererError> {
+    pipelines.get(handle.0).and_then(|slot| slot.as_ref()).map(|slot| &slot.pipeline).ok_or(RendererError::InvalidHandle)
+  }
+}
+
+impl Renderer for VulkanApp {
+  type Error = RendererError;
+
+  fn create_vertex_buffer(&mut self, vertex_count: usize) -> Result<BufferHandle, RendererError> {
+    let buffer = VertexBuffer::new(&self.device, &mut self.allocator, VertexBuffer::get_size_for_num_verts(vertex_count))?;
+    Ok(self.insert_buffer(BufferSlot::Vertex(buffer)))
+  }
+
+  fn create_index_buffer(&mut self, index_count: usize) -> Result<BufferHandle, RendererError> {
+    let buffer = IndexBuffer::new(&self.device, &mut self.allocator, IndexBuffer::get_size_for_num_indices(index_count))?;
+    Ok(self.insert_buffer(BufferSlot::Index(buffer)))
+  }
+
+  fn create_static_vertex_buffer(&mut self, vertices: &[Vertex]) -> Result<BufferHandle, RendererError> {
+    let buffer = VertexBuffer::new_static(&self.device, &mut self.allocator, &mut self.uploader, vertices)?;
+    Ok(self.insert_buffer(BufferSlot::Vertex(buffer)))
+  }
+
+  fn create_static_index_buffer(&mut self, indices: &[u32]) -> Result<BufferHandle, RendererError> {
+    let buffer = IndexBuffer::new_static(&self.device, &mut self.allocator, &mut self.uploader, indices)?;
+    Ok(self.insert_buffer(BufferSlot::Index(buffer)))
+  }
+
+  fn update_vertex_buffer(&mut self, buffer: BufferHandle, vertices: &[Vertex]) -> Result<(), RendererError> {
+    let device = self.device.clone();
+    match self.get_buffer_mut(buffer)? {
+      BufferSlot::Vertex(vertex_buffer) => vertex_buffer.update_buffer(&device, vertices),
+      _ => Err(RendererError::InvalidHandle),
+    }
+  }
+
+  fn update_index_buffer(&mut self, buffer: BufferHandle, indices: &[u32]) -> Result<(), RendererError> {
+    let device = self.device.clone();
+    match self.get_buffer_mut(buffer)? {
+      BufferSlot::Index(index_buffer) => index_buffer.update_buffer(&device, indices),
+      _ => Err(RendererError::InvalidHandle),
+    }
+  }
+
+  fn create_instance_buffer(&mut self, instance_count: usize) -> Result<BufferHandle, RendererError> {
+    let buffer = InstanceBuffer::new(&self.device, &mut self.allocator, InstanceBuffer::get_size_for_num_instances(instance_count))?;
+    Ok(self.insert_buffer(BufferSlot::Instance(buffer)))
+  }
+
+  fn update_instance_buffer(&mut self, buffer: BufferHandle, instances: &[InstanceData]) -> Result<(), RendererError> {
+    let device = self.device.clone();
+    match self.get_buffer_mut(buffer)? {
+      BufferSlot::Instance(instance_buffer) => instance_buffer.update_buffer(&device, instances),
+      _ => Err(RendererError::InvalidHandle),
+    }
+  }
+
+  fn destroy_buffer(&mut self, buffer: BufferHandle) -> Result<(), RendererError> {
+    let slot = self.buffers.get_mut(buffer.0).and_then(|slot| slot.take()).ok_or(RendererError::InvalidHandle)?;
+    unsafe { self.device.device_wait_idle()? }; // The buffer may still be in use by a frame in flight
+    match slot {
+      BufferSlot::Vertex(mut vertex_buffer) => vertex_buffer.destroy(&self.device, &mut self.allocator),
+      BufferSlot::Index(mut index_buffer) => index_buffer.destroy(&self.device, &mut self.allocator),
+      BufferSlot::Instance(mut instance_buffer) => instance_buffer.destroy(&self.device, &mut self.allocator),
+    }
+  }
+
+  fn create_texture_from_file(&mut self, path: &std::path::Path) -> Result<TextureHandle, RendererError> {
+    let texture = Texture::from_file(&self.device, &mut self.allocator, &mut self.uploader, &mut self.descriptors, path)?;
+    Ok(self.insert_texture(texture))
+  }
+
+  fn create_texture_from_memory(&mut self, bytes: &[u8]) -> Result<TextureHandle, RendererError> {
+    let texture = Texture::from_memory(&self.device, &mut self.allocator, &mut self.uploader, &mut self.descriptors, bytes)?;
+    Ok(self.insert_texture(texture))
+  }
+
+  fn create_texture_from_rgba(&mut self, width: u32, height: u32, pixels: &[u8]) -> Result<TextureHandle, RendererError> {
+    let texture = Texture::from_rgba(&self.device, &mut self.allocator, &mut self.uploader, &mut self.descriptors, width, height, pixels)?;
+    Ok(self.insert_texture(texture))
+  }
+
+  fn destroy_texture(&mut self, texture: TextureHandle) -> Result<(), RendererError> {
+    let mut texture = self.textures.get_mut(texture.0).and_then(|slot| slot.take()).ok_or(RendererError::InvalidHandle)?;
+    unsafe { self.device.device_wait_idle()? }; // The texture may still be in use by a frame in flight
+    texture.destroy(&self.device, &mut self.allocator, &mut self.descriptors)
+  }
+
+  fn default_pipeline(&self) -> PipelineHandle {
+    PipelineHandle(0) // Created with the app
+  }
+
+  fn create_pipeline(&mut self, description: &PipelineDescription) -> Result<PipelineHandle, RendererError> {
+    if description.wireframe && self.physical_device_features.fill_mode_non_solid == vk::FALSE {
+      return Err(RendererError::Pipeline(vk::Result::ERROR_FEATURE_NOT_PRESENT));
+    }
+    let builder = VulkanApp::describe_pipeline(
+      &self.device, &mut self.descriptors, self.config.shader_directory.as_deref(), self.msaa_samples, description,
+    )?;
+    let pipeline = builder.build(&self.device, self.renderpass, self.pipeline_cache.cache)?;
+    // Slots of destroyed pipelines aren't reused, like buffers
+    self.pipelines.push(Some(PipelineSlot { pipeline, builder }));
+    Ok(PipelineHandle(self.pipelines.len() - 1))
+  }
+
+  fn destroy_pipeline(&mut self, pipeline: PipelineHandle) -> Result<(), RendererError> {
+    if pipeline == self.default_pipeline() {
+      return Err(RendererError::InvalidHandle);
+    }
+    let slot = self.pipelines.get_mut(pipeline.0).and_then(|slot| slot.take()).ok_or(RendererError::InvalidHandle)?;
+    unsafe { self.device.device_wait_idle()? }; // The pipeline may still be in use by a frame in flight
+    slot.pipeline.cleanup(&self.device);
+    Ok(())
+  }
+
+  fn frames_in_flight(&self) -> usize {
+    self.frames.frames.len()
+  }
+
+  fn begin_frame(&mut self) -> Result<FrameHandle, RendererError> {
+    self.frames.wait_for_current(&self.device)?; // Resources used by this frame in flight may be reused from here on
+    self.draws.clear();
+    self.push_constants = PushConstants::IDENTITY;
+    self.scissor = None;
+    let frame = FrameHandle { index: self.frame_count, slot: self.frames.current_frame };
+    self.frame_count += 1;
+    Ok(frame)
+  }
+
+  fn set_view_projection(&mut self, view_projection: [[f32; 4]; 4]) {
+    self.camera = CameraUniform { view_projection }; // Written into the frame's uniform buffer when it's submitted
+  }
+
+  fn set_push_constants(&mut self, _frame: &FrameHandle, push_constants: PushConstants) {
+    self.push_constants = push_constants;
+  }
+
+  fn set_scissor(&mut self, _frame: &FrameHandle, scissor: Option<ScissorRect>) {
+    // Vulkan doesn't allow negative offsets, so cut off whatever is left of or above the framebuffer
+    self.scissor = scissor.map(|scissor| {
+      let (x, y) = (scissor.x.max(0), scissor.y.max(0));
+      vk::Rect2D {
+        offset: vk::Offset2D { x, y },
+        extent: vk::Extent2D {
+          width: scissor.width.saturating_sub((x - scissor.x) as u32),
+          height: scissor.height.saturating_sub((y - scissor.y) as u32),
+        },
+      }
+    });
+  }
+
+  fn draw(
+    &mut self, _frame: &FrameHandle, pipeline: PipelineHandle, vertex_buffer: BufferHandle, index_buffer: Option<BufferHandle>,
+    texture: Option<TextureHandle>,
+  ) -> Result<(), RendererError> {
+    VulkanApp::get_pipeline(&self.pipelines, pipeline)?;
+    let texture_set = self.get_texture_set(texture)?;
+    let (vertex_buffer, vertex_count) = {
+      let vertex_buffer = self.get_vertex_buffer(vertex_buffer)?;
+      (vertex_buffer.get_buffer(), vertex_buffer.get_vert_count())
+    };
+    let (index_buffer, index_count) = match index_buffer {
+      Some(index_buffer) => {
+        let index_buffer = self.get_index_buffer(index_buffer)?;
+        (Some(index_buffer.get_buffer()), index_buffer.get_indice_count())
+      }
+      None => (None, 0),
+    };
+    let instance_buffer = self.identity_instance.get_buffer();
+    self.draws.push(DrawCall {
+      pipeline, vertex_buffer, vertex_count, index_buffer, first_index: 0, index_count, texture_set, instance_buffer, instance_count: 1,
+      push_constants: self.push_constants, scissor: self.scissor,
+    });
+    Ok(())
+  }
+
+  fn draw_indexed(
+    &mut self, _frame: &FrameHandle, pipeline: PipelineHandle, vertex_buffer: BufferHandle, index_buffer: BufferHandle,
+    first_index: u32, index_count: u32, texture: Option<TextureHandle>,
+  ) -> Result<(), RendererError> {
+    VulkanApp::get_pipeline(&self.pipelines, pipeline)?;
+    let texture_set = self.get_texture_set(texture)?;
+    let (vertex_buffer, vertex_count) = {
+      let vertex_buffer = self.get_vertex_buffer(vertex_buffer)?;
+      (vertex_buffer.get_buffer(), vertex_buffer.get_vert_count())
+    };
+    let index_buffer = self.get_index_buffer(index_buffer)?.get_buffer();
+    let instance_buffer = self.identity_instance.get_buffer();
+    self.draws.push(DrawCall {
+      pipeline, vertex_buffer, vertex_count, index_buffer: Some(index_buffer), first_index, index_count, texture_set, instance_buffer, instance_count: 1,
+      push_constants: self.push_constants, scissor: self.scissor,
+    });
+    Ok(())
+  }
+
+  fn draw_instanced(
+    &mut self, _frame: &FrameHandle, pipeline: PipelineHandle, vertex_buffer: BufferHandle, index_buffer: Option<BufferHandle>,
+    instance_buffer: BufferHandle, texture: Option<TextureHandle>,
+  ) -> Result<(), RendererError> {
+    VulkanApp::get_pipeline(&self.pipelines, pipeline)?;
+    let texture_set = self.get_texture_set(texture)?;
+    let (vertex_buffer, vertex_count) = {
+      let vertex_buffer = self.get_vertex_buffer(vertex_buffer)?;
+      (vertex_buffer.get_buffer(), vertex_buffer.get_vert_count())
+    };
+    let (index_buffer, index_count) = match index_buffer {
+      Some(index_buffer) => {
+        let index_buffer = self.get_index_buffer(index_buffer)?;
+        (Some(index_buffer.get_buffer()), index_buffer.get_indice_count())
+      }
+      None => (None, 0),
+    };
+    let (instance_buffer, instance_count) = {
+      let instance_buffer = self.get_instance_buffer(instance_buffer)?;
+      (instance_buffer.get_buffer(), instance_buffer.get_instance_count())
+    };
+    self.draws.push(DrawCall {
+      pipeline, vertex_buffer, vertex_count, index_buffer, first_index: 0, index_count, texture_set, instance_buffer, instance_count,
+      push_constants: self.push_constants, scissor: self.scissor,
+    });
+    Ok(())
+  }
+
+  fn end_frame(&mut self, _frame: FrameHandle) -> Result<Option<Vec<u8>>, RendererError> {
+    self.draw_frame()
+  }
+
+  fn window_resized(&mut self) {
+    self.is_framebuffer_resized = true;
+  }
+
+  fn set_vsync(&mut self, vsync: bool) -> Result<(), RendererError> {
+    VulkanApp::set_vsync(self, vsync)
+  }
+
+  fn set_window_title(&self, title: &str) {
+    VulkanApp::set_window_title(self, title);
+  }
+
+  fn request_redraw(&self) {
+    VulkanApp::request_redraw(self);
+  }
+}
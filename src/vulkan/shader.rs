@@ -0,0 +1,47 @@
+use std::path::Path;
+
+use super::error::*;
+
+// Loading shaders at runtime, instead of compiling them into the binary with vk_shader_macros::include_glsl!.
+// Lets shaders be changed without rebuilding (see ShaderWatcher), or shipped separately as .spv files compiled with glslc
+
+// Load a shader as SPIR-V. .spv files are read as is, anything else is compiled as GLSL
+pub fn load_shader(path: &Path) -> Result<Vec<u32>, RendererError> {
+  match path.extension().and_then(|extension| extension.to_str()) {
+    Some("spv") => load_spirv(path),
+    _ => compile_glsl(path),
+  }
+}
+
+// Read a SPIR-V binary
+pub fn load_spirv(path: &Path) -> Result<Vec<u32>, RendererError> {
+  let shader_file_error = |error| RendererError::ShaderFile { path: path.to_path_buf(), error };
+  let mut file = std::fs::File::open(path).map_err(shader_file_error)?;
+  ash::util::read_spv(&mut file).map_err(shader_file_error) // Also checks the size and fixes up the endianness
+}
+
+// Compile a GLSL shader into SPIR-V. The stage comes from the extension, like glslc: .vert, .frag, .comp, .geom, .tesc or .tese
+pub fn compile_glsl(path: &Path) -> Result<Vec<u32>, RendererError> {
+  let compile_error = |message: String| RendererError::ShaderCompile { path: path.to_path_buf(), message };
+  let kind = match path.extension().and_then(|extension| extension.to_str()) {
+    Some("vert") => shaderc::ShaderKind::Vertex,
+    Some("frag") => shaderc::ShaderKind::Fragment,
+    Some("comp") => shaderc::ShaderKind::Compute,
+    Some("geom") => shaderc::ShaderKind::Geometry,
+    Some("tesc") => shaderc::ShaderKind::TessControl,
+    Some("tese") => shaderc::ShaderKind::TessEvaluation,
+    _ => return Err(compile_error("Unknown shader stage, expected a .vert, .frag, .comp, .geom, .tesc or .tese file".to_string())),
+  };
+  let source = std::fs::read_to_string(path).map_err(|error| RendererError::ShaderFile { path: path.to_path_buf(), error })?;
+
+  let mut compiler = shaderc::Compiler::new().ok_or_else(|| compile_error("Failed to create the shader compiler".to_string()))?;
+  let mut options = shaderc::CompileOptions::new().ok_or_else(|| compile_error("Failed to create the shader compiler options".to_string()))?;
+  options.set_generate_debug_info(); // Keeps names around for debuggers and the validation layer
+  let file_name = path.to_string_lossy(); // Only used in error messages
+  let artifact = compiler.compile_into_spirv(&source, kind, &file_name, "main", Some(&options))
+    .map_err(|error| compile_error(error.to_string()))?;
+  if artifact.get_num_warnings() > 0 {
+    log::warn!("{}", artifact.get_warning_messages().trim_end());
+  }
+  Ok(artifact.as_binary().to_vec())
+}
\ No newline at end of file
@@ -0,0 +1,54 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use notify::Watcher;
+
+// How long a directory has to be quiet before its changes are reported. Editors often save in several steps
+// (truncate then write, or write a temporary file and rename it), which we don't want to pick up halfway through
+const SETTLE_TIME: Duration = Duration::from_millis(100);
+
+// Watches a directory (not its subdirectories) for files being created or changed, so shaders can be reloaded while running.
+// The OS notifies a background thread, which passes the events over a channel, so polling is cheap enough to do every frame
+pub struct ShaderWatcher {
+  _watcher: notify::RecommendedWatcher, // Stops watching when dropped
+  events: mpsc::Receiver<notify::Result<notify::Event>>,
+  changed: HashSet<PathBuf>, // Changed files that haven't been reported yet
+  last_change: Instant,
+}
+
+impl ShaderWatcher {
+  pub fn new(directory: &Path) -> notify::Result<ShaderWatcher> {
+    let (sender, events) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    watcher.watch(directory, notify::RecursiveMode::NonRecursive)?;
+    Ok(ShaderWatcher {
+      _watcher: watcher,
+      events,
+      changed: HashSet::new(),
+      last_change: Instant::now(),
+    })
+  }
+
+  // The files that were created or changed since the last call, once nothing has changed for a moment.
+  // Paths are canonicalized where possible, so they can be compared with canonicalized paths of our own
+  pub fn changed_files(&mut self) -> HashSet<PathBuf> {
+    for event in self.events.try_iter() {
+      match event {
+        Ok(event) if event.kind.is_create() || event.kind.is_modify() => {
+          for path in event.paths {
+            self.changed.insert(std::fs::canonicalize(&path).unwrap_or(path));
+          }
+          self.last_change = Instant::now();
+        }
+        Ok(_) => {} // Accesses and removals don't change anything we could reload
+        Err(error) => log::warn!("Error watching shaders: {}", error),
+      }
+    }
+    match self.last_change.elapsed() >= SETTLE_TIME {
+      true => std::mem::take(&mut self.changed),
+      false => HashSet::new(),
+    }
+  }
+}
\ No newline at end of file
@@ -1,10 +1,11 @@
 use ash::vk;
 use super::surface::*;
 use super::queue::*;
+use super::error::*;
+use super::config::*;
 
 // Stores the things needed for a Vulkan Swapchain (that is, a series of images that can be drawn on and then presented to the screen)
-// We are currently using a triple buffered queue
-// TODO: Allow for setting the number of images in the swapchain
+// How many images it has and how they're presented is set with a SwapchainConfig
 pub struct VulkanSwapchain {
   pub swapchain_loader: ash::extensions::khr::Swapchain,
   pub swapchain: vk::SwapchainKHR,
@@ -12,12 +13,11 @@ pub struct VulkanSwapchain {
   pub imageviews: Vec<vk::ImageView>,
   pub framebuffers: Vec<vk::Framebuffer>,
   pub surface_format: vk::SurfaceFormatKHR,
+  pub present_mode: vk::PresentModeKHR, // The present mode actually used (the first of the config's the surface supports)
   pub extent: vk::Extent2D,
-  pub image_available: Vec<vk::Semaphore>,
-  pub rendering_finished: Vec<vk::Semaphore>,
-  pub may_begin_drawing: Vec<vk::Fence>, // A fence is used to synchronize CPU-GPU operations
+  pub rendering_finished: Vec<vk::Semaphore>, // One per image, presentation of an image waits on its semaphore (so it can't be reused before that image is presented again)
+  pub images_in_flight: Vec<vk::Fence>, // One per image, the fence of the frame in flight currently rendering to it (null if none). A fence is used to synchronize CPU-GPU operations
   pub amount_of_images: usize,
-  pub current_image: usize,
 }
 
 impl VulkanSwapchain {
@@ -28,31 +28,48 @@ impl VulkanSwapchain {
     surface: &VulkanSurface,
     queue_families: &QueueFamilies,
     queues: &Queues,
-  ) -> Result<VulkanSwapchain, vk::Result> {
+    config: &SwapchainConfig,
+  ) -> Result<VulkanSwapchain, RendererError> {
     let surface_capabilities = surface.get_capabilities(physical_device)?; // Get the surface capabilities
     let mut extent = surface_capabilities.current_extent; // Get the current extent (the size of the surface)
     let surface_present_modes = surface.get_present_modes(physical_device)?; // Get the surface presentation modes
-    let surface_format = *surface.get_formats(physical_device)?.first().unwrap(); // Get the surface formats
-    let queuefamilies = [queue_families.graphics.unwrap()]; // Use the graphics queue family
+    let present_mode = config.present_modes.iter()
+      .copied()
+      .find(|present_mode| surface_present_modes.contains(present_mode))
+      .unwrap_or(vk::PresentModeKHR::FIFO); // The only mode every surface has to support
+    // A max_image_count of 0 means there's no maximum
+    let max_image_count = match surface_capabilities.max_image_count {
+      0 => u32::MAX,
+      max_image_count => max_image_count,
+    };
+    let image_count = config.image_count.clamp(surface_capabilities.min_image_count, max_image_count);
+    let surface_formats = surface.get_formats(physical_device)?; // Get the surface formats
+    let surface_format = VulkanSwapchain::pick_surface_format(&surface_formats, config.color_space)
+      .ok_or(RendererError::Surface(vk::Result::ERROR_FORMAT_NOT_SUPPORTED))?;
+    // Images are rendered to on the graphics queue and presented on the present queue. If those are different families,
+    // the images are shared between them (simpler than transferring ownership every frame, and rare enough that the cost doesn't matter)
+    let graphics_family = queue_families.graphics.unwrap();
+    let present_family = queue_families.present.unwrap_or(graphics_family);
+    let (sharing_mode, queuefamilies) = match graphics_family == present_family {
+      true => (vk::SharingMode::EXCLUSIVE, vec![]),
+      false => (vk::SharingMode::CONCURRENT, vec![graphics_family, present_family]),
+    };
     let swapchain_create_info = vk::SwapchainCreateInfoKHR::builder()
       .surface(surface.surface) // The surface to create the swapchain for
-      .min_image_count( // 3 images are needed for triple buffering. Use the largest between 3 and min supported, as well as the smallest between 3 and the max supported
-        3.max(surface_capabilities.min_image_count)
-            .min(surface_capabilities.max_image_count),
-      )
-      .image_format(surface_format.format) // Use the first format supported by the surface
-      .image_color_space(surface_format.color_space) // Use the first color space supported by the surface
+      .min_image_count(image_count) // The driver may create more
+      .image_format(surface_format.format)
+      .image_color_space(surface_format.color_space)
       .image_extent(extent) // Use the current extent (width & height) of the surface (change later when resizing)
       .image_array_layers(1) // We only have one layer, more than one is for steroscopic 3D and VR, etc
       .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT) // We want to use the image as a color attachment
-      .image_sharing_mode(vk::SharingMode::EXCLUSIVE) // We don't want to share the images with other queues (we access images from one queue at a time)
-      .queue_family_indices(&queuefamilies) // Using the graphics queue
+      .image_sharing_mode(sharing_mode)
+      .queue_family_indices(&queuefamilies) // Only used when sharing
       .pre_transform(surface_capabilities.current_transform) // Use the current transform (we don't need to rotate or scale yet so use the identity transform)
       .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE) // We don't need to use alpha blending with other windows
-      .present_mode(vk::PresentModeKHR::FIFO); // We want to use the FIFO present mode, show images in order as created, waiting for the next vblank
+      .present_mode(present_mode);
     let swapchain_loader = ash::extensions::khr::Swapchain::new(instance, logical_device);
-    let swapchain = unsafe { swapchain_loader.create_swapchain(&swapchain_create_info, None)? };
-    let swapchain_images = unsafe { swapchain_loader.get_swapchain_images(swapchain)? };
+    let swapchain = unsafe { swapchain_loader.create_swapchain(&swapchain_create_info, None).map_err(RendererError::Swapchain)? };
+    let swapchain_images = unsafe { swapchain_loader.get_swapchain_images(swapchain).map_err(RendererError::Swapchain)? };
     let amount_of_images = swapchain_images.len();
     let mut swapchain_imageviews = Vec::with_capacity(swapchain_images.len());
     for image in &swapchain_images { // Create an image view for each image in the swapchain
@@ -65,25 +82,18 @@ impl VulkanSwapchain {
       let imageview_create_info = vk::ImageViewCreateInfo::builder()
         .image(*image)
         .view_type(vk::ImageViewType::TYPE_2D) // Type (1D, 2D, 3D, Cube Map, etc.)
-        .format(vk::Format::B8G8R8A8_UNORM) // Format (should match the swapchain format)
+        .format(surface_format.format) // Format (the same as the swapchain images, so the render pass writes them the way they're presented)
         .subresource_range(*subresource_range); // Subresource range (we currently care about the color aspect only, not depth, so mip_level and array_layers are 0/ignored)
       let imageview =
         unsafe { logical_device.create_image_view(&imageview_create_info, None) }?;
       swapchain_imageviews.push(imageview);
     }
 
-    let mut image_available = vec![];
     let mut rendering_finished = vec![];
-    let mut may_begin_drawing = vec![];
     let semaphoreinfo = vk::SemaphoreCreateInfo::builder();
-    let fenceinfo = vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED);
     for _ in 0..amount_of_images {
-      let semaphore_available = unsafe { logical_device.create_semaphore(&semaphoreinfo, None)? };
       let semaphore_finished = unsafe { logical_device.create_semaphore(&semaphoreinfo, None)? };
-      image_available.push(semaphore_available);
       rendering_finished.push(semaphore_finished);
-      let fence = unsafe { logical_device.create_fence(&fenceinfo, None)? };
-      may_begin_drawing.push(fence);
     }
 
     Ok(VulkanSwapchain {
@@ -93,21 +103,48 @@ impl VulkanSwapchain {
       imageviews: swapchain_imageviews,
       framebuffers: vec![],
       surface_format,
+      present_mode,
       extent,
       amount_of_images,
-      current_image: 0,
-      image_available,
       rendering_finished,
-      may_begin_drawing,
+      images_in_flight: vec![vk::Fence::null(); amount_of_images], // Nothing is rendering to any image yet
     })
   }
 
-  pub fn create_framebuffers(&mut self, logical_device: &ash::Device, renderpass: vk::RenderPass) -> Result<(), vk::Result> {
-    let mut width = self.extent.width;
-    let mut height = self.extent.height;
+  // The best of the surface's formats for the given color space. If the surface doesn't offer that color space, the best sRGB format.
+  // None if the surface has no formats at all
+  pub fn pick_surface_format(formats: &[vk::SurfaceFormatKHR], color_space: SurfaceColorSpace) -> Option<vk::SurfaceFormatKHR> {
+    // The surface doesn't care, pick the most common sRGB format
+    if let [vk::SurfaceFormatKHR { format: vk::Format::UNDEFINED, .. }] = formats {
+      return Some(vk::SurfaceFormatKHR { format: vk::Format::B8G8R8A8_SRGB, color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR });
+    }
+    let find = |wanted_formats: &[vk::Format], wanted_color_space: vk::ColorSpaceKHR| wanted_formats.iter()
+      .find_map(|&wanted_format| formats.iter().find(|format| format.format == wanted_format && format.color_space == wanted_color_space))
+      .copied();
+
+    let hdr_format = match color_space {
+      SurfaceColorSpace::Srgb => None,
+      SurfaceColorSpace::Hdr10 => find(&[vk::Format::A2B10G10R10_UNORM_PACK32, vk::Format::A2R10G10B10_UNORM_PACK32], vk::ColorSpaceKHR::HDR10_ST2084_EXT),
+      SurfaceColorSpace::ScRgb => find(&[vk::Format::R16G16B16A16_SFLOAT], vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT),
+    };
+    hdr_format
+      // sRGB formats encode the (linear) shader output on write
+      .or_else(|| find(&[vk::Format::B8G8R8A8_SRGB, vk::Format::R8G8B8A8_SRGB, vk::Format::A8B8G8R8_SRGB_PACK32], vk::ColorSpaceKHR::SRGB_NONLINEAR))
+      // Otherwise anything meant for an sRGB display (colors will look darker than intended), or anything at all
+      .or_else(|| formats.iter().find(|format| format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR).copied())
+      .or_else(|| formats.first().copied())
+  }
+
+  // msaa_view is the multisampled color image when MSAA is on, it's resolved into the swapchain image (so it comes first, see RenderPass).
+  // depth_view is the depth image when there's a depth buffer, it always comes last
+  pub fn create_framebuffers(
+    &mut self, logical_device: &ash::Device, renderpass: vk::RenderPass, msaa_view: Option<vk::ImageView>, depth_view: Option<vk::ImageView>,
+  ) -> Result<(), RendererError> {
+    let width = self.extent.width;
+    let height = self.extent.height;
 
     for iv in &self.imageviews {
-      let iview = [*iv];
+      let iview: Vec<vk::ImageView> = msaa_view.into_iter().chain([*iv]).chain(depth_view).collect();
       let framebuffer_info  = vk::FramebufferCreateInfo::builder()
         .render_pass(renderpass)
         .attachments(&iview)
@@ -120,13 +157,43 @@ impl VulkanSwapchain {
     Ok(())
   }
 
-  pub unsafe fn cleanup(&mut self, logical_device: &ash::Device) {
-    for fence in &self.may_begin_drawing {
-      logical_device.destroy_fence(*fence, None);
+  // Acquire the next image to draw to, signalling the given semaphore once it's ready. Returns the image index, and whether the
+  // swapchain is suboptimal (the image can still be drawn to and presented, but the swapchain should be recreated)
+  pub fn acquire_next_image(&self, semaphore: vk::Semaphore) -> Result<(u32, bool), RendererError> {
+    let result = unsafe {
+      self.swapchain_loader.acquire_next_image(
+        self.swapchain, // The swapchain to acquire an image from
+        std::u64::MAX, // How long to wait for the image (nanoseconds)
+        semaphore, // The semaphore to signal when the image is ready to be used
+        vk::Fence::null(), // A fence to signal when the image is acquired (must have either a semaphore or fence)
+      )
+    };
+    match result {
+      Ok(acquired) => Ok(acquired),
+      Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => Err(RendererError::OutOfDate),
+      Err(vk_result) => Err(RendererError::Swapchain(vk_result)),
     }
-    for semaphore in &self.image_available {
-      logical_device.destroy_semaphore(*semaphore, None); // Destroy image available semaphores
+  }
+
+  // Present the given image once the wait semaphores are signalled. Returns whether the swapchain is suboptimal (it was still presented,
+  // but should be recreated)
+  pub fn present(&self, queue: vk::Queue, image_index: u32, wait_semaphores: &[vk::Semaphore]) -> Result<bool, RendererError> {
+    let swapchains = [self.swapchain];
+    let indices = [image_index];
+    let present_info = vk::PresentInfoKHR::builder()
+      .wait_semaphores(wait_semaphores)
+      .swapchains(&swapchains)
+      .image_indices(&indices);
+
+    match unsafe { self.swapchain_loader.queue_present(queue, &present_info) } {
+      Ok(is_suboptimal) => Ok(is_suboptimal),
+      Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => Err(RendererError::OutOfDate),
+      Err(vk_result) => Err(RendererError::Swapchain(vk_result)),
     }
+  }
+
+  pub unsafe fn cleanup(&mut self, logical_device: &ash::Device) {
+    // The fences in images_in_flight belong to the frames in flight, so they aren't destroyed here
     for semaphore in &self.rendering_finished {
       logical_device.destroy_semaphore(*semaphore, None); // Destroy rendering semaphores
     }
@@ -140,4 +207,76 @@ impl VulkanSwapchain {
 
     self.swapchain_loader.destroy_swapchain(self.swapchain, None); // Destroy the swapchain
   }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const SRGB: vk::ColorSpaceKHR = vk::ColorSpaceKHR::SRGB_NONLINEAR;
+  const HDR10: vk::ColorSpaceKHR = vk::ColorSpaceKHR::HDR10_ST2084_EXT;
+  const SCRGB: vk::ColorSpaceKHR = vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT;
+
+  type Format = (vk::Format, vk::ColorSpaceKHR);
+
+  #[test]
+  fn pick_surface_format() {
+    // Name, what the surface offers, what's asked for, what's picked
+    let cases: &[(&str, &[Format], SurfaceColorSpace, Option<Format>)] = &[
+      ("lone UNDEFINED", &[(vk::Format::UNDEFINED, SRGB)], SurfaceColorSpace::Srgb, Some((vk::Format::B8G8R8A8_SRGB, SRGB))),
+      ("lone UNDEFINED, HDR wanted", &[(vk::Format::UNDEFINED, SRGB)], SurfaceColorSpace::Hdr10, Some((vk::Format::B8G8R8A8_SRGB, SRGB))),
+      (
+        "sRGB over UNORM",
+        &[(vk::Format::B8G8R8A8_UNORM, SRGB), (vk::Format::R8G8B8A8_UNORM, SRGB), (vk::Format::R8G8B8A8_SRGB, SRGB)],
+        SurfaceColorSpace::Srgb,
+        Some((vk::Format::R8G8B8A8_SRGB, SRGB)),
+      ),
+      (
+        "most common sRGB format first",
+        &[(vk::Format::R8G8B8A8_SRGB, SRGB), (vk::Format::B8G8R8A8_SRGB, SRGB)],
+        SurfaceColorSpace::Srgb,
+        Some((vk::Format::B8G8R8A8_SRGB, SRGB)),
+      ),
+      ("only UNORM", &[(vk::Format::B8G8R8A8_UNORM, SRGB)], SurfaceColorSpace::Srgb, Some((vk::Format::B8G8R8A8_UNORM, SRGB))),
+      (
+        "HDR10 offered",
+        &[(vk::Format::B8G8R8A8_SRGB, SRGB), (vk::Format::A2B10G10R10_UNORM_PACK32, HDR10)],
+        SurfaceColorSpace::Hdr10,
+        Some((vk::Format::A2B10G10R10_UNORM_PACK32, HDR10)),
+      ),
+      (
+        "HDR10 offered, sRGB wanted",
+        &[(vk::Format::A2B10G10R10_UNORM_PACK32, HDR10), (vk::Format::B8G8R8A8_SRGB, SRGB)],
+        SurfaceColorSpace::Srgb,
+        Some((vk::Format::B8G8R8A8_SRGB, SRGB)),
+      ),
+      (
+        "HDR10 not offered",
+        &[(vk::Format::B8G8R8A8_UNORM, SRGB), (vk::Format::B8G8R8A8_SRGB, SRGB), (vk::Format::R16G16B16A16_SFLOAT, SCRGB)],
+        SurfaceColorSpace::Hdr10,
+        Some((vk::Format::B8G8R8A8_SRGB, SRGB)),
+      ),
+      (
+        "scRGB offered",
+        &[(vk::Format::B8G8R8A8_SRGB, SRGB), (vk::Format::R16G16B16A16_SFLOAT, SCRGB)],
+        SurfaceColorSpace::ScRgb,
+        Some((vk::Format::R16G16B16A16_SFLOAT, SCRGB)),
+      ),
+      (
+        "scRGB not offered",
+        &[(vk::Format::A2B10G10R10_UNORM_PACK32, HDR10), (vk::Format::B8G8R8A8_SRGB, SRGB)],
+        SurfaceColorSpace::ScRgb,
+        Some((vk::Format::B8G8R8A8_SRGB, SRGB)),
+      ),
+      ("nothing for sRGB displays", &[(vk::Format::A2B10G10R10_UNORM_PACK32, HDR10)], SurfaceColorSpace::Srgb, Some((vk::Format::A2B10G10R10_UNORM_PACK32, HDR10))),
+      ("no formats", &[], SurfaceColorSpace::Srgb, None),
+      ("no formats, HDR wanted", &[], SurfaceColorSpace::Hdr10, None),
+    ];
+
+    for (name, formats, color_space, expected) in cases {
+      let formats: Vec<vk::SurfaceFormatKHR> = formats.iter().map(|&(format, color_space)| vk::SurfaceFormatKHR { format, color_space }).collect();
+      let picked = VulkanSwapchain::pick_surface_format(&formats, *color_space).map(|format| (format.format, format.color_space));
+      assert_eq!(picked, *expected, "{}", name);
+    }
+  }
 }
\ No newline at end of file
@@ -0,0 +1,125 @@
+use ash::vk;
+use gpu_allocator::vulkan::*;
+use gpu_allocator::MemoryLocation;
+
+use super::descriptors::*;
+use super::error::*;
+use super::upload::*;
+
+// An image the shaders can sample from, along with the view, sampler and descriptor set needed to bind it.
+// The pixels live in device local memory, they're uploaded through the Uploader so the texture can't be drawn with
+// until the next flush (draw_frame flushes before recording).
+pub struct Texture {
+  pub image: vk::Image,
+  pub allocation: Allocation,
+  pub imageview: vk::ImageView,
+  pub sampler: vk::Sampler,
+  pub descriptor_set: vk::DescriptorSet,
+  pub extent: vk::Extent2D,
+}
+
+impl Texture {
+  // 4 bytes per pixel. Image files are sRGB encoded, an sRGB format has them decoded to linear colors when sampled
+  // (and the color target encodes them again, so the pixels end up on screen unchanged)
+  pub const FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+  // Load a PNG or JPEG from disk
+  pub fn from_file(
+    logical_device: &ash::Device, allocator: &mut Allocator, uploader: &mut Uploader, descriptors: &mut Descriptors, path: &std::path::Path,
+  ) -> Result<Texture, RendererError> {
+    let image = image::open(path)?.to_rgba8();
+    Texture::from_rgba(logical_device, allocator, uploader, descriptors, image.width(), image.height(), image.as_raw())
+  }
+
+  // Load a PNG or JPEG that's already in memory (e.g. from include_bytes!)
+  pub fn from_memory(
+    logical_device: &ash::Device, allocator: &mut Allocator, uploader: &mut Uploader, descriptors: &mut Descriptors, bytes: &[u8],
+  ) -> Result<Texture, RendererError> {
+    let image = image::load_from_memory(bytes)?.to_rgba8();
+    Texture::from_rgba(logical_device, allocator, uploader, descriptors, image.width(), image.height(), image.as_raw())
+  }
+
+  // Create a texture from tightly packed RGBA8 rows, top row first
+  pub fn from_rgba(
+    logical_device: &ash::Device, allocator: &mut Allocator, uploader: &mut Uploader, descriptors: &mut Descriptors,
+    width: u32, height: u32, pixels: &[u8],
+  ) -> Result<Texture, RendererError> {
+    let expected = width as usize * height as usize * 4;
+    if pixels.len() != expected || width == 0 || height == 0 {
+      return Err(RendererError::TextureDataSize { expected, actual: pixels.len() });
+    }
+    let extent = vk::Extent2D { width, height };
+
+    let image_create_info = vk::ImageCreateInfo::builder()
+      .image_type(vk::ImageType::TYPE_2D)
+      .format(Texture::FORMAT)
+      .extent(vk::Extent3D { width, height, depth: 1 })
+      .mip_levels(1)
+      .array_layers(1)
+      .samples(vk::SampleCountFlags::TYPE_1)
+      .tiling(vk::ImageTiling::OPTIMAL)
+      .usage(vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED) // We copy the pixels in and then sample from it
+      .sharing_mode(vk::SharingMode::EXCLUSIVE) // Ownership is transferred by the uploader if the queue families differ
+      .initial_layout(vk::ImageLayout::UNDEFINED);
+    let image = unsafe { logical_device.create_image(&image_create_info, None)? };
+
+    let allocation = allocator.allocate(&AllocationCreateDesc {
+      requirements: unsafe { logical_device.get_image_memory_requirements(image) },
+      location: MemoryLocation::GpuOnly,
+      linear: false, // Optimal tiling images are not linear
+      name: "Texture",
+    })?;
+    unsafe { logical_device.bind_image_memory(image, allocation.memory(), allocation.offset())? };
+
+    uploader.upload_to_image(logical_device, allocator, image, extent, vk::PipelineStageFlags::FRAGMENT_SHADER, pixels)?;
+
+    let subresource_range = vk::ImageSubresourceRange::builder()
+      .aspect_mask(vk::ImageAspectFlags::COLOR)
+      .base_mip_level(0)
+      .level_count(1)
+      .base_array_layer(0)
+      .layer_count(1);
+    let imageview_create_info = vk::ImageViewCreateInfo::builder()
+      .image(image)
+      .view_type(vk::ImageViewType::TYPE_2D)
+      .format(Texture::FORMAT)
+      .subresource_range(*subresource_range);
+    let imageview = unsafe { logical_device.create_image_view(&imageview_create_info, None)? };
+
+    let sampler_create_info = vk::SamplerCreateInfo::builder()
+      .mag_filter(vk::Filter::LINEAR)
+      .min_filter(vk::Filter::LINEAR)
+      .mipmap_mode(vk::SamplerMipmapMode::NEAREST) // There's only one mip level
+      .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE) // Don't bleed in the opposite edge when sampling near the border
+      .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+      .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+      .anisotropy_enable(false) // Would need the samplerAnisotropy feature
+      .min_lod(0.0)
+      .max_lod(0.0)
+      .unnormalized_coordinates(false); // UVs go from 0 to 1
+    let sampler = unsafe { logical_device.create_sampler(&sampler_create_info, None)? };
+
+    let descriptor_set = descriptors.allocate_texture_set(logical_device, imageview, sampler)?;
+
+    Ok(Texture {
+      image,
+      allocation,
+      imageview,
+      sampler,
+      descriptor_set,
+      extent,
+    })
+  }
+
+  // The texture must no longer be in use by the GPU
+  pub fn destroy(&mut self, logical_device: &ash::Device, allocator: &mut Allocator, descriptors: &mut Descriptors) -> Result<(), RendererError> {
+    descriptors.free_set(logical_device, self.descriptor_set)?;
+    unsafe {
+      logical_device.destroy_sampler(self.sampler, None);
+      logical_device.destroy_image_view(self.imageview, None);
+      logical_device.destroy_image(self.image, None);
+    }
+    allocator.free(std::mem::take(&mut self.allocation))?;
+    Ok(())
+  }
+}
\ No newline at end of file
@@ -0,0 +1,71 @@
+use ash::{vk};
+use gpu_allocator::vulkan::*;
+use gpu_allocator::MemoryLocation;
+
+use super::error::*;
+
+// A host visible uniform buffer holding a single value that the CPU overwrites (e.g. once per frame).
+// Use one per frame in flight, the GPU may still be reading the previous frame's copy
+pub struct UniformBuffer {
+  pub buffer: vk::Buffer,
+  pub allocation: Allocation,
+  size: u64,
+}
+
+impl UniformBuffer {
+  // Create a uniform buffer big enough for one T
+  pub fn new<T: Copy>(device: &ash::Device, allocator: &mut Allocator) -> Result<UniformBuffer, RendererError> {
+    let size = std::mem::size_of::<T>() as u64;
+    let uniform_buffer_create_info = vk::BufferCreateInfo::builder()
+      .size(size)
+      .usage(vk::BufferUsageFlags::UNIFORM_BUFFER)
+      .sharing_mode(vk::SharingMode::EXCLUSIVE);
+    let buffer = unsafe { device.create_buffer(&uniform_buffer_create_info, None)? };
+
+    let allocation = allocator.allocate(&AllocationCreateDesc {
+      requirements: unsafe { device.get_buffer_memory_requirements(buffer) },
+      location: MemoryLocation::CpuToGpu,
+      linear: true, // Buffers are always linear
+      name: "Uniform Buffer",
+    })?;
+    unsafe { device.bind_buffer_memory(buffer, allocation.memory(), allocation.offset())? };
+
+    Ok(UniformBuffer {
+      buffer,
+      allocation,
+      size,
+    })
+  }
+
+  // Overwrite the value. The GPU must not be using the buffer (i.e. its frame in flight has finished)
+  pub fn update<T: Copy>(&mut self, data: &T) -> Result<(), RendererError> {
+    let requested = std::mem::size_of::<T>() as u64;
+    if requested > self.size {
+      return Err(RendererError::BufferTooSmall { capacity: self.size, requested });
+    }
+    let dst = self.allocation.mapped_ptr().ok_or(RendererError::BufferNotMapped)?.cast().as_ptr();
+    unsafe { std::ptr::copy_nonoverlapping(data as *const T, dst, 1) };
+    Ok(())
+  }
+
+  pub fn destroy(&mut self, device: &ash::Device, allocator: &mut Allocator) -> Result<(), RendererError> {
+    allocator.free(std::mem::take(&mut self.allocation))?;
+    unsafe { device.destroy_buffer(self.buffer, None) };
+    Ok(())
+  }
+
+  pub fn get_buffer(&self) -> vk::Buffer {
+    self.buffer
+  }
+
+  // The size of the value it holds (in bytes), which is the range to bind
+  pub fn get_size(&self) -> vk::DeviceSize {
+    self.size
+  }
+}
+
+// The uniform buffers of one frame in flight, with the descriptor sets binding them
+pub struct FrameUniforms {
+  pub camera_buffer: UniformBuffer,
+  pub camera_set: vk::DescriptorSet, // Allocated from the frame's descriptor allocator, so replaced every time the frame is drawn
+}
\ No newline at end of file
@@ -0,0 +1,57 @@
+use ash::{vk};
+
+// Data the shaders get from outside the vertex buffers. The layouts must match shader.vert
+// (the uniform block is std140, which for nothing but mat4s and vec4s is the same as repr(C))
+
+// The camera, in the uniform buffer at set 0, binding 0. One copy per frame in flight, written when the frame is submitted
+#[repr(C)]
+#[derive(Clone, Debug, Copy, PartialEq)]
+pub struct CameraUniform {
+  pub view_projection: [[f32; 4]; 4], // Column major, world space to clip space
+}
+
+impl CameraUniform {
+  // World space is clip space
+  pub const IDENTITY: CameraUniform = CameraUniform {
+    view_projection: [
+      [1.0, 0.0, 0.0, 0.0],
+      [0.0, 1.0, 0.0, 0.0],
+      [0.0, 0.0, 1.0, 0.0],
+      [0.0, 0.0, 0.0, 1.0],
+    ],
+  };
+}
+
+// Per draw data, pushed straight into the command buffer (no buffer or descriptor needed). Applied on top of the instance data
+#[repr(C)]
+#[derive(Clone, Debug, Copy, PartialEq)]
+pub struct PushConstants {
+  pub model: [[f32; 4]; 4], // Column major, transforms every vertex of the draw
+  pub tint: [f32; 4], // Multiplied with every vertex color of the draw
+}
+
+impl PushConstants {
+  // Leaves the draw unchanged
+  pub const IDENTITY: PushConstants = PushConstants {
+    model: [
+      [1.0, 0.0, 0.0, 0.0],
+      [0.0, 1.0, 0.0, 0.0],
+      [0.0, 0.0, 1.0, 0.0],
+      [0.0, 0.0, 0.0, 1.0],
+    ],
+    tint: [1.0, 1.0, 1.0, 1.0],
+  };
+
+  // The range the pipeline layout reserves for them. 80 bytes, well under the 128 every device supports
+  pub fn get_range() -> vk::PushConstantRange {
+    vk::PushConstantRange {
+      stage_flags: vk::ShaderStageFlags::VERTEX,
+      offset: 0,
+      size: std::mem::size_of::<PushConstants>() as u32,
+    }
+  }
+
+  pub fn as_bytes(&self) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(self as *const PushConstants as *const u8, std::mem::size_of::<PushConstants>()) }
+  }
+}
\ No newline at end of file
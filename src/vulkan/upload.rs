@@ -0,0 +1,365 @@
+use ash::vk;
+use gpu_allocator::vulkan::*;
+use gpu_allocator::MemoryLocation;
+
+use super::command_pool::*;
+use super::queue::*;
+use super::error::*;
+
+// A host visible buffer the data is written into before being copied to its device local destination
+struct StagingBuffer {
+  buffer: vk::Buffer,
+  allocation: Allocation,
+}
+
+// A buffer waiting for its upload to finish, and how it will be used afterwards (needed for the barriers)
+struct PendingBuffer {
+  buffer: vk::Buffer,
+  dst_stage: vk::PipelineStageFlags,
+  dst_access: vk::AccessFlags,
+}
+
+// An image waiting for its upload to finish. It's in TRANSFER_DST_OPTIMAL until the flush moves it to SHADER_READ_ONLY_OPTIMAL
+struct PendingImage {
+  image: vk::Image,
+  dst_stage: vk::PipelineStageFlags,
+}
+
+// Uploads data into device local (GpuOnly) memory, which the CPU can't write to directly. Data is written into host visible
+// staging buffers and then copied on the transfer queue. Uploads are batched: queue as many as needed and then flush them
+// all in a single submission. When the transfer queue is from a different family than the graphics queue, ownership of the
+// destination buffers (and images) is released by the transfer queue and acquired by the graphics queue (we use exclusive sharing).
+pub struct Uploader {
+  transfer_commandbuffer: vk::CommandBuffer,
+  graphics_commandbuffer: vk::CommandBuffer, // Only used to acquire ownership when the queue families differ
+  ownership_semaphore: vk::Semaphore, // The graphics queue waits on this before acquiring ownership
+  fence: vk::Fence, // Signalled when the whole batch is done
+  transfer_family: u32,
+  graphics_family: u32,
+  staging_buffers: Vec<StagingBuffer>,
+  pending_buffers: Vec<PendingBuffer>,
+  pending_images: Vec<PendingImage>,
+  is_recording: bool,
+}
+
+impl Uploader {
+  pub fn init(logical_device: &ash::Device, pools: &Pools, queue_families: &QueueFamilies) -> Result<Uploader, RendererError> {
+    let transfer_allocate_info = vk::CommandBufferAllocateInfo::builder()
+      .command_pool(pools.transfer_command_pool)
+      .level(vk::CommandBufferLevel::PRIMARY)
+      .command_buffer_count(1);
+    let transfer_commandbuffer = unsafe { logical_device.allocate_command_buffers(&transfer_allocate_info)? }[0];
+
+    let graphics_allocate_info = vk::CommandBufferAllocateInfo::builder()
+      .command_pool(pools.graphics_command_pool)
+      .level(vk::CommandBufferLevel::PRIMARY)
+      .command_buffer_count(1);
+    let graphics_commandbuffer = unsafe { logical_device.allocate_command_buffers(&graphics_allocate_info)? }[0];
+
+    let ownership_semaphore = unsafe { logical_device.create_semaphore(&vk::SemaphoreCreateInfo::builder(), None)? };
+    let fence = unsafe { logical_device.create_fence(&vk::FenceCreateInfo::builder(), None)? };
+
+    Ok(Uploader {
+      transfer_commandbuffer,
+      graphics_commandbuffer,
+      ownership_semaphore,
+      fence,
+      transfer_family: queue_families.transfer.unwrap(),
+      graphics_family: queue_families.graphics.unwrap(),
+      staging_buffers: vec![],
+      pending_buffers: vec![],
+      pending_images: vec![],
+      is_recording: false,
+    })
+  }
+
+  // Whether there are uploads queued that haven't been flushed yet
+  pub fn has_pending(&self) -> bool {
+    !self.pending_buffers.is_empty() || !self.pending_images.is_empty()
+  }
+
+  // Queue an upload of data into dst (which must have been created with TRANSFER_DST usage and EXCLUSIVE sharing).
+  // dst_stage and dst_access describe how the graphics queue will use the buffer (e.g. VERTEX_INPUT + VERTEX_ATTRIBUTE_READ).
+  // The data may not be used until flush has been called. Uploading nothing does nothing
+  pub fn upload_to_buffer(
+    &mut self, logical_device: &ash::Device, allocator: &mut Allocator, dst: vk::Buffer,
+    dst_stage: vk::PipelineStageFlags, dst_access: vk::AccessFlags, data: &[u8],
+  ) -> Result<(), RendererError> {
+    if data.is_empty() { // A staging buffer can't be empty, and there would be nothing to copy anyway
+      return Ok(());
+    }
+    let staging_buffer = self.create_staging_buffer(logical_device, allocator, data)?;
+
+    // Record the copy
+    self.begin_recording(logical_device)?;
+    let region = vk::BufferCopy { src_offset: 0, dst_offset: 0, size: data.len() as u64 };
+    unsafe { logical_device.cmd_copy_buffer(self.transfer_commandbuffer, staging_buffer, dst, &[region]) };
+    self.pending_buffers.push(PendingBuffer { buffer: dst, dst_stage, dst_access });
+    Ok(())
+  }
+
+  // Queue an upload of tightly packed pixels into the first mip level and layer of a color image (which must have been created
+  // with TRANSFER_DST usage, EXCLUSIVE sharing and an UNDEFINED initial layout). After the flush the image is in
+  // SHADER_READ_ONLY_OPTIMAL and can be sampled from dst_stage (e.g. FRAGMENT_SHADER)
+  pub fn upload_to_image(
+    &mut self, logical_device: &ash::Device, allocator: &mut Allocator, dst: vk::Image, extent: vk::Extent2D,
+    dst_stage: vk::PipelineStageFlags, data: &[u8],
+  ) -> Result<(), RendererError> {
+    let staging_buffer = self.create_staging_buffer(logical_device, allocator, data)?;
+
+    self.begin_recording(logical_device)?;
+    // The old contents don't matter, so transition from UNDEFINED to the layout copies are fastest into
+    let to_transfer_dst = vk::ImageMemoryBarrier::builder()
+      .src_access_mask(vk::AccessFlags::empty())
+      .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+      .old_layout(vk::ImageLayout::UNDEFINED)
+      .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+      .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+      .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+      .image(dst)
+      .subresource_range(Uploader::color_subresource_range());
+    let region = vk::BufferImageCopy::builder()
+      .buffer_offset(0)
+      .buffer_row_length(0) // 0 means tightly packed according to the image extent
+      .buffer_image_height(0)
+      .image_subresource(vk::ImageSubresourceLayers {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        mip_level: 0,
+        base_array_layer: 0,
+        layer_count: 1,
+      })
+      .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+      .image_extent(vk::Extent3D { width: extent.width, height: extent.height, depth: 1 });
+    unsafe {
+      logical_device.cmd_pipeline_barrier(
+        self.transfer_commandbuffer,
+        vk::PipelineStageFlags::TOP_OF_PIPE,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::DependencyFlags::empty(),
+        &[],
+        &[],
+        &[to_transfer_dst.build()],
+      );
+      logical_device.cmd_copy_buffer_to_image(
+        self.transfer_commandbuffer,
+        staging_buffer,
+        dst,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        &[region.build()],
+      );
+    }
+    self.pending_images.push(PendingImage { image: dst, dst_stage });
+    Ok(())
+  }
+
+  // The whole of a color image with a single mip level and layer
+  fn color_subresource_range() -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+      aspect_mask: vk::ImageAspectFlags::COLOR,
+      base_mip_level: 0,
+      level_count: 1,
+      base_array_layer: 0,
+      layer_count: 1,
+    }
+  }
+
+  // Create a host visible buffer holding data (which must not be empty), it's freed once the next flush is done
+  fn create_staging_buffer(&mut self, logical_device: &ash::Device, allocator: &mut Allocator, data: &[u8]) -> Result<vk::Buffer, RendererError> {
+    let staging_buffer_create_info = vk::BufferCreateInfo::builder()
+      .size(data.len() as u64)
+      .usage(vk::BufferUsageFlags::TRANSFER_SRC)
+      .sharing_mode(vk::SharingMode::EXCLUSIVE);
+    let staging_buffer = unsafe { logical_device.create_buffer(&staging_buffer_create_info, None)? };
+    let allocation = allocator.allocate(&AllocationCreateDesc {
+      requirements: unsafe { logical_device.get_buffer_memory_requirements(staging_buffer) },
+      location: MemoryLocation::CpuToGpu,
+      linear: true, // Buffers are always linear
+      name: "Staging Buffer",
+    })?;
+    unsafe { logical_device.bind_buffer_memory(staging_buffer, allocation.memory(), allocation.offset())? };
+    let dst_ptr = allocation.mapped_ptr().ok_or(RendererError::BufferNotMapped)?.cast().as_ptr();
+    unsafe { std::ptr::copy_nonoverlapping(data.as_ptr(), dst_ptr, data.len()) };
+    self.staging_buffers.push(StagingBuffer { buffer: staging_buffer, allocation });
+    Ok(staging_buffer)
+  }
+
+  fn begin_recording(&mut self, logical_device: &ash::Device) -> Result<(), RendererError> {
+    if !self.is_recording {
+      let begininfo = vk::CommandBufferBeginInfo::builder().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+      unsafe { logical_device.begin_command_buffer(self.transfer_commandbuffer, &begininfo)? };
+      self.is_recording = true;
+    }
+    Ok(())
+  }
+
+  // Submit every queued upload and wait for them to finish, then free the staging buffers.
+  // Afterwards the destination buffers and images are owned by the graphics queue family and ready to be used
+  pub fn flush(&mut self, logical_device: &ash::Device, allocator: &mut Allocator, queues: &Queues) -> Result<(), RendererError> {
+    if !self.is_recording {
+      return Ok(());
+    }
+    self.is_recording = false;
+    let needs_ownership_transfer = self.transfer_family != self.graphics_family;
+
+    // On the transfer queue, make the copies available. Either by releasing ownership to the graphics family,
+    // or (same family) with a plain barrier to the stage that uses the buffer
+    let release_barriers: Vec<vk::BufferMemoryBarrier> = self.pending_buffers.iter().map(|pending| {
+      let barrier = vk::BufferMemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .buffer(pending.buffer)
+        .offset(0)
+        .size(vk::WHOLE_SIZE);
+      if needs_ownership_transfer {
+        barrier
+          .dst_access_mask(vk::AccessFlags::empty()) // Ignored for a release
+          .src_queue_family_index(self.transfer_family)
+          .dst_queue_family_index(self.graphics_family)
+          .build()
+      } else {
+        barrier
+          .dst_access_mask(pending.dst_access)
+          .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+          .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+          .build()
+      }
+    }).collect();
+    // Images also get transitioned to the layout they're sampled in. With an ownership transfer the release and acquire barriers
+    // both describe the same transition, and it only happens once
+    let release_image_barriers: Vec<vk::ImageMemoryBarrier> = self.pending_images.iter().map(|pending| {
+      let barrier = vk::ImageMemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+        .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image(pending.image)
+        .subresource_range(Uploader::color_subresource_range());
+      if needs_ownership_transfer {
+        barrier
+          .dst_access_mask(vk::AccessFlags::empty())
+          .src_queue_family_index(self.transfer_family)
+          .dst_queue_family_index(self.graphics_family)
+          .build()
+      } else {
+        barrier
+          .dst_access_mask(vk::AccessFlags::SHADER_READ)
+          .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+          .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+          .build()
+      }
+    }).collect();
+    let dst_stages = self.pending_buffers.iter().map(|pending| pending.dst_stage)
+      .chain(self.pending_images.iter().map(|pending| pending.dst_stage))
+      .fold(vk::PipelineStageFlags::empty(), |stages, stage| stages | stage);
+    let release_dst_stage = if needs_ownership_transfer {
+      vk::PipelineStageFlags::BOTTOM_OF_PIPE
+    } else {
+      dst_stages
+    };
+
+    unsafe {
+      logical_device.cmd_pipeline_barrier(
+        self.transfer_commandbuffer,
+        vk::PipelineStageFlags::TRANSFER,
+        release_dst_stage,
+        vk::DependencyFlags::empty(),
+        &[],
+        &release_barriers,
+        &release_image_barriers,
+      );
+      logical_device.end_command_buffer(self.transfer_commandbuffer)?;
+    }
+
+    let transfer_commandbuffers = [self.transfer_commandbuffer];
+    let signal_semaphores = [self.ownership_semaphore];
+    if needs_ownership_transfer {
+      // The transfer queue signals the semaphore, then the graphics queue acquires ownership (the barriers must match the release ones)
+      let acquire_barriers: Vec<vk::BufferMemoryBarrier> = self.pending_buffers.iter().map(|pending| {
+        vk::BufferMemoryBarrier::builder()
+          .src_access_mask(vk::AccessFlags::empty()) // Ignored for an acquire
+          .dst_access_mask(pending.dst_access)
+          .src_queue_family_index(self.transfer_family)
+          .dst_queue_family_index(self.graphics_family)
+          .buffer(pending.buffer)
+          .offset(0)
+          .size(vk::WHOLE_SIZE)
+          .build()
+      }).collect();
+      let acquire_image_barriers: Vec<vk::ImageMemoryBarrier> = self.pending_images.iter().map(|pending| {
+        vk::ImageMemoryBarrier::builder()
+          .src_access_mask(vk::AccessFlags::empty()) // Ignored for an acquire
+          .dst_access_mask(vk::AccessFlags::SHADER_READ)
+          .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+          .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+          .src_queue_family_index(self.transfer_family)
+          .dst_queue_family_index(self.graphics_family)
+          .image(pending.image)
+          .subresource_range(Uploader::color_subresource_range())
+          .build()
+      }).collect();
+      let acquire_dst_stage = dst_stages;
+
+      let begininfo = vk::CommandBufferBeginInfo::builder().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+      unsafe {
+        logical_device.begin_command_buffer(self.graphics_commandbuffer, &begininfo)?;
+        logical_device.cmd_pipeline_barrier(
+          self.graphics_commandbuffer,
+          vk::PipelineStageFlags::TOP_OF_PIPE,
+          acquire_dst_stage,
+          vk::DependencyFlags::empty(),
+          &[],
+          &acquire_barriers,
+          &acquire_image_barriers,
+        );
+        logical_device.end_command_buffer(self.graphics_commandbuffer)?;
+      }
+
+      let transfer_submit = [vk::SubmitInfo::builder()
+        .command_buffers(&transfer_commandbuffers)
+        .signal_semaphores(&signal_semaphores)
+        .build()];
+      let graphics_commandbuffers = [self.graphics_commandbuffer];
+      let wait_stages = [acquire_dst_stage];
+      let graphics_submit = [vk::SubmitInfo::builder()
+        .wait_semaphores(&signal_semaphores)
+        .wait_dst_stage_mask(&wait_stages)
+        .command_buffers(&graphics_commandbuffers)
+        .build()];
+      unsafe {
+        logical_device.queue_submit(queues.transfer_queue, &transfer_submit, vk::Fence::null())?;
+        logical_device.queue_submit(queues.graphics_queue, &graphics_submit, self.fence)?;
+      }
+    } else {
+      let transfer_submit = [vk::SubmitInfo::builder()
+        .command_buffers(&transfer_commandbuffers)
+        .build()];
+      unsafe { logical_device.queue_submit(queues.transfer_queue, &transfer_submit, self.fence)? };
+    }
+
+    // Wait for the uploads to finish so the staging buffers can be freed
+    unsafe {
+      logical_device.wait_for_fences(&[self.fence], true, std::u64::MAX)?;
+      logical_device.reset_fences(&[self.fence])?;
+    }
+    self.pending_buffers.clear();
+    self.pending_images.clear();
+    self.free_staging_buffers(logical_device, allocator)
+  }
+
+  fn free_staging_buffers(&mut self, logical_device: &ash::Device, allocator: &mut Allocator) -> Result<(), RendererError> {
+    for staging_buffer in self.staging_buffers.drain(..) {
+      unsafe { logical_device.destroy_buffer(staging_buffer.buffer, None) };
+      allocator.free(staging_buffer.allocation)?;
+    }
+    Ok(())
+  }
+
+  pub fn cleanup(&mut self, logical_device: &ash::Device, allocator: &mut Allocator, pools: &Pools) -> Result<(), RendererError> {
+    self.free_staging_buffers(logical_device, allocator)?;
+    unsafe {
+      logical_device.destroy_fence(self.fence, None);
+      logical_device.destroy_semaphore(self.ownership_semaphore, None);
+      logical_device.free_command_buffers(pools.transfer_command_pool, &[self.transfer_commandbuffer]);
+      logical_device.free_command_buffers(pools.graphics_command_pool, &[self.graphics_commandbuffer]);
+    }
+    Ok(())
+  }
+}
@@ -7,6 +7,7 @@ use memoffset::offset_of;
 pub struct Vertex {
   pub pos: [f32; 4],
   pub color: [f32; 4],
+  pub uv: [f32; 2], // Texture coordinates, (0, 0) is the top left of the texture. Untextured geometry samples a white texture, so any value works
 }
 impl Vertex {
   pub fn get_binding_description() -> [vk::VertexInputBindingDescription; 1] {
@@ -17,7 +18,7 @@ impl Vertex {
     }]
   }
 
-  pub fn get_attribute_descriptions() -> [vk::VertexInputAttributeDescription; 2] {
+  pub fn get_attribute_descriptions() -> [vk::VertexInputAttributeDescription; 3] {
     [
       vk::VertexInputAttributeDescription {
         binding: 0,
@@ -31,6 +32,77 @@ impl Vertex {
         format: vk::Format::R32G32B32A32_SFLOAT,
         offset: offset_of!(Vertex, color) as u32,
       },
+      vk::VertexInputAttributeDescription {
+        binding: 0,
+        location: 2,
+        format: vk::Format::R32G32_SFLOAT,
+        offset: offset_of!(Vertex, uv) as u32,
+      },
+    ]
+  }
+}
+
+// Per instance data for instanced drawing. Every draw is instanced: the vertices are drawn once per instance, transformed by the
+// instance's model matrix, multiplied by its color and with their UVs mapped into its UV rect. Non-instanced draws use IDENTITY
+#[repr(C)]
+#[derive(Clone, Debug, Copy, PartialEq)]
+pub struct InstanceData {
+  pub model: [[f32; 4]; 4], // Column major, like GLSL's mat4
+  pub color: [f32; 4],
+  pub uv_rect: [f32; 4], // min u, min v, max u, max v
+}
+
+impl InstanceData {
+  // Leaves the vertices unchanged
+  pub const IDENTITY: InstanceData = InstanceData {
+    model: [
+      [1.0, 0.0, 0.0, 0.0],
+      [0.0, 1.0, 0.0, 0.0],
+      [0.0, 0.0, 1.0, 0.0],
+      [0.0, 0.0, 0.0, 1.0],
+    ],
+    color: [1.0, 1.0, 1.0, 1.0],
+    uv_rect: [0.0, 0.0, 1.0, 1.0],
+  };
+
+  // Scale the vertices by size and then move them to position (e.g. to place copies of a unit quad)
+  pub fn new_2d(position: [f32; 3], size: [f32; 2], color: [f32; 4], uv_rect: [f32; 4]) -> InstanceData {
+    InstanceData {
+      model: [
+        [size[0], 0.0, 0.0, 0.0],
+        [0.0, size[1], 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [position[0], position[1], position[2], 1.0],
+      ],
+      color,
+      uv_rect,
+    }
+  }
+
+  pub fn get_binding_description() -> vk::VertexInputBindingDescription {
+    vk::VertexInputBindingDescription {
+      binding: 1,
+      stride: std::mem::size_of::<InstanceData>() as u32,
+      input_rate: vk::VertexInputRate::INSTANCE, // Advance once per instance instead of once per vertex
+    }
+  }
+
+  pub fn get_attribute_descriptions() -> [vk::VertexInputAttributeDescription; 6] {
+    let attribute = |location: u32, offset: usize| vk::VertexInputAttributeDescription {
+      binding: 1,
+      location,
+      format: vk::Format::R32G32B32A32_SFLOAT,
+      offset: offset as u32,
+    };
+    let column_size = std::mem::size_of::<[f32; 4]>();
+    [
+      // A mat4 takes up 4 locations, one per column
+      attribute(3, offset_of!(InstanceData, model)),
+      attribute(4, offset_of!(InstanceData, model) + column_size),
+      attribute(5, offset_of!(InstanceData, model) + column_size * 2),
+      attribute(6, offset_of!(InstanceData, model) + column_size * 3),
+      attribute(7, offset_of!(InstanceData, color)),
+      attribute(8, offset_of!(InstanceData, uv_rect)),
     ]
   }
 }
\ No newline at end of file
@@ -2,7 +2,7 @@ use ash::{vk};
 use gpu_allocator::vulkan::*;
 use gpu_allocator::MemoryLocation;
 
-use super::{vertex::Vertex};
+use super::{vertex::Vertex, error::*, upload::*};
 
 pub struct VertexBuffer {
   pub buffer: vk::Buffer,
@@ -11,49 +11,62 @@ pub struct VertexBuffer {
 }
 
 impl VertexBuffer {
-  pub fn new(device: &ash::Device, allocator: &mut Allocator, size: u64) -> VertexBuffer {
+  // Create a host visible vertex buffer that can be updated every frame
+  pub fn new(device: &ash::Device, allocator: &mut Allocator, size: u64) -> Result<VertexBuffer, RendererError> {
+    VertexBuffer::create(device, allocator, size, vk::BufferUsageFlags::VERTEX_BUFFER, MemoryLocation::CpuToGpu)
+  }
+
+  // Create a vertex buffer in device local memory (fastest for the GPU to read) holding the given vertices.
+  // It can't be updated afterwards, and the vertices aren't there until the uploader has been flushed
+  pub fn new_static(device: &ash::Device, allocator: &mut Allocator, uploader: &mut Uploader, data: &[Vertex]) -> Result<VertexBuffer, RendererError> {
+    let size = VertexBuffer::get_size_for_num_verts(data.len());
+    let mut vertex_buffer = VertexBuffer::create(device, allocator, size, vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST, MemoryLocation::GpuOnly)?;
+    let bytes = unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, size as usize) };
+    uploader.upload_to_buffer(device, allocator, vertex_buffer.buffer, vk::PipelineStageFlags::VERTEX_INPUT, vk::AccessFlags::VERTEX_ATTRIBUTE_READ, bytes)?;
+    vertex_buffer.vert_count = data.len() as u32;
+    Ok(vertex_buffer)
+  }
+
+  fn create(device: &ash::Device, allocator: &mut Allocator, size: u64, usage: vk::BufferUsageFlags, location: MemoryLocation) -> Result<VertexBuffer, RendererError> {
     let vertex_buffer_create_info = vk::BufferCreateInfo::builder()
-      .size(size)
-      .usage(vk::BufferUsageFlags::VERTEX_BUFFER)
+      .size(size.max(1)) // Vulkan doesn't allow empty buffers, so one for no vertices still gets a byte
+      .usage(usage)
       .sharing_mode(vk::SharingMode::EXCLUSIVE);
 
     let vert_buff = unsafe {
         device
-            .create_buffer(&vertex_buffer_create_info, None)
-            .expect("Failed to create Vertex Buffer")
+            .create_buffer(&vertex_buffer_create_info, None)?
     };
 
     let mem_requirements = unsafe { device.get_buffer_memory_requirements(vert_buff) };
-    let location = MemoryLocation::CpuToGpu;
 
     let allocation = allocator.allocate(&AllocationCreateDesc {
       requirements: mem_requirements,
       location,
       linear: true, // Buffers are always linear
       name: "Vertex Buffer",
-    }).expect("Failed to allocate memory for vertex buffer!");
+    })?;
 
     unsafe {
         // Bind the vertex buffer memory to the vertex buffer
         device
-            .bind_buffer_memory(vert_buff,  allocation.memory(), allocation.offset())
-            .expect("Failed to bind vertex buffer");
+            .bind_buffer_memory(vert_buff,  allocation.memory(), allocation.offset())?;
     }
 
-    VertexBuffer {
+    Ok(VertexBuffer {
       buffer: vert_buff,
-      allocation: allocation,
+      allocation,
       vert_count: 0,
-    }
+    })
   }
 
-  pub fn destroy(&mut self, device: &ash::Device, allocator: &mut Allocator) {
-    allocator.free(std::mem::take(&mut self.allocation)).expect("Failed to free vertex buffer memory!");
+  pub fn destroy(&mut self, device: &ash::Device, allocator: &mut Allocator) -> Result<(), RendererError> {
+    allocator.free(std::mem::take(&mut self.allocation))?;
     unsafe {
       device.destroy_buffer(self.buffer, None);
       //device.free_memory(self.memory, None);
     }
-    drop(self);
+    Ok(())
   }
 
   /// Returns the size for the number of vertices (in bytes)
@@ -61,8 +74,12 @@ impl VertexBuffer {
     (num_verts * std::mem::size_of::<Vertex>()) as u64
   }
 
-  pub fn update_buffer(&mut self, device: &ash::Device, data: &[Vertex]) {
-    let dst = self.allocation.mapped_ptr().unwrap().cast().as_ptr();
+  pub fn update_buffer(&mut self, _device: &ash::Device, data: &[Vertex]) -> Result<(), RendererError> {
+    let requested = VertexBuffer::get_size_for_num_verts(data.len());
+    if requested > self.allocation.size() {
+      return Err(RendererError::BufferTooSmall { capacity: self.allocation.size(), requested });
+    }
+    let dst = self.allocation.mapped_ptr().ok_or(RendererError::BufferNotMapped)?.cast().as_ptr();
     unsafe {
       std::ptr::copy_nonoverlapping(
           data.as_ptr(),
@@ -72,6 +89,7 @@ impl VertexBuffer {
     }
     self.vert_count = data.len() as u32;
     //println!("Updated vertex buffer with {} vertices", self.vert_count);
+    Ok(())
   }
 
   pub fn get_buffer(&self) -> vk::Buffer {
@@ -0,0 +1,17 @@
+// Shared by the integration tests, which need a Vulkan driver (a software one such as lavapipe is enough, see the README)
+
+use vulkan_renderer::vulkan::app::VulkanApp;
+use vulkan_renderer::vulkan::config::RendererConfig;
+use vulkan_renderer::vulkan::error::RendererError;
+
+// A headless app, or None (so the test can skip itself) if there's no Vulkan driver to create one with
+pub fn init_headless(width: u32, height: u32, config: RendererConfig) -> Option<VulkanApp> {
+  match VulkanApp::init_headless_with_config(width, height, config) {
+    Ok(app) => Some(app),
+    Err(error @ (RendererError::Instance(_) | RendererError::NoSuitableDevice)) => {
+      eprintln!("Skipping, no usable Vulkan driver: {}", error);
+      None
+    }
+    Err(error) => panic!("Failed to create the renderer: {}", error),
+  }
+}
\ No newline at end of file
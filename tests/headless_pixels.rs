@@ -0,0 +1,56 @@
+// Checks the pixels headless rendering hands back: where a quad lands, the row order and the sRGB encoding of the offscreen target.
+// Skipped without a Vulkan driver
+
+mod common;
+
+use vulkan_renderer::renderer::{Renderer, Vertex};
+use vulkan_renderer::vulkan::config::RendererConfig;
+
+const WIDTH: u32 = 64;
+const HEIGHT: u32 = 32; // Not square, so swapped rows and columns would show
+const CLEAR: [u8; 4] = [0, 0, 20, 255]; // VulkanApp's clear color, linear (0, 0, 0.007) sRGB encoded
+const QUAD_COLOR: [f32; 4] = [1.0, 0.5, 0.0, 1.0]; // Linear, like every color given to the renderer
+const QUAD_PIXEL: [u8; 4] = [255, 188, 0, 255]; // QUAD_COLOR sRGB encoded (0.5 linear is 188, not 128)
+
+fn pixel(pixels: &[u8], x: u32, y: u32) -> [u8; 4] {
+  let offset = ((y * WIDTH + x) * 4) as usize;
+  pixels[offset..offset + 4].try_into().unwrap()
+}
+
+fn assert_close(actual: [u8; 4], expected: [u8; 4], x: u32, y: u32) {
+  // The GPU may round the encoding either way
+  let close = actual.iter().zip(expected).all(|(&actual, expected)| actual.abs_diff(expected) <= 1);
+  assert!(close, "Pixel ({}, {}) is {:?}, expected {:?}", x, y, actual, expected);
+}
+
+#[test]
+fn quad_covers_the_top_left_quarter() {
+  let Some(mut app) = common::init_headless(WIDTH, HEIGHT, RendererConfig::default()) else { return };
+  let pipeline = app.default_pipeline();
+
+  // Without a camera positions are in clip space, where y = -1 is the top of the framebuffer
+  let vertex = |x: f32, y: f32| Vertex { pos: [x, y, 0.0, 1.0], color: QUAD_COLOR, uv: [0.0, 0.0] };
+  let vertex_buffer = app.create_static_vertex_buffer(&[vertex(-1.0, -1.0), vertex(0.0, -1.0), vertex(0.0, 0.0), vertex(-1.0, 0.0)]).unwrap();
+  let index_buffer = app.create_static_index_buffer(&[0, 1, 2, 2, 3, 0]).unwrap();
+
+  let frame = app.begin_frame().unwrap();
+  app.draw(&frame, pipeline, vertex_buffer, Some(index_buffer), None).unwrap();
+  let pixels = app.end_frame(frame).unwrap().expect("Headless frames hand back their pixels");
+  assert_eq!(pixels.len(), (WIDTH * HEIGHT * 4) as usize);
+
+  // Pixels on the quad's edges may be blended with the clear color by MSAA, so only check ones clear of them
+  for y in 0..HEIGHT {
+    for x in 0..WIDTH {
+      let (inside_x, inside_y) = (x < WIDTH / 2 - 1, y < HEIGHT / 2 - 1);
+      let (outside_x, outside_y) = (x > WIDTH / 2, y > HEIGHT / 2);
+      if inside_x && inside_y {
+        assert_close(pixel(&pixels, x, y), QUAD_PIXEL, x, y);
+      } else if outside_x || outside_y {
+        assert_close(pixel(&pixels, x, y), CLEAR, x, y);
+      }
+    }
+  }
+
+  app.destroy_buffer(index_buffer).unwrap();
+  app.destroy_buffer(vertex_buffer).unwrap();
+}
\ No newline at end of file
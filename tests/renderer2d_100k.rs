@@ -0,0 +1,53 @@
+// Draws the Notes.md target of 100K quads a frame through the 2D batch renderer, headless, and reports how long the frames took.
+// Frame times are only meaningful in release builds: cargo test --release --test renderer2d_100k -- --nocapture
+// Skipped without a Vulkan driver
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use vulkan_renderer::renderer::Renderer;
+use vulkan_renderer::renderer::renderer2d::{Renderer2D, Renderer2DStats};
+use vulkan_renderer::vulkan::config::RendererConfig;
+
+const QUADS: usize = 100_000;
+const COLUMNS: usize = 400;
+const FRAMES: usize = 10;
+
+#[test]
+fn draws_100k_quads_in_one_draw_call() {
+  let Some(mut app) = common::init_headless(512, 512, RendererConfig::default()) else { return };
+  let mut renderer2d = Renderer2D::new(&mut app).unwrap();
+
+  let rows = QUADS.div_ceil(COLUMNS);
+  let cell_size = [2.0 / COLUMNS as f32, 2.0 / rows as f32];
+  let mut frame_times = Vec::with_capacity(FRAMES);
+  for _ in 0..FRAMES {
+    let start = Instant::now();
+    let frame = app.begin_frame().unwrap();
+    renderer2d.begin_scene();
+    for quad in 0..QUADS {
+      let (x, y) = ((quad % COLUMNS) as f32, (quad / COLUMNS) as f32);
+      renderer2d.draw_quad(
+        [-1.0 + (x + 0.5) * cell_size[0], -1.0 + (y + 0.5) * cell_size[1], 0.0],
+        [cell_size[0] * 0.8, cell_size[1] * 0.8],
+        [x / COLUMNS as f32, y / rows as f32, 0.5, 1.0],
+      );
+    }
+    renderer2d.end_scene(&mut app, &frame).unwrap();
+    app.end_frame(frame).unwrap(); // Headless frames wait for the GPU, so this is the whole frame
+    frame_times.push(start.elapsed());
+  }
+
+  // Untextured quads all share one batch
+  assert_eq!(renderer2d.stats(), Renderer2DStats { quads: QUADS, draw_calls: 1 });
+  // The first frames grow the buffers, so leave them out
+  let steady: &[Duration] = &frame_times[FRAMES / 2..];
+  let average = steady.iter().sum::<Duration>() / steady.len() as u32;
+  eprintln!(
+    "{} quads in {} draw call(s): {:.2}ms per frame on average (slowest {:.2}ms, first {:.2}ms)",
+    QUADS, renderer2d.stats().draw_calls, average.as_secs_f64() * 1000.0,
+    steady.iter().max().unwrap().as_secs_f64() * 1000.0, frame_times[0].as_secs_f64() * 1000.0,
+  );
+  renderer2d.destroy(&mut app).unwrap();
+}
\ No newline at end of file
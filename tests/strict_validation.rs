@@ -0,0 +1,52 @@
+// Renders through the Renderer trait in strict mode, so any validation error fails the test. Needs a Vulkan driver (a software one
+// such as lavapipe is enough, see the README) and is skipped without one. The validation layer is used if it's installed
+
+mod common;
+
+use vulkan_renderer::renderer::{BlendMode, PipelineDescription, Renderer, Topology, Vertex};
+use vulkan_renderer::vulkan::config::RendererConfig;
+
+const WIDTH: u32 = 64;
+const HEIGHT: u32 = 64;
+
+fn vertex(x: f32, y: f32, u: f32, v: f32) -> Vertex {
+  Vertex { pos: [x, y, 0.0, 1.0], color: [1.0, 1.0, 1.0, 1.0], uv: [u, v] }
+}
+
+#[test]
+fn strict_frame_has_no_validation_errors() {
+  let config = RendererConfig { strict_validation: true, ..Default::default() };
+  let Some(mut app) = common::init_headless(WIDTH, HEIGHT, config) else { return };
+  let pipeline = app.default_pipeline();
+
+  let vertices = [vertex(-0.5, -0.5, 0.0, 0.0), vertex(0.5, -0.5, 1.0, 0.0), vertex(0.5, 0.5, 1.0, 1.0), vertex(-0.5, 0.5, 0.0, 1.0)];
+  let vertex_buffer = app.create_static_vertex_buffer(&vertices).unwrap();
+  let index_buffer = app.create_static_index_buffer(&[0, 1, 2, 2, 3, 0]).unwrap();
+  let empty_buffer = app.create_static_vertex_buffer(&[]).unwrap(); // Draws nothing, but is still a valid buffer
+  let texture = app.create_texture_from_rgba(2, 2, &[255; 16]).unwrap();
+  let outline_pipeline = app.create_pipeline(&PipelineDescription {
+    blend_mode: BlendMode::Additive,
+    depth_write: false,
+    topology: Topology::LineStrip,
+    ..Default::default()
+  }).unwrap();
+
+  // More frames than are in flight, so every frame slot gets reused at least once
+  for _ in 0..app.frames_in_flight() + 1 {
+    let frame = app.begin_frame().unwrap();
+    app.draw(&frame, pipeline, vertex_buffer, Some(index_buffer), None).unwrap();
+    app.draw(&frame, pipeline, vertex_buffer, Some(index_buffer), Some(texture)).unwrap();
+    app.draw(&frame, outline_pipeline, vertex_buffer, None, None).unwrap();
+    app.draw(&frame, pipeline, empty_buffer, None, None).unwrap();
+    let pixels = app.end_frame(frame).unwrap().expect("Headless frames hand back their pixels");
+    assert_eq!(pixels.len(), (WIDTH * HEIGHT * 4) as usize);
+  }
+
+  app.destroy_pipeline(outline_pipeline).unwrap();
+  assert!(app.destroy_pipeline(pipeline).is_err(), "The default pipeline can't be destroyed");
+  app.destroy_texture(texture).unwrap();
+  app.destroy_buffer(index_buffer).unwrap();
+  app.destroy_buffer(vertex_buffer).unwrap();
+  app.destroy_buffer(empty_buffer).unwrap();
+  assert!(app.check_validation().is_ok(), "{} validation errors", app.validation_error_count());
+}
\ No newline at end of file